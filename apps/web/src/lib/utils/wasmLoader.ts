@@ -9,10 +9,286 @@ export interface RegionsMapItem {
 
 export type DataRecord = TagNode | TagNode[];
 
+export type MdrErrorDetails =
+  | { code: "DESERIALIZE" | "SERIALIZE"; target: string; message: string }
+  | { code: "INVALID_PARAMETER"; name: string; value: string; reason: string }
+  | { code: "MISSING_XPATH"; xpath: string }
+  | { code: "LIMIT_EXCEEDED"; limit: string; max: number; actual: number }
+  | { code: "CANCELLED" };
+
+export type MdrErrorCode = MdrErrorDetails["code"];
+
+// Errors thrown by the WASM entry points
+export interface MdrError extends Error {
+  name: "MdrError";
+  code: MdrErrorCode;
+  details: MdrErrorDetails;
+}
+
+export const isMdrError = (error: unknown): error is MdrError =>
+  error instanceof Error && error.name === "MdrError" && "code" in error;
+
 export interface MdrFullOutput {
   regions: RegionsMapItem[];
   records: DataRecord[];
   orphans: TagNode[];
+  finalRecords: DataRecord[];
+  warnings?: MdrErrorDetails[];
+  // Set when a limit stopped the region search; the reason is in `warnings`
+  truncated?: boolean;
+}
+
+export type SimilarityMetric =
+  | "stringEditDistance"
+  | "tokenEditDistance"
+  | "treeEditDistance"
+  | "simpleTreeMatching";
+
+export type OrphanMerge = "dedup" | "xpath";
+
+export interface MdrOptions {
+  signatureAttributes?: string[];
+  similarity?: SimilarityMetric;
+  orphanMerge?: OrphanMerge;
+}
+
+export interface MdrLimits {
+  maxNodes?: number;
+  maxDepth?: number;
+  maxComparisons?: number;
+  timeBudgetMs?: number;
+}
+
+export interface MdrRunOptions extends MdrOptions {
+  limits?: MdrLimits;
+}
+
+export type NestedTextMap = string | { [segment: string]: NestedTextMap };
+
+// Flat and hier LLM input formats, as produced by extractTextWithXPaths
+export interface TextMaps {
+  textMapFlat: Record<string, string>;
+  textMap: Record<string, NestedTextMap>;
+}
+
+export type InvalidXpathReason = "malformed" | "not_in_tree" | "no_text";
+
+export interface InvalidXpath {
+  record: number;
+  xpath: string;
+  normalized: string;
+  reason: InvalidXpathReason;
+}
+
+// Predicted records expanded to textMapFlat keys, as mapResponseToFullXpath
+export interface MappedResponse {
+  records: string[][];
+  numHallucination: number;
+  invalidXpaths: InvalidXpath[];
+}
+
+export interface ReductionOptions extends MdrOptions {
+  k?: number;
+  t?: number;
+  minRecords?: number;
+  contextLevels?: number;
+  regions?: RegionsMapItem[];
+}
+
+// A page cut down to its MDR data regions, in the slim, flat and hier formats
+export interface ReducedPage extends TextMaps {
+  regions: RegionsMapItem[];
+  slimHtml: string;
+  xpathMap: Record<string, string>;
+  stats: {
+    originalNodes: number;
+    reducedNodes: number;
+    originalChars: number;
+    reducedChars: number;
+  };
+}
+
+export interface ChunkOptions extends MdrOptions {
+  k?: number;
+  t?: number;
+  maxTokens?: number;
+  format?: "slim" | "flat" | "hier";
+  bytesPerToken?: number;
+  regions?: RegionsMapItem[];
+}
+
+// A run of whole subtrees under the token budget, rendered as its own page
+export interface Chunk extends ReducedPage {
+  xpaths: string[];
+  startXpath: string;
+  endXpath: string;
+  tokens: number;
+  oversized?: boolean;
+}
+
+export interface TableOptions extends MdrOptions {
+  k?: number;
+  t?: number;
+  keepEmptyColumns?: boolean;
+}
+
+export interface TableColumn {
+  index: number;
+  name: string;
+  nonEmpty: number;
+}
+
+export interface ExtractedTable {
+  parent_xpath: string;
+  region: [number, number, number];
+  columns: TableColumn[];
+  rows: Record<string, string>[];
+  csv: string;
+}
+
+export interface EvaluationResult {
+  precision: number;
+  recall: number;
+  f1: number;
+  totalOverlap: number;
+  matches: number;
+}
+
+export interface DistanceCacheConfig {
+  capacity?: number; // 0 disables caching
+  policy?: "lru" | "fifo";
+}
+
+export interface DistanceCacheStats {
+  hits: number;
+  misses: number;
+  insertions: number;
+  evictions: number;
+  entries: number;
+  capacity: number;
+}
+
+export interface MdrSessionConfig extends MdrOptions {
+  k?: number;
+  t?: number;
+  cache?: DistanceCacheConfig;
+  limits?: MdrLimits;
+}
+
+export interface MdrSessionStats {
+  pages: number;
+  regions: number;
+  records: number;
+  comparisons: number;
+  cache: DistanceCacheStats;
+}
+
+// Long-lived session with its own configuration, distance cache and statistics
+export interface MdrSession {
+  run: (root: TagNode) => MdrFullOutput;
+  runHtml: (html: string) => MdrFullOutput;
+  stats: () => MdrSessionStats;
+  clearCache: () => void;
+  free: () => void;
+}
+
+export interface SweepOptions extends MdrOptions {
+  ks?: number[];
+  ts?: number[];
+  cache?: DistanceCacheConfig;
+  groundTruth?: string[][];
+}
+
+export interface SweepResult extends MdrFullOutput {
+  k: number;
+  t: number;
+  metrics?: EvaluationResult;
+}
+
+export interface SweepOutput {
+  results: SweepResult[];
+  best?: number;
+  comparisons: number;
+  cache: DistanceCacheStats;
+}
+
+export interface GnComparison {
+  gnLength: number;
+  startIdx: number;
+  checkIdx: number;
+  distance: number;
+  similar: boolean;
+}
+
+export type CandidateVerdict =
+  | "initial"
+  | "more_nodes"
+  | "shorter_gn"
+  | "starts_later"
+  | "fewer_nodes"
+  | "not_better";
+
+export interface IdentPass {
+  startChildIdx: number;
+  comparisons: GnComparison[];
+  candidates: Array<{
+    region: [number, number, number];
+    verdict: CandidateVerdict;
+    previousMax: [number, number, number] | null;
+  }>;
+  selected: [number, number, number] | null;
+}
+
+// ident_drs decisions for one parent node
+export interface ParentTrace {
+  parent_xpath: string;
+  childCount: number;
+  passes: IdentPass[];
+  regions: Array<[number, number, number]>;
+  inherited: Array<[number, number, number]>;
+}
+
+export interface MdrTraceOutput {
+  regions: RegionsMapItem[];
+  trace: ParentTrace[];
+}
+
+export interface WrapperRule {
+  parent_xpath: string;
+  parentPattern: string;
+  gnLength: number;
+  recordShape: "node" | "children" | "columns";
+  recordPaths: string[];
+  signature: string;
+  recordCount: number;
+}
+
+// Serializable extraction wrapper induced from one page's MDR output
+export interface Wrapper {
+  k: number;
+  t: number;
+  options?: MdrOptions;
+  rules: WrapperRule[];
+}
+
+export interface WrapperApplyOptions {
+  minConfidence?: number;
+  fallback?: boolean;
+}
+
+export interface WrapperRuleMatch {
+  rule: number;
+  parent_xpath: string;
+  region: [number, number, number];
+  confidence: number;
+}
+
+export interface WrapperResult {
+  regions: RegionsMapItem[];
+  records: DataRecord[];
+  matches: WrapperRuleMatch[];
+  confidence: number;
+  fallback: boolean;
 }
 
 export interface RustMDRModule {
@@ -31,7 +307,67 @@ export interface RustMDRModule {
   ) => TagNode[];
   getNormalizedEditDistance: (s1: string, s2: string) => number;
   get_normalized_edit_distance_wasm: (s1: string, s2: string) => number;
-  runMdrFull: (root: TagNode, k?: number, t?: number) => MdrFullOutput;
+  clearDistanceCache: () => void;
+  configureDistanceCache: (config?: DistanceCacheConfig) => void;
+  getDistanceCacheStats: () => DistanceCacheStats;
+  resetDistanceCacheStats: () => void;
+  runMdrFull: (
+    root: TagNode,
+    k?: number,
+    t?: number,
+    options?: MdrRunOptions,
+  ) => MdrFullOutput;
+  runMdrFromHtml: (html: string, k?: number, t?: number) => MdrFullOutput;
+  slimHtml: (html: string) => string;
+  extractTextMaps: (root: TagNode) => TextMaps;
+  parseLlmResponse: (content: string) => string[][];
+  mapResponseToFullXpath: (
+    root: TagNode,
+    response: string[][],
+  ) => MappedResponse;
+  reduceContext: (root: TagNode, opts?: ReductionOptions) => ReducedPage;
+  mapReducedResponse: (
+    root: TagNode,
+    response: string[][],
+    opts?: ReductionOptions,
+  ) => MappedResponse;
+  chunkPage: (
+    root: TagNode,
+    opts?: ChunkOptions,
+    estimateTokens?: (text: string) => number,
+  ) => Chunk[];
+  mergeChunkResponses: (
+    root: TagNode,
+    responses: string[][][],
+    opts?: ChunkOptions,
+    estimateTokens?: (text: string) => number,
+  ) => MappedResponse;
+  traceMdr: (
+    root: TagNode,
+    k?: number,
+    t?: number,
+    options?: MdrOptions,
+  ) => MdrTraceOutput;
+  extractTables: (root: TagNode, opts?: TableOptions) => ExtractedTable[];
+  calculateEvaluationMetrics: (
+    predictedRecords: string[][],
+    groundTruthRecords: string[][],
+  ) => EvaluationResult;
+  calculateOverlap: (record1: string[], record2: string[]) => number;
+  sweepParameters: (root: TagNode, opts?: SweepOptions) => SweepOutput;
+  induceWrapper: (
+    root: TagNode,
+    mdrOutput: Pick<MdrFullOutput, "regions" | "records">,
+    k?: number,
+    t?: number,
+    options?: MdrOptions,
+  ) => Wrapper;
+  applyWrapper: (
+    wrapper: Wrapper,
+    root: TagNode,
+    opts?: WrapperApplyOptions,
+  ) => WrapperResult;
+  MdrSession: new (config?: MdrSessionConfig) => MdrSession;
 }
 
 let wasmModule: RustMDRModule | null = null;
@@ -102,29 +438,13 @@ export const runRustMDR = async (
   // No conversion needed - serde handles the field renaming
   const rustRootNode = rootNode;
 
-  // One-shot WASM call
-  const { regions, records, orphans } = module.runMdrFull(
+  // One-shot WASM call; finalRecords (records + orphans, see orphanMerge) comes from Rust
+  const { regions, records, orphans, finalRecords } = module.runMdrFull(
     rustRootNode,
     K,
     T,
-  ) as unknown as {
-    regions: RegionsMapItem[];
-    records: DataRecord[];
-    orphans: TagNode[];
-  };
-
-  // Step 4: Combine records (avoiding duplicates)
-  const finalRecords = [...records];
-  const recordSet = new Set(
-    records.filter((r): r is TagNode => !Array.isArray(r)).map((r) => r.xpath),
   );
 
-  orphans.forEach((orphan) => {
-    if (!recordSet.has(orphan.xpath)) {
-      finalRecords.push(orphan);
-    }
-  });
-
   return { regions, records, orphans, finalRecords };
 };
 
@@ -134,7 +134,8 @@ async function processMDR(request: MdrWorkerRequest): Promise<void> {
       );
     }
 
-    const { regions, records, orphans } = mdrResult as unknown as MdrFullOutput;
+    const { regions, records, orphans, finalRecords } =
+      mdrResult as unknown as MdrFullOutput;
     console.log(
       "[mdr.worker] MDR algorithm completed. Regions:",
       regions?.length,
@@ -145,20 +146,6 @@ async function processMDR(request: MdrWorkerRequest): Promise<void> {
     );
     console.timeEnd("MDR algorithm execution");
 
-    // Combine records (avoiding duplicates)
-    const finalRecords = [...records];
-    const recordSet = new Set(
-      records
-        .filter((r): r is TagNode => !Array.isArray(r))
-        .map((r) => r.xpath),
-    );
-
-    orphans.forEach((orphan) => {
-      if (!recordSet.has(orphan.xpath)) {
-        finalRecords.push(orphan);
-      }
-    });
-
     console.timeEnd("MDR total processing");
 
     // Send final result
@@ -135,4 +135,23 @@ describe("MDR Implementation Consistency Test", () => {
       }
     });
   });
+
+  describe("slimHtml Consistency", () => {
+    // Written by `mdr --format slim` (packages/rust-mdr) and compared there too
+    it("should produce the committed slim markup", async () => {
+      for (const [filename, content] of sampleFiles) {
+        const expectedSlim = await readFile(
+          join(process.cwd(), "tests", "slim-expected", filename),
+          "utf-8",
+        );
+        const slimmedResult = pipe(
+          content,
+          p.parseHtml,
+          p.slimDocument,
+          (result) => result.slimmedHtml,
+        );
+        expect(slimmedResult).toBe(expectedSlim);
+      }
+    });
+  });
 });
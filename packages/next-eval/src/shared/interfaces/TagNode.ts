@@ -3,4 +3,5 @@ export interface TagNode {
   children: TagNode[];
   rawText: string;
   xpath: string;
+  attributes?: Record<string, string>;
 }
@@ -0,0 +1,313 @@
+import json
+import re
+from pathlib import Path
+
+import pytest
+
+import rust_mdr_utils as mdr
+
+REPO_ROOT = Path(__file__).resolve().parents[4]
+SAMPLES_DIR = REPO_ROOT / "apps" / "web" / "public" / "samples"
+EXPECTED_DIR = REPO_ROOT / "apps" / "web" / "tests" / "mdr-expected"
+ASSETS_DIR = REPO_ROOT / "apps" / "web" / "src" / "assets"
+
+SAMPLES = sorted(SAMPLES_DIR.glob("*.html"))
+
+
+def load_expected(sample: Path) -> dict:
+    path = EXPECTED_DIR / f"{sample.stem}.json"
+    if not path.exists():
+        pytest.skip(f"no expected output for {sample.stem}")
+    return json.loads(path.read_text())
+
+
+@pytest.fixture(scope="module", params=SAMPLES, ids=lambda path: path.stem)
+def sample(request):
+    html = request.param.read_text(encoding="utf-8")
+    root = mdr.parse_html(html)
+    return request.param, html, root, mdr.run_mdr(root)
+
+
+def collect_xpaths(node, xpaths):
+    xpaths.add(node["xpath"])
+    for child in node["children"]:
+        collect_xpaths(child, xpaths)
+    return xpaths
+
+
+def collect_nodes(node, nodes):
+    nodes.setdefault(node["xpath"], node)  # text nodes share their parent's xpath
+    for child in node["children"]:
+        collect_nodes(child, nodes)
+    return nodes
+
+
+def test_samples_are_present():
+    assert len(SAMPLES) == 20
+
+
+def test_full_output_shape(sample):
+    _, _, root, output = sample
+    assert {"regions", "records", "orphans", "finalRecords"} <= output.keys()
+    assert output["regions"], "every sample has at least one data region"
+    assert "truncated" not in output
+
+    tree_xpaths = collect_xpaths(root, set())
+    for item in output["regions"]:
+        assert item["parent_xpath"] in tree_xpaths
+        for gn_length, start_idx, node_count in item["regions"]:
+            assert gn_length >= 1 and start_idx >= 0 and node_count >= 2 * gn_length
+    for record in mdr.record_xpaths(output["finalRecords"]):
+        assert record and set(record) <= tree_xpaths
+
+
+def test_html_entry_point_matches_tag_tree(sample):
+    _, html, root, output = sample
+    # Without comments, scripts or styles the HTML entry point builds the same tree
+    clean = mdr.slim_html(html)
+    assert mdr.run_mdr_html(clean) == mdr.run_mdr(mdr.parse_html(clean))
+    # JSON text is accepted wherever a tree is
+    assert mdr.run_mdr_algorithm(json.dumps(root)) == output["regions"]
+
+
+def test_step_functions_match_pipeline(sample):
+    _, _, root, output = sample
+    regions = mdr.run_mdr_algorithm(root)
+    assert regions == output["regions"]
+    assert mdr.identify_all_data_records(regions, 0.3, root) == output["records"]
+    assert mdr.find_orphan_records(regions, 0.3, root) == output["orphans"]
+
+
+def test_evaluation_against_expected(sample):
+    path, _, _, output = sample
+    expected = load_expected(path)["xpaths"]
+    predicted = mdr.record_xpaths(output["finalRecords"])
+
+    metrics = mdr.calculate_evaluation_metrics(predicted, expected)
+    assert {"precision", "recall", "f1", "totalOverlap", "matches"} <= metrics.keys()
+    assert 0.0 <= metrics["f1"] <= 1.0
+    assert mdr.calculate_evaluation_metrics(expected, expected)["f1"] == pytest.approx(1.0)
+
+
+def test_slim_final_records_reproduce_expected(sample):
+    # The fixtures are `finalRecords` over slimHtml output, written by the `mdr` CLI
+    path, html, _, _ = sample
+    expected = load_expected(path)["xpaths"]
+    output = mdr.run_mdr_html(mdr.slim_html(html))
+    assert mdr.record_xpaths(output["finalRecords"]) == expected
+
+    # No record twice, no orphan repeating a record node
+    keys = [tuple(record) for record in expected]
+    assert len(set(keys)) == len(keys)
+    records = [tuple(record) for record in mdr.record_xpaths(output["records"])]
+    orphans = keys[len(set(records)) :]
+    record_nodes = {xpath for record in records for xpath in record}
+    assert not {xpath for orphan in orphans for xpath in orphan} & record_nodes
+
+    merged = mdr.run_mdr_html(mdr.slim_html(html), options={"orphanMerge": "xpath"})
+    assert len(merged["finalRecords"]) >= len(output["finalRecords"])
+
+
+def test_html_entry_point_strips_comments_scripts_and_styles(sample):
+    # runMDR.ts removes them before building the tree; left in, they become children
+    # and shift region indices
+    path, html, _, _ = sample
+    expected = load_expected(path)["xpaths"]
+    noise = "<!-- note --><script>var a = '<p>';</script><style>p { color: red }</style>"
+    raw = re.sub(r"(<(?:body|div|ul|li|tr)>)", rf"\1{noise}", mdr.slim_html(html))
+    assert raw.count(noise) > 1
+    output = mdr.run_mdr_html(raw)
+    assert mdr.record_xpaths(output["finalRecords"]) == expected
+
+
+def test_slim_tree_contains_expected_xpaths(sample):
+    # mdr-expected was produced by MDR over slimHtml output, so every record xpath
+    # must exist in the tree of the Rust slim HTML
+    path, html, _, _ = sample
+    expected = load_expected(path)["xpaths"]
+    slim = mdr.slim_html(html)
+    assert mdr.slim_html(slim) == slim
+
+    tree_xpaths = collect_xpaths(mdr.parse_html(slim), set())
+    assert {xpath for record in expected for xpath in record} <= tree_xpaths
+
+
+def test_text_maps_address_slim_tree(sample):
+    _, html, _, _ = sample
+    root = mdr.parse_html(mdr.slim_html(html))
+    maps = mdr.extract_text_maps(root)
+    assert maps["textMapFlat"]
+
+    tree_xpaths = collect_xpaths(root, set())
+    for xpath in maps["textMapFlat"]:
+        assert xpath.startswith("id('") or f"/html[1]{xpath}" in tree_xpaths
+
+
+@pytest.mark.parametrize("fmt", ["slim", "flat", "hier"])
+def test_llm_responses_map_to_text_keys(sample, fmt):
+    path, html, _, _ = sample
+    content = ASSETS_DIR / f"{path.stem}_{fmt}_content.json"
+    if not content.exists():
+        pytest.skip(f"no {fmt} response for {path.stem}")
+    response = mdr.parse_llm_response(content.read_text())
+    root = mdr.parse_html(mdr.slim_html(html))
+    keys = mdr.extract_text_maps(root)["textMapFlat"]
+
+    mapped = mdr.map_response_to_full_xpath(root, response)
+    assert len(mapped["records"]) == len(response)
+    assert all(set(record) <= keys.keys() for record in mapped["records"])
+    assert mapped["numHallucination"] == sum(not record for record in mapped["records"])
+    for invalid in mapped["invalidXpaths"]:
+        assert invalid["xpath"] in response[invalid["record"]]
+
+
+def test_mdr_records_map_through_the_same_path(sample):
+    _, html, _, _ = sample
+    root = mdr.parse_html(mdr.slim_html(html))
+    predicted = mdr.record_xpaths(mdr.run_mdr(root)["finalRecords"])
+
+    mapped = mdr.map_response_to_full_xpath(root, predicted)
+    assert not any(
+        invalid["reason"] != "no_text" for invalid in mapped["invalidXpaths"]
+    )
+
+
+def test_reduced_context_maps_back_to_original(sample):
+    _, html, _, _ = sample
+    root = mdr.parse_html(mdr.slim_html(html))
+    page = mdr.reduce_context(root)
+    stats = page["stats"]
+    assert stats["reducedNodes"] < stats["originalNodes"]
+    assert stats["reducedChars"] < stats["originalChars"]
+    # Copies of descendant regions under ancestors are not cut around
+    assert all(item["parent_xpath"] != "/html[1]" for item in page["regions"])
+
+    # The reduced markup numbers its elements like the reduced text maps
+    reparsed = mdr.parse_html(page["slimHtml"])
+    assert mdr.extract_text_maps(reparsed)["textMapFlat"] == page["textMapFlat"]
+
+    original_xpaths = collect_xpaths(root, set())
+    assert set(page["xpathMap"].values()) <= original_xpaths
+
+    response = [[xpath] for xpath in page["textMapFlat"]]
+    options = {"regions": page["regions"]}
+    mapped = mdr.map_reduced_response(root, response, options)
+    assert mapped["numHallucination"] == 0
+    original_keys = mdr.extract_text_maps(root)["textMapFlat"]
+    for record in mapped["records"]:
+        assert set(record) <= original_keys.keys()
+
+
+def test_chunks_keep_regions_whole_and_merge_back(sample):
+    _, html, _, _ = sample
+    root = mdr.parse_html(mdr.slim_html(html))
+    regions = mdr.run_mdr_algorithm(root)
+    options = {"maxTokens": 1500, "regions": regions}
+    chunks = mdr.chunk_page(root, options)
+    assert chunks
+    for chunk in chunks:
+        assert chunk["tokens"] <= 1500 or chunk.get("oversized")
+        assert chunk["startXpath"] == chunk["xpaths"][0]
+        assert chunk["endXpath"] == chunk["xpaths"][-1]
+
+    # A region's generalized nodes never end up in different chunks
+    def chunk_of(xpath):
+        owners = {
+            i
+            for i, chunk in enumerate(chunks)
+            for top in chunk["xpaths"]
+            if xpath == top or xpath.startswith(f"{top}/")
+        }
+        assert len(owners) <= 1
+        return owners.pop() if owners else None
+
+    nodes = collect_nodes(root, {})
+    for item in (item for chunk in chunks for item in chunk["regions"]):
+        children = nodes[item["parent_xpath"]]["children"]
+        for _, start, count in item["regions"]:
+            owners = {chunk_of(child["xpath"]) for child in children[start : start + count]}
+            assert len(owners) == 1 and None not in owners
+
+    responses = [[[xpath] for xpath in chunk["textMapFlat"]] for chunk in chunks]
+    merged = mdr.merge_chunk_responses(root, responses, options)
+    assert len(merged["records"]) == sum(len(response) for response in responses)
+    assert merged["numHallucination"] == 0
+    original_keys = mdr.extract_text_maps(root)["textMapFlat"]
+    assert all(set(record) <= original_keys.keys() for record in merged["records"])
+
+
+@pytest.mark.parametrize("stem", ["sample3", "sample7", "sample19"])
+def test_chunks_split_real_pages_in_every_format(stem):
+    html = (SAMPLES_DIR / f"{stem}.html").read_text(encoding="utf-8")
+    root = mdr.parse_html(mdr.slim_html(html))
+    nodes = collect_nodes(root, {})
+    for format in ("slim", "flat", "hier"):
+        for max_tokens in (500, 2000, 8000):
+            chunks = mdr.chunk_page(root, {"maxTokens": max_tokens, "format": format})
+            whole = mdr.chunk_page(root, {"maxTokens": 10**9, "format": format})
+            if whole[0]["tokens"] > max_tokens:
+                assert len(chunks) > 1, (format, max_tokens)
+            for chunk in chunks:
+                if not chunk.get("oversized"):
+                    assert chunk["tokens"] <= max_tokens
+                    continue
+                # Only a childless node or a single data region may exceed the budget
+                xpaths = chunk["xpaths"]
+                if len(xpaths) == 1 and (
+                    "/text()[" in xpaths[0] or not nodes[xpaths[0]]["children"]
+                ):
+                    continue
+                parent = xpaths[0].rsplit("/", 1)[0]
+                children = [child["xpath"] for child in nodes[parent]["children"]]
+                spans = [
+                    children[start : start + count]
+                    for item in chunk["regions"]
+                    if item["parent_xpath"] == parent
+                    for _, start, count in item["regions"]
+                ]
+                assert spans and all(set(span) <= set(xpaths) for span in spans)
+                assert set(xpaths) <= {xpath for span in spans for xpath in span} | {
+                    xpath for xpath in xpaths if "/text()[" in xpath
+                }
+
+
+def test_reduction_cuts_samples_overall():
+    original = reduced = 0
+    for path in SAMPLES:
+        root = mdr.parse_html(mdr.slim_html(path.read_text(encoding="utf-8")))
+        stats = mdr.reduce_context(root)["stats"]
+        original += stats["originalChars"]
+        reduced += stats["reducedChars"]
+    assert reduced < 0.8 * original
+
+
+def test_limits_truncate_output():
+    html = SAMPLES[0].read_text(encoding="utf-8")
+    output = mdr.run_mdr_html(html, options={"limits": {"maxComparisons": 1}})
+    assert output["truncated"] is True
+    assert output["warnings"][-1]["code"] == "LIMIT_EXCEEDED"
+
+
+def test_errors_carry_code_and_details():
+    root = mdr.parse_html("<html><body><p>x</p></body></html>")
+    with pytest.raises(mdr.MdrError) as error:
+        mdr.run_mdr(root, k=0)
+    assert error.value.code == "INVALID_PARAMETER"
+    assert error.value.details["name"] == "k"
+
+    # Bindings that run MDR internally validate the options' k and t too
+    with pytest.raises(mdr.MdrError) as error:
+        mdr.reduce_context(root, {"t": 1.5})
+    assert error.value.details["name"] == "t"
+    with pytest.raises(mdr.MdrError):
+        mdr.map_reduced_response(root, [], {"k": 0})
+    with pytest.raises(mdr.MdrError):
+        mdr.chunk_page(root, {"k": 0})
+    with pytest.raises(mdr.MdrError):
+        mdr.merge_chunk_responses(root, [], {"t": -0.1})
+
+    with pytest.raises(mdr.MdrError) as error:
+        mdr.run_mdr({"tag": "html"})
+    assert error.value.code == "DESERIALIZE"
+
+    assert mdr.calculate_overlap(["/a", "/b"], ["/b", "/c"]) == pytest.approx(1 / 3)
@@ -0,0 +1,47 @@
+from typing import Any, Callable, Optional, Union
+
+# TagNode trees, regions, records and options are plain JSON-shaped values, the same
+# shapes as the wasm API; tree/region/record arguments also accept JSON text.
+TagNode = dict[str, Any]
+Json = Union[str, dict[str, Any], list[Any]]
+
+class MdrError(Exception):
+    code: str
+    details: dict[str, Any]
+
+def parse_html(html: str) -> TagNode: ...
+def slim_html(html: str) -> str: ...
+def extract_text_maps(root: Json) -> dict[str, dict[str, Any]]: ...
+def parse_llm_response(content: str) -> list[list[str]]: ...
+def map_response_to_full_xpath(root: Json, response: Json) -> dict[str, Any]: ...
+def reduce_context(root: Json, options: Optional[Json] = None) -> dict[str, Any]: ...
+def map_reduced_response(
+    root: Json, response: Json, options: Optional[Json] = None
+) -> dict[str, Any]: ...
+def chunk_page(
+    root: Json,
+    options: Optional[Json] = None,
+    estimate_tokens: Optional[Callable[[str], int]] = None,
+) -> list[dict[str, Any]]: ...
+def merge_chunk_responses(
+    root: Json,
+    responses: Json,
+    options: Optional[Json] = None,
+    estimate_tokens: Optional[Callable[[str], int]] = None,
+) -> dict[str, Any]: ...
+def run_mdr_algorithm(
+    root: Json, k: int = 10, t: float = 0.3, options: Optional[Json] = None
+) -> list[dict[str, Any]]: ...
+def run_mdr(
+    root: Json, k: int = 10, t: float = 0.3, options: Optional[Json] = None
+) -> dict[str, Any]: ...
+def run_mdr_html(
+    html: str, k: int = 10, t: float = 0.3, options: Optional[Json] = None
+) -> dict[str, Any]: ...
+def identify_all_data_records(regions: Json, t: float, root: Json) -> list[Any]: ...
+def find_orphan_records(regions: Json, t: float, root: Json) -> list[TagNode]: ...
+def record_xpaths(records: Json) -> list[list[str]]: ...
+def calculate_evaluation_metrics(
+    predicted_records: list[list[str]], ground_truth_records: list[list[str]]
+) -> dict[str, float]: ...
+def calculate_overlap(record1: list[str], record2: list[str]) -> float: ...
@@ -0,0 +1,560 @@
+use crate::distance_cache::{DistanceKey, DistanceScope};
+use crate::session::MdrContext;
+use crate::similarity::{
+    edit_distance, edit_distance_within, token_edit_distance, token_edit_distance_within,
+};
+use crate::tree_distance::{simple_tree_matching, tree_edit_distance, TreeNode};
+use crate::tree_utils::{escape_signature_value, hash128, intern_tag, LabelIds};
+use crate::types::{SimilarityMetric, TagNode, TagNodeRef};
+use indexmap::IndexMap;
+use once_cell::sync::OnceCell;
+use serde::ser::{SerializeSeq, SerializeStruct};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Index of a node in an [`ArenaTree`]
+pub type NodeId = u32;
+
+/// Index-based copy of a `TagNode` tree for the region search on large pages.
+///
+/// Nodes are numbered breadth-first so every node's children form a contiguous id
+/// range, tag names are interned, and the flattened structural signature of the
+/// whole tree is built once: each node's `flatten_subtree` string (and that of any
+/// run of consecutive siblings) is a slice of it. Structural hashes equal
+/// `tree_utils::structural_hash`, so distances share cache entries with the
+/// `TagNode` path.
+pub struct ArenaTree {
+    tag_names: Vec<String>,
+    tags: Vec<u32>,
+    first_child: Vec<NodeId>,
+    child_count: Vec<u32>,
+    xpaths: Vec<String>,
+    raw_texts: Vec<Option<String>>,
+    attributes: Vec<Option<IndexMap<String, String>>>,
+    /// Signature attributes folded into `flattened`
+    signature_attributes: Vec<String>,
+    flattened: String,
+    /// Byte range of each node's signature in `flattened`
+    spans: Vec<(u32, u32)>,
+    hashes: Vec<OnceCell<u128>>,
+}
+
+impl ArenaTree {
+    /// Converts `root`, flattening signatures with `signature_attributes` like
+    /// `tree_utils::flatten_subtree_with_attributes`
+    pub fn from_tag_node(root: &TagNodeRef, signature_attributes: &[String]) -> Self {
+        let mut tree = ArenaTree {
+            tag_names: Vec::new(),
+            tags: Vec::new(),
+            first_child: Vec::new(),
+            child_count: Vec::new(),
+            xpaths: Vec::new(),
+            raw_texts: Vec::new(),
+            attributes: Vec::new(),
+            signature_attributes: signature_attributes.to_vec(),
+            flattened: String::new(),
+            spans: Vec::new(),
+            hashes: Vec::new(),
+        };
+        let mut interner: HashMap<String, u32> = HashMap::new();
+
+        // Breadth-first numbering: a node's children are pushed together
+        let mut queue: Vec<&TagNodeRef> = vec![root];
+        let mut head = 0;
+        while head < queue.len() {
+            let node = queue[head];
+            head += 1;
+
+            let tag = match interner.get(&node.tag_name) {
+                Some(&tag) => tag,
+                None => {
+                    let tag = tree.tag_names.len() as u32;
+                    tree.tag_names.push(node.tag_name.clone());
+                    interner.insert(node.tag_name.clone(), tag);
+                    tag
+                }
+            };
+            tree.tags.push(tag);
+            tree.first_child.push(queue.len() as NodeId);
+            tree.child_count.push(node.children.len() as u32);
+            tree.xpaths.push(node.xpath.clone());
+            tree.raw_texts.push(node.raw_text.clone());
+            tree.attributes.push(node.attributes.clone());
+            queue.extend(node.children.iter());
+        }
+
+        let len = tree.tags.len();
+        let mut spans = vec![(0, 0); len];
+        let mut flattened = String::with_capacity(len * 8);
+        tree.flatten_into(&mut flattened, &mut spans, 0);
+        tree.flattened = flattened;
+        tree.spans = spans;
+        tree.hashes = (0..len).map(|_| OnceCell::new()).collect();
+        tree
+    }
+
+    /// Mirrors `tree_utils::inner_flatten`, recording each node's span
+    fn flatten_into(&self, out: &mut String, spans: &mut [(u32, u32)], id: NodeId) {
+        let start = out.len() as u32;
+        let idx = id as usize;
+        let tag = &self.tag_names[self.tags[idx] as usize];
+
+        let is_text = tag == "text";
+        let has_text = self.raw_texts[idx]
+            .as_ref()
+            .is_some_and(|text| !text.trim().is_empty());
+        if !(is_text && has_text) {
+            out.push('<');
+            out.push_str(tag);
+            for name in &self.signature_attributes {
+                if let Some(value) = self.attribute(id, name) {
+                    out.push(' ');
+                    out.push_str(name);
+                    out.push_str("=\"");
+                    out.push_str(&escape_signature_value(value));
+                    out.push('"');
+                }
+            }
+            out.push('>');
+
+            for child in self.children(id) {
+                self.flatten_into(out, spans, child);
+            }
+
+            if !is_text {
+                out.push_str("</");
+                out.push_str(tag);
+                out.push('>');
+            }
+        }
+        spans[idx] = (start, out.len() as u32);
+    }
+
+    pub fn root(&self) -> NodeId {
+        0
+    }
+
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    pub fn children(&self, id: NodeId) -> Range<NodeId> {
+        let first = self.first_child[id as usize];
+        first..first + self.child_count[id as usize]
+    }
+
+    pub fn tag(&self, id: NodeId) -> &str {
+        &self.tag_names[self.tags[id as usize] as usize]
+    }
+
+    pub fn xpath(&self, id: NodeId) -> &str {
+        &self.xpaths[id as usize]
+    }
+
+    pub fn raw_text(&self, id: NodeId) -> Option<&str> {
+        self.raw_texts[id as usize].as_deref()
+    }
+
+    pub fn attribute(&self, id: NodeId, name: &str) -> Option<&str> {
+        self.attributes[id as usize]
+            .as_ref()
+            .and_then(|attrs| attrs.get(name))
+            .map(String::as_str)
+    }
+
+    pub fn signature_attributes(&self) -> &[String] {
+        &self.signature_attributes
+    }
+
+    /// Flattened signature of a node, equal to `flatten_subtree_with_attributes`
+    pub fn flattened(&self, id: NodeId) -> &str {
+        let (start, end) = self.spans[id as usize];
+        &self.flattened[start as usize..end as usize]
+    }
+
+    /// Flattened signature of consecutive siblings `ids`, equal to
+    /// `similarity::flatten_node_sequence`
+    pub fn flattened_sequence(&self, ids: Range<NodeId>) -> &str {
+        if ids.is_empty() {
+            return "";
+        }
+        let start = self.spans[ids.start as usize].0;
+        let end = self.spans[ids.end as usize - 1].1;
+        &self.flattened[start as usize..end as usize]
+    }
+
+    /// Equal to `tree_utils::structural_hash` of the corresponding `TagNode`
+    pub fn structural_hash(&self, id: NodeId) -> u128 {
+        *self.hashes[id as usize].get_or_init(|| hash128(self.flattened(id)))
+    }
+
+    /// Equal to `tree_utils::structural_sequence_hash`
+    pub fn structural_sequence_hash(&self, ids: Range<NodeId>) -> u128 {
+        let hashes: Vec<u128> = ids.map(|id| self.structural_hash(id)).collect();
+        hash128(&hashes)
+    }
+
+    /// Mirrors `tree_utils::flatten_subtree_tokens` over consecutive siblings
+    fn sequence_tokens(&self, ids: Range<NodeId>, labels: &mut LabelIds) -> Vec<u32> {
+        let mut out = Vec::new();
+        for id in ids {
+            self.tokens_into(&mut out, id, labels);
+        }
+        out
+    }
+
+    fn tokens_into(&self, out: &mut Vec<u32>, id: NodeId, labels: &mut LabelIds) {
+        let tag = self.tag(id);
+        if tag == "text"
+            && self
+                .raw_text(id)
+                .is_some_and(|text| !text.trim().is_empty())
+        {
+            return;
+        }
+
+        let tag_id = intern_tag(tag);
+        let open_id = if self.signature_attributes.is_empty() {
+            tag_id
+        } else {
+            let mut label = tag.to_string();
+            for name in &self.signature_attributes {
+                if let Some(value) = self.attribute(id, name) {
+                    label.push(' ');
+                    label.push_str(name);
+                    label.push_str("=\"");
+                    label.push_str(&escape_signature_value(value));
+                    label.push('"');
+                }
+            }
+            labels.get(label)
+        };
+        out.push(open_id * 2);
+
+        for child in self.children(id) {
+            self.tokens_into(out, child, labels);
+        }
+
+        if tag != "text" {
+            out.push(tag_id * 2 + 1);
+        }
+    }
+
+    /// Rebuilds the `TagNode` tree
+    pub fn to_tag_node(&self) -> TagNodeRef {
+        self.subtree_to_tag_node(self.root())
+    }
+
+    /// Rebuilds the `TagNode` subtree rooted at `id`
+    pub fn subtree_to_tag_node(&self, id: NodeId) -> TagNodeRef {
+        let idx = id as usize;
+        let mut node = TagNode::new(self.tag(id).to_string(), self.xpaths[idx].clone());
+        node.raw_text = self.raw_texts[idx].clone();
+        node.attributes = self.attributes[idx].clone();
+        node.children = self
+            .children(id)
+            .map(|child| self.subtree_to_tag_node(child))
+            .collect();
+        Arc::new(node)
+    }
+
+    /// Distance between generalized nodes `a` and `b` (consecutive sibling ids), keyed
+    /// and computed like `similarity::get_normalized_edit_distance_sequences`
+    pub fn sequence_distance(
+        &self,
+        a: Range<NodeId>,
+        b: Range<NodeId>,
+        context: &MdrContext,
+    ) -> f32 {
+        let key = self.sequence_key(&a, &b, context);
+        context.count_comparison();
+        context.cache().get_or_compute(key, || {
+            self.compute_sequence_distance(a, b, context, None).0
+        })
+    }
+
+    /// Thresholded [`ArenaTree::sequence_distance`], like
+    /// `similarity::get_normalized_edit_distance_sequences_within`
+    pub fn sequence_distance_within(
+        &self,
+        a: Range<NodeId>,
+        b: Range<NodeId>,
+        context: &MdrContext,
+    ) -> Option<f32> {
+        let key = self.sequence_key(&a, &b, context);
+        context.count_comparison();
+        context
+            .cache()
+            .get_or_compute_within(key, context.t, |bound| {
+                let (distance, within) = self.compute_sequence_distance(a, b, context, Some(bound));
+                within.then_some(distance)
+            })
+    }
+
+    fn sequence_key(
+        &self,
+        a: &Range<NodeId>,
+        b: &Range<NodeId>,
+        context: &MdrContext,
+    ) -> DistanceKey {
+        debug_assert_eq!(
+            context.options.signature_attributes,
+            self.signature_attributes
+        );
+        DistanceKey::new(
+            context.options.similarity,
+            DistanceScope::Sequence,
+            self.structural_sequence_hash(a.clone()),
+            self.structural_sequence_hash(b.clone()),
+        )
+    }
+
+    /// Distance and whether it is within `bound` (always true without a bound)
+    fn compute_sequence_distance(
+        &self,
+        a: Range<NodeId>,
+        b: Range<NodeId>,
+        context: &MdrContext,
+        bound: Option<f32>,
+    ) -> (f32, bool) {
+        let within = |distance: f32| (distance, bound.is_none_or(|bound| distance <= bound));
+        let bounded = |distance: Option<f32>| match distance {
+            Some(distance) => (distance, true),
+            None => (1.0, false),
+        };
+
+        match context.options.similarity {
+            SimilarityMetric::StringEditDistance => {
+                let s1 = self.flattened_sequence(a);
+                let s2 = self.flattened_sequence(b);
+                if s1.len() > 2 * s2.len() || s2.len() > 2 * s1.len() {
+                    return within(1.0); // Consider highly dissimilar
+                }
+                match bound {
+                    Some(bound) => bounded(edit_distance_within(s1, s2, bound)),
+                    None => within(edit_distance(s1, s2)),
+                }
+            }
+            SimilarityMetric::TokenEditDistance => {
+                let mut labels = LabelIds::default();
+                let t1 = self.sequence_tokens(a, &mut labels);
+                let t2 = self.sequence_tokens(b, &mut labels);
+                if t1.len() > 2 * t2.len() || t2.len() > 2 * t1.len() {
+                    return within(1.0); // Consider highly dissimilar
+                }
+                match bound {
+                    Some(bound) => bounded(token_edit_distance_within(&t1, &t2, bound)),
+                    None => within(token_edit_distance(&t1, &t2)),
+                }
+            }
+            SimilarityMetric::TreeEditDistance => within(tree_edit_distance(
+                self.nodes(a),
+                self.nodes(b),
+                &context.options.signature_attributes,
+            )),
+            SimilarityMetric::SimpleTreeMatching => within(simple_tree_matching(
+                self.nodes(a),
+                self.nodes(b),
+                &context.options.signature_attributes,
+            )),
+        }
+    }
+
+    fn nodes(&self, ids: Range<NodeId>) -> impl Iterator<Item = ArenaNode<'_>> {
+        ids.map(|id| ArenaNode { tree: self, id })
+    }
+}
+
+/// An arena node as seen by the tree metrics
+#[derive(Clone, Copy)]
+struct ArenaNode<'a> {
+    tree: &'a ArenaTree,
+    id: NodeId,
+}
+
+impl<'a> TreeNode<'a> for ArenaNode<'a> {
+    fn tag(self) -> &'a str {
+        self.tree.tag(self.id)
+    }
+
+    fn raw_text(self) -> Option<&'a str> {
+        self.tree.raw_text(self.id)
+    }
+
+    fn attribute(self, name: &str) -> Option<&'a str> {
+        self.tree.attribute(self.id, name)
+    }
+
+    fn children(self) -> impl Iterator<Item = Self> {
+        self.tree.nodes(self.tree.children(self.id))
+    }
+}
+
+impl Serialize for ArenaTree {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        NodeView {
+            tree: self,
+            id: self.root(),
+        }
+        .serialize(serializer)
+    }
+}
+
+/// Deserializes `TagNode` JSON (plain signature, no signature attributes)
+impl<'de> Deserialize<'de> for ArenaTree {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let root = TagNodeRef::deserialize(deserializer)?;
+        Ok(ArenaTree::from_tag_node(&root, &[]))
+    }
+}
+
+/// Serializes one arena node with the same shape as `TagNode`
+struct NodeView<'a> {
+    tree: &'a ArenaTree,
+    id: NodeId,
+}
+
+struct ChildrenView<'a> {
+    tree: &'a ArenaTree,
+    ids: Range<NodeId>,
+}
+
+impl Serialize for NodeView<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let idx = self.id as usize;
+        let attributes = &self.tree.attributes[idx];
+        let mut state =
+            serializer.serialize_struct("TagNode", if attributes.is_some() { 5 } else { 4 })?;
+        state.serialize_field("tag", self.tree.tag(self.id))?;
+        state.serialize_field(
+            "children",
+            &ChildrenView {
+                tree: self.tree,
+                ids: self.tree.children(self.id),
+            },
+        )?;
+        state.serialize_field("rawText", &self.tree.raw_texts[idx])?;
+        state.serialize_field("xpath", &self.tree.xpaths[idx])?;
+        if let Some(attributes) = attributes {
+            state.serialize_field("attributes", attributes)?;
+        }
+        state.end()
+    }
+}
+
+impl Serialize for ChildrenView<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.ids.len()))?;
+        for id in self.ids.clone() {
+            seq.serialize_element(&NodeView {
+                tree: self.tree,
+                id,
+            })?;
+        }
+        seq.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_parser::parse_html;
+    use crate::similarity::flatten_node_sequence;
+    use crate::tree_utils::{flatten_subtree_with_attributes, structural_hash};
+
+    #[test]
+    fn arena_round_trips_and_matches_tag_node_signatures() {
+        let root = parse_html(
+            "<html><body><ul class=\"list\">\
+             <li class=\"item\"><a>One</a> <b></b></li>\
+             <li class=\"item\"><a>Two</a></li>\
+             </ul><p>End</p></body></html>",
+        );
+        let attributes = vec!["class".to_string()];
+        let tree = ArenaTree::from_tag_node(&root, &attributes);
+
+        assert_eq!(
+            serde_json::to_value(&tree).unwrap(),
+            serde_json::to_value(&root).unwrap()
+        );
+        assert_eq!(
+            serde_json::to_value(tree.to_tag_node()).unwrap(),
+            serde_json::to_value(&root).unwrap()
+        );
+
+        // Walk both trees together, comparing signatures, hashes and sibling runs
+        let mut stack = vec![(tree.root(), root.clone())];
+        while let Some((id, node)) = stack.pop() {
+            assert_eq!(tree.xpath(id), node.xpath);
+            assert_eq!(
+                tree.flattened(id),
+                flatten_subtree_with_attributes(&node, &attributes)
+            );
+            assert_eq!(
+                tree.structural_hash(id),
+                structural_hash(&node, &attributes)
+            );
+            let options = crate::types::MdrOptions {
+                signature_attributes: attributes.clone(),
+                ..Default::default()
+            };
+            assert_eq!(
+                tree.flattened_sequence(tree.children(id)),
+                flatten_node_sequence(&node.children, &options)
+            );
+            stack.extend(tree.children(id).zip(node.children.iter().cloned()));
+        }
+    }
+
+    #[test]
+    fn arena_tree_metrics_match_tag_node_backends() {
+        let root = parse_html(
+            "<html><body><div>\
+             <p class=\"a\"><b>1</b><i>x</i></p><p class=\"b\"><b>2</b></p>\
+             <ul><li>1</li><li><a>2</a></li></ul><p class=\"a\">Text <br></p>\
+             </div></body></html>",
+        );
+        for similarity in [
+            SimilarityMetric::TreeEditDistance,
+            SimilarityMetric::SimpleTreeMatching,
+        ] {
+            let options = crate::types::MdrOptions {
+                signature_attributes: vec!["class".to_string()],
+                similarity,
+                ..Default::default()
+            };
+            let tree = ArenaTree::from_tag_node(&root, &options.signature_attributes);
+            let context = MdrContext::new(10, 0.3, options.clone());
+
+            // Every pair of sibling runs of up to two nodes under every parent
+            let mut stack = vec![(tree.root(), root.clone())];
+            while let Some((id, node)) = stack.pop() {
+                let first = tree.children(id).start;
+                let count = node.children.len();
+                for (i, j, len) in (0..count)
+                    .flat_map(|i| (i..count).map(move |j| (i, j)))
+                    .flat_map(|(i, j)| (1..=2).map(move |len| (i, j, len)))
+                    .filter(|&(_, j, len)| j + len <= count)
+                {
+                    let a = first + i as NodeId..first + (i + len) as NodeId;
+                    let b = first + j as NodeId..first + (j + len) as NodeId;
+                    assert_eq!(
+                        tree.sequence_distance(a, b, &context),
+                        similarity.backend().distance(
+                            &node.children[i..i + len],
+                            &node.children[j..j + len],
+                            &options
+                        )
+                    );
+                }
+                stack.extend(tree.children(id).zip(node.children.iter().cloned()));
+            }
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Batch MDR over HTML or TagNode JSON files.
+//!
+//! ```text
+//! mdr [--k N] [--t F] [--format expected|full|slim] [--slim] [--out DIR] <FILE|DIR>...
+//! ```
+//!
+//! `expected` (default) writes `{ xpaths, texts, records }` like
+//! `apps/web/tests/mdr-expected`; `full` writes `{ regions, records, orphans, finalRecords }`;
+//! `slim` writes the `slim_html` markup of HTML inputs like `apps/web/tests/slim-expected`.
+//! `--slim` runs HTML inputs through `slim_html` first, as the TypeScript consistency
+//! test does; `mdr --slim --out apps/web/tests/mdr-expected apps/web/public/samples`
+//! regenerates those fixtures (format them with biome afterwards).
+
+use rust_mdr_utils::evaluation::record_xpaths;
+use rust_mdr_utils::html_parser::parse_page_html;
+use rust_mdr_utils::session::{MdrSession, MdrSessionConfig};
+use rust_mdr_utils::slim_html::slim_html;
+use rust_mdr_utils::types::{DataRecord, TagNodeRef};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::{env, fs};
+
+const USAGE: &str =
+    "usage: mdr [--k N] [--t F] [--format expected|full|slim] [--slim] [--out DIR] <FILE|DIR>...";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Expected,
+    Full,
+    Slim,
+}
+
+struct Args {
+    k: usize,
+    t: f32,
+    format: Format,
+    slim: bool,
+    out: Option<PathBuf>,
+    inputs: Vec<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct ExpectedOutput {
+    xpaths: Vec<Vec<String>>,
+    texts: Vec<String>,
+    records: Vec<DataRecord>,
+}
+
+fn parse_args() -> Result<Args, String> {
+    let mut args = Args {
+        k: 10,
+        t: 0.3,
+        format: Format::Expected,
+        slim: false,
+        out: None,
+        inputs: Vec::new(),
+    };
+
+    let mut iter = env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = |name: &str| iter.next().ok_or(format!("missing value for {}", name));
+        match arg.as_str() {
+            "--k" => {
+                args.k = value("--k")?
+                    .parse()
+                    .map_err(|e| format!("invalid --k: {}", e))?
+            }
+            "--t" => {
+                args.t = value("--t")?
+                    .parse()
+                    .map_err(|e| format!("invalid --t: {}", e))?
+            }
+            "--format" => {
+                args.format = match value("--format")?.as_str() {
+                    "expected" => Format::Expected,
+                    "full" => Format::Full,
+                    "slim" => Format::Slim,
+                    other => return Err(format!("unknown format: {}", other)),
+                }
+            }
+            "--slim" => args.slim = true,
+            "--out" => args.out = Some(PathBuf::from(value("--out")?)),
+            "-h" | "--help" => return Err(USAGE.to_string()),
+            _ if arg.starts_with("--") => return Err(format!("unknown option: {}", arg)),
+            _ => args.inputs.push(PathBuf::from(arg)),
+        }
+    }
+
+    if args.inputs.is_empty() {
+        return Err(USAGE.to_string());
+    }
+    Ok(args)
+}
+
+fn is_supported(path: &Path) -> bool {
+    matches!(
+        path.extension().and_then(|e| e.to_str()),
+        Some("html" | "htm" | "json")
+    )
+}
+
+fn collect_files(inputs: &[PathBuf]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            let entries = fs::read_dir(input).map_err(|e| format!("{}: {}", input.display(), e))?;
+            let mut dir_files: Vec<PathBuf> = entries
+                .filter_map(|entry| entry.ok().map(|e| e.path()))
+                .filter(|path| path.is_file() && is_supported(path))
+                .collect();
+            dir_files.sort();
+            files.extend(dir_files);
+        } else {
+            files.push(input.clone());
+        }
+    }
+    Ok(files)
+}
+
+fn load_tree(path: &Path, slim: bool) -> Result<TagNodeRef, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if path.extension().and_then(|e| e.to_str()) == Some("json") {
+        serde_json::from_str(&content).map_err(|e| format!("{}: {}", path.display(), e))
+    } else if slim {
+        Ok(parse_page_html(&slim_html(&content)))
+    } else {
+        Ok(parse_page_html(&content))
+    }
+}
+
+fn collect_texts(node: &TagNodeRef, texts: &mut Vec<String>) {
+    if let Some(text) = &node.raw_text {
+        if !text.trim().is_empty() {
+            texts.push(text.clone());
+        }
+    }
+    for child in &node.children {
+        collect_texts(child, texts);
+    }
+}
+
+fn run(path: &Path, args: &Args, session: &MdrSession) -> Result<String, String> {
+    if args.format == Format::Slim {
+        if path.extension().and_then(|e| e.to_str()) == Some("json") {
+            return Err(format!("{}: slim output needs HTML input", path.display()));
+        }
+        let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        return Ok(slim_html(&content));
+    }
+
+    let root = load_tree(path, args.slim)?;
+    let output = session.run(&root);
+
+    let json = match args.format {
+        Format::Expected => {
+            let final_records = output.final_records;
+            let mut texts = Vec::new();
+            for record in &final_records {
+                match record {
+                    DataRecord::Single(node) => collect_texts(node, &mut texts),
+                    DataRecord::Multi(nodes) => nodes
+                        .iter()
+                        .for_each(|node| collect_texts(node, &mut texts)),
+                }
+            }
+            serde_json::to_string_pretty(&ExpectedOutput {
+                xpaths: record_xpaths(&final_records),
+                texts,
+                records: final_records,
+            })
+        }
+        Format::Full => serde_json::to_string_pretty(&output),
+        Format::Slim => unreachable!("slim output is written before running MDR"),
+    };
+    json.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn main() -> ExitCode {
+    let args = match parse_args() {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    // One session for all inputs keeps the distance cache warm across pages
+    let session = match MdrSession::new(MdrSessionConfig {
+        k: args.k,
+        t: args.t,
+        ..MdrSessionConfig::default()
+    }) {
+        Ok(session) => session,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let files = match collect_files(&args.inputs) {
+        Ok(files) => files,
+        Err(message) => {
+            eprintln!("{}", message);
+            return ExitCode::FAILURE;
+        }
+    };
+    if args.out.is_none() && files.len() > 1 {
+        eprintln!("--out is required when processing more than one file");
+        return ExitCode::FAILURE;
+    }
+    if let Some(out) = &args.out {
+        if let Err(e) = fs::create_dir_all(out) {
+            eprintln!("{}: {}", out.display(), e);
+            return ExitCode::FAILURE;
+        }
+    }
+
+    let mut failed = false;
+    for file in &files {
+        let result = run(file, &args, &session).and_then(|json| match &args.out {
+            Some(out) => {
+                let stem = file
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("output");
+                let extension = if args.format == Format::Slim {
+                    "html"
+                } else {
+                    "json"
+                };
+                let target = out.join(format!("{}.{}", stem, extension));
+                fs::write(&target, json).map_err(|e| format!("{}: {}", target.display(), e))?;
+                eprintln!("{} -> {}", file.display(), target.display());
+                Ok(())
+            }
+            None => {
+                println!("{}", json);
+                Ok(())
+            }
+        });
+
+        if let Err(message) = result {
+            eprintln!("{}", message);
+            failed = true;
+        }
+    }
+
+    if failed {
+        ExitCode::FAILURE
+    } else {
+        ExitCode::SUCCESS
+    }
+}
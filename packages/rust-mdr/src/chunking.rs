@@ -0,0 +1,611 @@
+use crate::context_reduction::{cut_page, PageIndex, PromptFormat, ReducedPage};
+use crate::error::MdrError;
+use crate::llm_response::MappedResponse;
+use crate::mdr_algorithm::{own_regions, run_mdr_algorithm_with_context};
+use crate::session::MdrContext;
+use crate::types::{MdrOptions, RegionsMapItem, TagNode, TagNodeRef};
+use crate::xpath_index::XpathIndex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// Token count of a prompt; plug in the model's tokenizer for exact budgets
+pub trait TokenEstimator {
+    fn estimate_tokens(&self, text: &str) -> usize;
+}
+
+impl<F: Fn(&str) -> usize> TokenEstimator for F {
+    fn estimate_tokens(&self, text: &str) -> usize {
+        self(text)
+    }
+}
+
+/// One token per `bytes_per_token` bytes, but at least one per whitespace-separated
+/// word. Markup tokenizes densely: the `_usage.json` prompts run at roughly one to
+/// two bytes per token, hence the default of 2.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteTokenEstimator {
+    pub bytes_per_token: usize,
+}
+
+impl Default for ByteTokenEstimator {
+    fn default() -> Self {
+        ByteTokenEstimator { bytes_per_token: 2 }
+    }
+}
+
+impl TokenEstimator for ByteTokenEstimator {
+    fn estimate_tokens(&self, text: &str) -> usize {
+        let bytes = text.len().div_ceil(self.bytes_per_token.max(1));
+        bytes.max(text.split_whitespace().count())
+    }
+}
+
+/// Options for [`chunk_page`]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ChunkOptions {
+    pub k: usize,
+    pub t: f32,
+    #[serde(flatten)]
+    pub mdr: MdrOptions,
+    /// Token budget of each chunk's prompt data
+    pub max_tokens: usize,
+    /// Prompt format the budget is measured in
+    pub format: PromptFormat,
+    /// For the default [`ByteTokenEstimator`]
+    pub bytes_per_token: usize,
+    /// Regions to keep together instead of running MDR
+    pub regions: Option<Vec<RegionsMapItem>>,
+}
+
+impl Default for ChunkOptions {
+    fn default() -> Self {
+        ChunkOptions {
+            k: 10,
+            t: 0.3,
+            mdr: MdrOptions::default(),
+            max_tokens: 4000,
+            format: PromptFormat::default(),
+            bytes_per_token: ByteTokenEstimator::default().bytes_per_token,
+            regions: None,
+        }
+    }
+}
+
+impl ChunkOptions {
+    pub fn default_estimator(&self) -> ByteTokenEstimator {
+        ByteTokenEstimator {
+            bytes_per_token: self.bytes_per_token,
+        }
+    }
+}
+
+/// A run of whole subtrees in document order, rendered as its own page
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Chunk {
+    /// Original xpaths of the subtrees in the chunk, in document order. A text node is
+    /// `<parent>/text()[n]`, the `n`-th text child of its parent.
+    pub xpaths: Vec<String>,
+    /// First and last of `xpaths`: the chunk covers this range of the page
+    pub start_xpath: String,
+    pub end_xpath: String,
+    /// Estimated tokens of the chunk's prompt data in the chosen format
+    pub tokens: usize,
+    /// A single childless subtree or data region that alone exceeds the budget
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub oversized: bool,
+    /// The chunk in all three formats, with `xpathMap` back to the original page;
+    /// `regions` lists the data regions inside it
+    #[serde(flatten)]
+    pub page: ReducedPage,
+}
+
+/// Sibling nodes that go into a chunk together
+#[derive(Clone)]
+struct Unit {
+    nodes: Vec<TagNodeRef>,
+    /// Xpaths of `nodes` as reported in [`Chunk::xpaths`]
+    xpaths: Vec<String>,
+    /// Estimated tokens of the unit rendered alone, ancestors included
+    tokens: usize,
+    /// The data region these siblings form, if any
+    region: Option<RegionsMapItem>,
+}
+
+/// Subtrees estimated above this multiple of the budget are split without rendering
+/// them; the bottom-up estimate stays well within it of the rendered count
+const ESTIMATE_SLACK: usize = 2;
+
+/// Rough tokens of a node's own share of the prompt data and of its whole subtree's
+#[derive(Clone, Copy, Default)]
+struct SizeEstimate {
+    own: usize,
+    subtree: usize,
+}
+
+struct Chunker<'a> {
+    page: PageIndex<'a>,
+    options: &'a ChunkOptions,
+    estimator: &'a dyn TokenEstimator,
+    /// Parent xpath → data regions among its children, as merged `(start, end)` ranges
+    protected: HashMap<String, Vec<(usize, usize)>>,
+    regions: Vec<RegionsMapItem>,
+    estimates: HashMap<*const TagNode, SizeEstimate>,
+}
+
+/// Splits a page into chunks of whole subtrees whose prompt data stays within
+/// `max_tokens`.
+///
+/// A subtree whose prompt data, ancestors included, is over budget is replaced by its
+/// children, recursively, except that the children forming an MDR data region always
+/// stay together. Only regions that repeat under their own parent count, not the
+/// copies MDR lists under ancestors. Subtree sizes are first estimated bottom-up, so
+/// only subtrees near the budget are rendered to decide. Units are then packed in
+/// document order, each chunk taking as many units as fit on its actual prompt data
+/// (found by bisection, assuming more units never render smaller). A subtree
+/// without children or a data region larger than the budget becomes a chunk of its
+/// own, flagged `oversized`. Each chunk is a [`ReducedPage`], so per-chunk LLM replies
+/// map back with [`merge_chunk_responses`].
+pub fn chunk_page(
+    root: &TagNodeRef,
+    options: &ChunkOptions,
+    estimator: &dyn TokenEstimator,
+) -> Vec<Chunk> {
+    let context = MdrContext::global(options.k, options.t, options.mdr.clone());
+    let regions = match &options.regions {
+        Some(regions) => regions.clone(),
+        None => run_mdr_algorithm_with_context(root, &context),
+    };
+
+    let index = XpathIndex::new(root);
+    let regions = own_regions(&regions, &index, &context);
+    let mut protected: HashMap<String, Vec<(usize, usize)>> = HashMap::new();
+    for item in &regions {
+        let ranges = protected.entry(item.parent_xpath.clone()).or_default();
+        ranges.extend(
+            item.regions
+                .iter()
+                .map(|&(_, start, count)| (start, start + count)),
+        );
+    }
+    for ranges in protected.values_mut() {
+        *ranges = merge_ranges(ranges);
+    }
+
+    let mut estimates = HashMap::new();
+    estimate_subtree(root, 0, options.format, estimator, &mut estimates);
+    let chunker = Chunker {
+        page: PageIndex::new(root),
+        options,
+        estimator,
+        protected,
+        regions,
+        estimates,
+    };
+    let mut units = Vec::new();
+    chunker.split(root, root.xpath.clone(), 0, &mut units);
+    chunker.pack(&units)
+}
+
+/// Estimates `node`'s subtree at `depth` from one estimate per node of the text it adds
+/// to the prompt data; skipped `script`/`style` subtrees count as nothing
+fn estimate_subtree(
+    node: &TagNodeRef,
+    depth: usize,
+    format: PromptFormat,
+    estimator: &dyn TokenEstimator,
+    estimates: &mut HashMap<*const TagNode, SizeEstimate>,
+) -> usize {
+    if matches!(node.tag_name.as_str(), "script" | "style") {
+        return 0;
+    }
+    let mut children = 0;
+    let mut nested = false;
+    for child in &node.children {
+        let tokens = estimate_subtree(child, depth + 1, format, estimator, estimates);
+        children += tokens;
+        nested |= tokens > 0 && child.tag_name != "text";
+    }
+    let own = estimator.estimate_tokens(&own_prompt_data(node, depth, format, nested));
+    let subtree = own + children;
+    estimates.insert(Arc::as_ptr(node), SizeEstimate { own, subtree });
+    subtree
+}
+
+/// Roughly what `node` itself adds to the prompt data in `format`: its tags or text in
+/// the markup, its text-map entry. `nested` is set when element children add text-map
+/// entries, which makes the element an object in `hier`.
+fn own_prompt_data(node: &TagNode, depth: usize, format: PromptFormat, nested: bool) -> String {
+    let text = node.raw_text.as_deref().map_or("", str::trim);
+    if node.tag_name == "text" && text.is_empty() {
+        return String::new();
+    }
+    let step = node.xpath.rsplit('/').next().unwrap_or_default();
+    // A text is an entry of its parent element
+    let indent = "  ".repeat(depth.saturating_sub(usize::from(node.tag_name == "text")));
+    match (format, node.tag_name.as_str()) {
+        (PromptFormat::Slim, "text") => format!(" {text} "),
+        (PromptFormat::Slim, tag) => format!("<{tag}></{tag}>"),
+        (PromptFormat::Flat, "text") => format!("  \"{}\": \"{text}\",\n", node.xpath),
+        (PromptFormat::Hier, "text") => format!("{indent}\"{step}\": \"{text}\",\n"),
+        (PromptFormat::Hier, _) if nested => format!("{indent}\"{step}\": {{\n{indent}}},\n"),
+        (PromptFormat::Flat | PromptFormat::Hier, _) => String::new(),
+    }
+}
+
+/// Sorted ranges with overlapping ones merged
+fn merge_ranges(ranges: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    let mut sorted = ranges.to_vec();
+    sorted.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(sorted.len());
+    for (start, end) in sorted {
+        match merged.last_mut() {
+            Some(last) if start < last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Xpaths of `parent`'s children. Text nodes share their parent's xpath, so they are
+/// reported as `<parent>/text()[n]` rather than as the whole parent.
+fn child_xpaths(parent: &TagNode) -> Vec<String> {
+    let mut texts = 0;
+    parent
+        .children
+        .iter()
+        .map(|child| {
+            if child.tag_name != "text" {
+                return child.xpath.clone();
+            }
+            texts += 1;
+            format!("{}/text()[{texts}]", parent.xpath)
+        })
+        .collect()
+}
+
+impl Chunker<'_> {
+    fn unit(
+        &self,
+        nodes: Vec<TagNodeRef>,
+        xpaths: Vec<String>,
+        region: Option<RegionsMapItem>,
+    ) -> Unit {
+        let mut unit = Unit {
+            nodes,
+            xpaths,
+            tokens: 0,
+            region,
+        };
+        unit.tokens = self.tokens(std::slice::from_ref(&unit));
+        unit
+    }
+
+    fn estimate(&self, node: &TagNode) -> SizeEstimate {
+        self.estimates
+            .get(&(node as *const TagNode))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Splits `node` into units; `overhead` estimates what its ancestors add
+    fn split(&self, node: &TagNodeRef, xpath: String, overhead: usize, units: &mut Vec<Unit>) {
+        let max_tokens = self.options.max_tokens;
+        let estimate = self.estimate(node);
+        if node.children.is_empty()
+            || overhead + estimate.subtree <= max_tokens.saturating_mul(ESTIMATE_SLACK)
+        {
+            let unit = self.unit(vec![node.clone()], vec![xpath], None);
+            if unit.tokens <= max_tokens || node.children.is_empty() {
+                units.push(unit);
+                return;
+            }
+        }
+
+        let ranges = self
+            .protected
+            .get(&node.xpath)
+            .map_or(&[][..], Vec::as_slice);
+        let mut ranges = ranges.iter().peekable();
+        let xpaths = child_xpaths(node);
+        let overhead = overhead + estimate.own;
+        let mut i = 0;
+        while i < node.children.len() {
+            if let Some(&&(start, end)) = ranges.peek().filter(|(start, _)| *start == i) {
+                ranges.next();
+                let nodes = node.children[start..end].to_vec();
+                let region = self.regions_within(&node.xpath, start, end);
+                units.push(self.unit(nodes, xpaths[start..end].to_vec(), Some(region)));
+                i = end;
+                continue;
+            }
+            self.split(&node.children[i], xpaths[i].clone(), overhead, units);
+            i += 1;
+        }
+    }
+
+    /// The data regions of `parent_xpath` inside children `start..end`
+    fn regions_within(&self, parent_xpath: &str, start: usize, end: usize) -> RegionsMapItem {
+        let regions = self
+            .regions
+            .iter()
+            .filter(|item| item.parent_xpath == parent_xpath)
+            .flat_map(|item| item.regions.iter().copied())
+            .filter(|&(_, region_start, count)| {
+                region_start >= start && region_start + count <= end
+            })
+            .collect();
+        RegionsMapItem {
+            parent_xpath: parent_xpath.to_string(),
+            regions,
+        }
+    }
+
+    /// Data regions of the chunk: those formed by its units plus every region whose
+    /// parent lies inside one of its subtrees
+    fn chunk_regions(&self, units: &[Unit]) -> Vec<RegionsMapItem> {
+        let mut regions: Vec<RegionsMapItem> = units
+            .iter()
+            .filter_map(|unit| unit.region.clone())
+            .collect();
+        let subtrees: Vec<&str> = units
+            .iter()
+            .filter(|unit| unit.region.is_none())
+            .flat_map(|unit| &unit.nodes)
+            .filter(|node| node.tag_name != "text")
+            .map(|node| node.xpath.as_str())
+            .collect();
+        regions.extend(
+            self.regions
+                .iter()
+                .filter(|item| {
+                    subtrees.iter().any(|xpath| {
+                        item.parent_xpath
+                            .strip_prefix(xpath)
+                            .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+                    })
+                })
+                .cloned(),
+        );
+        regions
+    }
+
+    fn pack(&self, units: &[Unit]) -> Vec<Chunk> {
+        let max_tokens = self.options.max_tokens;
+        let fits = |units: &[Unit]| self.tokens(units) <= max_tokens;
+        let mut chunks = Vec::new();
+        let mut rest = units;
+        while !rest.is_empty() {
+            // Each unit's tokens include its ancestors, so the sum overestimates units
+            // sharing them; start from it, then gallop and bisect on rendered counts
+            // for the longest run that fits (`fit` fits or is 1, `over` does not fit)
+            let mut take = 1;
+            let mut estimate = rest[0].tokens;
+            while take < rest.len() && estimate + rest[take].tokens <= max_tokens {
+                estimate += rest[take].tokens;
+                take += 1;
+            }
+
+            let (mut fit, mut over) = (1, take);
+            if fits(&rest[..take]) {
+                fit = take;
+                over = rest.len() + 1;
+                let mut step = 1;
+                while fit + step <= rest.len() {
+                    if !fits(&rest[..fit + step]) {
+                        over = fit + step;
+                        break;
+                    }
+                    fit += step;
+                    step *= 2;
+                }
+            }
+            while over - fit > 1 {
+                let mid = (fit + over) / 2;
+                if fits(&rest[..mid]) {
+                    fit = mid;
+                } else {
+                    over = mid;
+                }
+            }
+            chunks.push(self.render(&rest[..fit]));
+            rest = &rest[fit..];
+        }
+        chunks
+    }
+
+    /// Estimated tokens of the prompt data of `units` rendered as one chunk
+    fn tokens(&self, units: &[Unit]) -> usize {
+        let page = cut_page(&self.page, &nodes_of(units), Vec::new());
+        self.estimator
+            .estimate_tokens(&page.prompt_data(self.options.format))
+    }
+
+    fn render(&self, units: &[Unit]) -> Chunk {
+        let page = cut_page(&self.page, &nodes_of(units), self.chunk_regions(units));
+        let tokens = self
+            .estimator
+            .estimate_tokens(&page.prompt_data(self.options.format));
+
+        let mut xpaths: Vec<String> = units
+            .iter()
+            .flat_map(|unit| unit.xpaths.iter().cloned())
+            .filter(|xpath| !xpath.is_empty())
+            .collect();
+        xpaths.dedup();
+        Chunk {
+            start_xpath: xpaths.first().cloned().unwrap_or_default(),
+            end_xpath: xpaths.last().cloned().unwrap_or_default(),
+            xpaths,
+            oversized: tokens > self.options.max_tokens,
+            tokens,
+            page,
+        }
+    }
+}
+
+fn nodes_of(units: &[Unit]) -> Vec<TagNodeRef> {
+    units
+        .iter()
+        .flat_map(|unit| unit.nodes.iter().cloned())
+        .collect()
+}
+
+/// Merges per-chunk LLM replies (`responses[i]` answers `chunks[i]`) into one
+/// response over the original page, records in chunk order. `invalidXpaths` refer to
+/// records of the merged list.
+pub fn merge_chunk_responses<S: AsRef<str>>(
+    chunks: &[Chunk],
+    responses: &[Vec<Vec<S>>],
+) -> Result<MappedResponse, MdrError> {
+    if responses.len() != chunks.len() {
+        return Err(MdrError::InvalidParameter {
+            name: "responses".to_string(),
+            value: responses.len().to_string(),
+            reason: format!("expected one response per chunk ({})", chunks.len()),
+        });
+    }
+
+    let mut merged = MappedResponse::default();
+    for (chunk, response) in chunks.iter().zip(responses) {
+        let mapped = chunk.page.map_response(response);
+        let offset = merged.records.len();
+        merged.records.extend(mapped.records);
+        merged.num_hallucination += mapped.num_hallucination;
+        merged
+            .invalid_xpaths
+            .extend(mapped.invalid_xpaths.into_iter().map(|mut invalid| {
+                invalid.record += offset;
+                invalid
+            }));
+    }
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_parser::parse_html;
+    use crate::slim_html::tag_tree_html;
+
+    fn page(items: usize) -> String {
+        let rows: String = (0..items)
+            .map(|i| format!("<li><a>Item number {i}</a><span>{i} dollars</span></li>"))
+            .collect();
+        format!(
+            "<html><body><div><h1>Catalog of things</h1><p>Some introduction text.</p></div>\
+             <ul>{rows}</ul><div><p>Footer one</p><p>Footer two</p></div></body></html>"
+        )
+    }
+
+    #[test]
+    fn chunks_stay_within_budget_and_keep_regions_whole() {
+        let root = parse_html(&page(8));
+        let list = "/html[1]/body[1]/ul[1]";
+        let options = ChunkOptions {
+            max_tokens: 140,
+            regions: Some(vec![RegionsMapItem {
+                parent_xpath: list.to_string(),
+                regions: vec![(1, 2, 4)],
+            }]),
+            ..ChunkOptions::default()
+        };
+        let chunks = chunk_page(&root, &options, &options.default_estimator());
+
+        assert!(chunks.len() > 1);
+        assert!(chunks
+            .iter()
+            .all(|chunk| chunk.tokens <= 140 && !chunk.oversized));
+        // Every list item lands in exactly one chunk, li[3]..li[6] in the same one
+        let items: Vec<&String> = chunks
+            .iter()
+            .flat_map(|chunk| &chunk.xpaths)
+            .filter(|xpath| xpath.starts_with(list))
+            .collect();
+        assert_eq!(items.len(), 8);
+        let region_chunk: Vec<&Chunk> = chunks
+            .iter()
+            .filter(|chunk| chunk.xpaths.iter().any(|xpath| xpath.ends_with("li[3]")))
+            .collect();
+        assert_eq!(region_chunk.len(), 1);
+        assert!(region_chunk[0]
+            .xpaths
+            .iter()
+            .any(|xpath| xpath.ends_with("li[6]")));
+        assert_eq!(region_chunk[0].page.regions[0].regions, vec![(1, 2, 4)]);
+        assert!(chunks
+            .windows(2)
+            .all(|pair| pair[0].end_xpath != pair[1].start_xpath));
+
+        // A reply per chunk naming its first list item maps back to the original page
+        let responses: Vec<Vec<Vec<String>>> = chunks
+            .iter()
+            .map(|chunk| {
+                let first_li = chunk
+                    .page
+                    .xpath_map
+                    .keys()
+                    .find(|xpath| xpath.ends_with("/li[1]"));
+                vec![
+                    first_li.map_or(vec!["/body[1]/table[1]".to_string()], |xpath| {
+                        vec![xpath.clone()]
+                    }),
+                ]
+            })
+            .collect();
+        let merged = merge_chunk_responses(&chunks, &responses).unwrap();
+        assert_eq!(merged.records.len(), chunks.len());
+        let found: Vec<&String> = merged.records.iter().flatten().collect();
+        assert!(found.contains(&&"/body[1]/ul[1]/li[3]/a[1]".to_string()));
+        assert!(merged
+            .invalid_xpaths
+            .iter()
+            .all(|invalid| invalid.xpath == "/body[1]/table[1]"));
+        assert!(merge_chunk_responses(&chunks, &responses[1..]).is_err());
+    }
+
+    #[test]
+    fn oversized_regions_and_custom_estimators() {
+        let root = parse_html(&page(4));
+        let options = ChunkOptions {
+            max_tokens: 100,
+            regions: Some(vec![RegionsMapItem {
+                parent_xpath: "/html[1]/body[1]/ul[1]".to_string(),
+                regions: vec![(1, 0, 4)],
+            }]),
+            ..ChunkOptions::default()
+        };
+        let chunks = chunk_page(&root, &options, &options.default_estimator());
+        let oversized: Vec<&Chunk> = chunks.iter().filter(|chunk| chunk.oversized).collect();
+        assert_eq!(oversized.len(), 1);
+        assert_eq!(oversized[0].xpaths.len(), 4);
+
+        // Down to leaves, text reads as itself rather than as its whole element
+        let options = ChunkOptions {
+            max_tokens: 10,
+            regions: Some(Vec::new()),
+            ..ChunkOptions::default()
+        };
+        let chunks = chunk_page(&root, &options, &options.default_estimator());
+        let heading = "/html[1]/body[1]/div[1]/h1[1]";
+        assert!(chunks.iter().all(|chunk| chunk.xpaths.len() == 1));
+        assert!(chunks.iter().all(|chunk| chunk.xpaths[0] != heading));
+        assert!(chunks
+            .iter()
+            .any(|chunk| chunk.xpaths[0] == format!("{heading}/text()[1]")));
+
+        // One token per tag: the whole page fits a budget of 100
+        let count_tags = |text: &str| text.matches('<').count();
+        let options = ChunkOptions {
+            max_tokens: 100,
+            regions: Some(Vec::new()),
+            ..ChunkOptions::default()
+        };
+        let chunks = chunk_page(&root, &options, &count_tags);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].xpaths, vec!["/html[1]".to_string()]);
+        assert_eq!(chunks[0].tokens, tag_tree_html(&root).matches('<').count());
+    }
+}
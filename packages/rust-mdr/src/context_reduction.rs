@@ -0,0 +1,459 @@
+use crate::llm_response::{MappedResponse, ResponseMapper};
+use crate::mdr_algorithm::{own_regions, run_mdr_algorithm_with_context};
+use crate::session::MdrContext;
+use crate::slim_html::tag_tree_html;
+use crate::text_map::{extract_text_with_xpaths, llm_xpath, TextMaps};
+use crate::tree_utils::count_nodes;
+use crate::types::{DataRegion, MdrOptions, RegionsMapItem, TagNode, TagNodeRef};
+use crate::xpath_index::XpathIndex;
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Options for [`reduce_context`]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ReductionOptions {
+    pub k: usize,
+    pub t: f32,
+    #[serde(flatten)]
+    pub mdr: MdrOptions,
+    /// Regions with fewer generalized nodes (records) than this are ignored
+    pub min_records: usize,
+    /// Levels above the region's nodes whose whole subtree is kept: 0 keeps only the
+    /// region's nodes, 1 their parent (the whole list), 2 also the list's siblings such as
+    /// a heading, and so on
+    pub context_levels: usize,
+    /// Regions to cut around instead of running MDR, e.g. `regions` of an earlier
+    /// `runMdrFull` or of a previous reduction
+    pub regions: Option<Vec<RegionsMapItem>>,
+}
+
+impl Default for ReductionOptions {
+    fn default() -> Self {
+        ReductionOptions {
+            k: 10,
+            t: 0.3,
+            mdr: MdrOptions::default(),
+            min_records: 3,
+            context_levels: 0,
+            regions: None,
+        }
+    }
+}
+
+/// The three LLM input formats, named like the web app's `promptType`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PromptFormat {
+    #[default]
+    Slim,
+    Flat,
+    Hier,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct ReductionStats {
+    pub original_nodes: usize,
+    pub reduced_nodes: usize,
+    /// Length of the slim markup of the whole page and of the reduced page
+    pub original_chars: usize,
+    pub reduced_chars: usize,
+}
+
+/// A page cut down to its candidate data regions, in every prompt format
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ReducedPage {
+    /// The reduced tree, numbered as if its slim markup were parsed again
+    #[serde(skip)]
+    pub root: TagNodeRef,
+    /// Regions the page was cut around, after filtering
+    pub regions: Vec<RegionsMapItem>,
+    /// The `slim` format
+    pub slim_html: String,
+    /// The `flat` and `hier` formats
+    #[serde(flatten)]
+    pub text_maps: TextMaps,
+    /// Xpath of every kept element in the reduced tree → its xpath in the original
+    pub xpath_map: IndexMap<String, String>,
+    pub stats: ReductionStats,
+    /// Text-map key in the reduced page → key of the same text in the original
+    #[serde(skip)]
+    key_map: IndexMap<String, String>,
+}
+
+impl ReducedPage {
+    /// The `data` the web app sends for `format`, i.e. `JSON.stringify(data, null, 2)`
+    pub fn prompt_data(&self, format: PromptFormat) -> String {
+        let data = match format {
+            PromptFormat::Slim => serde_json::to_string_pretty(&self.slim_html),
+            PromptFormat::Flat => serde_json::to_string_pretty(&self.text_maps.text_map_flat),
+            PromptFormat::Hier => serde_json::to_string_pretty(&self.text_maps.text_map),
+        };
+        data.expect("strings and string maps always serialize")
+    }
+
+    /// Maps an LLM reply about the reduced page like `map_response_to_full_xpath`,
+    /// then rewrites the records to `textMapFlat` keys of the original page so they
+    /// can be scored against its ground truth. `invalidXpaths` stay as the LLM wrote
+    /// them.
+    pub fn map_response<S: AsRef<str>>(&self, response: &[Vec<S>]) -> MappedResponse {
+        let mut mapped = ResponseMapper::new(&self.root).map_response(response);
+        for record in &mut mapped.records {
+            for key in record.iter_mut() {
+                if let Some(original) = self.key_map.get(key.as_str()) {
+                    key.clone_from(original);
+                }
+            }
+        }
+        mapped
+    }
+}
+
+/// Cuts the tree down to the subtrees holding MDR data regions for a hybrid MDR+LLM
+/// run.
+///
+/// Copies of descendant regions listed under ancestors (see [`own_regions`]) and
+/// regions that repeat fewer than `min_records` times are skipped. Every other region keeps the subtree
+/// `context_levels` above its nodes plus the chain of ancestors up to the root
+/// (without their other children and texts), so the reduced page is still a
+/// well-formed document. Kept siblings are renumbered as `parse_html` would number
+/// them; `xpath_map` leads back to the original xpaths. A page without regions is
+/// kept whole.
+pub fn reduce_context(root: &TagNodeRef, options: &ReductionOptions) -> ReducedPage {
+    let context = MdrContext::global(options.k, options.t, options.mdr.clone());
+    let regions = match &options.regions {
+        Some(regions) => regions.clone(),
+        None => run_mdr_algorithm_with_context(root, &context),
+    };
+
+    let index = XpathIndex::new(root);
+    let mut selected: Vec<RegionsMapItem> = Vec::new();
+    let mut kept: Vec<TagNodeRef> = Vec::new();
+    for item in &own_regions(&regions, &index, &context) {
+        let Some(parent) = index.get(&item.parent_xpath) else {
+            continue;
+        };
+        let item_regions: Vec<DataRegion> = item
+            .regions
+            .iter()
+            .copied()
+            .filter(|&region| is_candidate(parent, region, options.min_records))
+            .collect();
+        for &(_, start_idx, node_count) in &item_regions {
+            if options.context_levels > 0 {
+                let context = ancestor_xpath(&parent.xpath, options.context_levels - 1);
+                kept.extend(index.get(context).cloned());
+            } else {
+                kept.extend_from_slice(&parent.children[start_idx..start_idx + node_count]);
+            }
+        }
+        if !item_regions.is_empty() {
+            selected.push(RegionsMapItem {
+                parent_xpath: item.parent_xpath.clone(),
+                regions: item_regions,
+            });
+        }
+    }
+    if kept.is_empty() {
+        kept.push(root.clone());
+    }
+
+    cut_page(&PageIndex::new(root), &kept, selected)
+}
+
+/// Parent links and whole-page stats, built once so that each [`cut_page`] takes time
+/// in the size of the cut rather than of the page
+pub(crate) struct PageIndex<'a> {
+    root: &'a TagNodeRef,
+    /// Node → its parent and its position among the parent's children
+    parents: HashMap<*const TagNode, (&'a TagNodeRef, usize)>,
+    nodes: usize,
+    chars: usize,
+}
+
+impl<'a> PageIndex<'a> {
+    pub(crate) fn new(root: &'a TagNodeRef) -> Self {
+        let mut parents = HashMap::new();
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            for (i, child) in node.children.iter().enumerate() {
+                parents.insert(Arc::as_ptr(child), (node, i));
+                stack.push(child);
+            }
+        }
+        PageIndex {
+            root,
+            parents,
+            nodes: count_nodes(root),
+            chars: tag_tree_html(root).chars().count(),
+        }
+    }
+}
+
+/// The page reduced to the `kept` subtrees and their ancestors, in every format
+pub(crate) fn cut_page(
+    page: &PageIndex,
+    kept: &[TagNodeRef],
+    regions: Vec<RegionsMapItem>,
+) -> ReducedPage {
+    // Positions of the children leading to kept nodes, per ancestor
+    let mut paths: HashMap<*const TagNode, Vec<usize>> = HashMap::new();
+    for node in kept {
+        let mut node = Arc::as_ptr(node);
+        while let Some(&(parent, i)) = page.parents.get(&node) {
+            node = Arc::as_ptr(parent);
+            let seen = paths.contains_key(&node);
+            paths.entry(node).or_default().push(i);
+            if seen {
+                break;
+            }
+        }
+    }
+    for positions in paths.values_mut() {
+        positions.sort_unstable();
+        positions.dedup();
+    }
+
+    let kept: NodeSet = kept.iter().map(Arc::as_ptr).collect();
+    let mut reducer = Reducer {
+        kept: &kept,
+        paths: &paths,
+        xpath_map: IndexMap::new(),
+        key_map: IndexMap::new(),
+    };
+    let reduced = reducer.rebuild(page.root, page.root.xpath.clone(), false);
+
+    let slim_html = tag_tree_html(&reduced);
+    let stats = ReductionStats {
+        original_nodes: page.nodes,
+        reduced_nodes: count_nodes(&reduced),
+        original_chars: page.chars,
+        reduced_chars: slim_html.chars().count(),
+    };
+    ReducedPage {
+        text_maps: extract_text_with_xpaths(&reduced),
+        root: reduced,
+        regions,
+        slim_html,
+        xpath_map: reducer.xpath_map,
+        stats,
+        key_map: reducer.key_map,
+    }
+}
+
+/// Nodes by identity, since a text node shares its parent's xpath
+type NodeSet = HashSet<*const TagNode>;
+
+/// Whether `region` lies within `parent`'s children and repeats at least
+/// `min_records` times
+pub(crate) fn is_candidate(parent: &TagNode, region: DataRegion, min_records: usize) -> bool {
+    let (gn_length, start_idx, node_count) = region;
+    gn_length > 0
+        && start_idx + node_count <= parent.children.len()
+        && node_count / gn_length >= min_records
+}
+
+/// `xpath` with its last `levels` steps removed, never above the root element
+fn ancestor_xpath(xpath: &str, levels: usize) -> &str {
+    let mut ancestor = xpath;
+    for _ in 0..levels {
+        match ancestor.rsplit_once('/') {
+            Some((parent, _)) if !parent.is_empty() => ancestor = parent,
+            _ => break,
+        }
+    }
+    ancestor
+}
+
+struct Reducer<'a> {
+    kept: &'a NodeSet,
+    paths: &'a HashMap<*const TagNode, Vec<usize>>,
+    xpath_map: IndexMap<String, String>,
+    key_map: IndexMap<String, String>,
+}
+
+impl Reducer<'_> {
+    /// Copy of `node` at `xpath`; inside a kept subtree everything is copied, above
+    /// it only the nodes leading to kept subtrees
+    fn rebuild(&mut self, node: &TagNode, xpath: String, in_kept: bool) -> TagNodeRef {
+        let in_kept = in_kept || self.kept.contains(&(node as *const TagNode));
+        let mut copy = TagNode::new(node.tag_name.clone(), xpath.clone());
+        copy.raw_text.clone_from(&node.raw_text);
+        copy.attributes.clone_from(&node.attributes);
+        self.xpath_map.insert(xpath.clone(), node.xpath.clone());
+
+        // Only the children on a path to a kept node, unless the node is kept itself
+        let paths = self.paths;
+        let children: Box<dyn Iterator<Item = &TagNodeRef>> = if in_kept {
+            Box::new(node.children.iter())
+        } else {
+            let positions = paths
+                .get(&(node as *const TagNode))
+                .map_or(&[][..], Vec::as_slice);
+            Box::new(positions.iter().map(|&i| &node.children[i]))
+        };
+
+        let mut has_text = false;
+        let mut tag_counts: Vec<(&str, usize)> = Vec::new();
+        for child in children {
+            if child.tag_name == "text" {
+                let mut text = TagNode::clone(child);
+                if !text.xpath.is_empty() {
+                    text.xpath.clone_from(&xpath);
+                    has_text = true;
+                }
+                copy.add_child(Arc::new(text));
+                continue;
+            }
+
+            let tag = child.tag_name.as_str();
+            let index = match tag_counts.iter_mut().find(|(seen, _)| *seen == tag) {
+                Some((_, count)) => {
+                    *count += 1;
+                    *count
+                }
+                None => {
+                    tag_counts.push((tag, 1));
+                    1
+                }
+            };
+            let child_xpath = format!("{}/{}[{}]", xpath, tag, index);
+            copy.add_child(self.rebuild(child, child_xpath, in_kept));
+        }
+
+        if has_text {
+            self.key_map.insert(llm_xpath(&copy), llm_xpath(node));
+        }
+        Arc::new(copy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_parser::parse_html;
+
+    const PAGE: &str = "<html><head><title>Shop</title></head><body>\
+        <div><p>Menu</p><p>About</p></div>\
+        <div><h2>Results</h2><ul>\
+        <li><a>One</a><span>1</span></li>\
+        <li><a>Two</a><span>2</span></li>\
+        <li><a>Three</a><span>3</span></li>\
+        </ul></div><p>Footer</p></body></html>";
+
+    #[test]
+    fn reduced_page_keeps_regions_and_maps_back() {
+        let root = parse_html(PAGE);
+        let regions = vec![RegionsMapItem {
+            parent_xpath: "/html[1]/body[1]/div[2]/ul[1]".to_string(),
+            regions: vec![(1, 0, 3)],
+        }];
+
+        let options = ReductionOptions {
+            regions: Some(regions.clone()),
+            context_levels: 0,
+            ..ReductionOptions::default()
+        };
+        let page = reduce_context(&root, &options);
+        assert_eq!(
+            page.slim_html,
+            "<html><body><div><ul><li><a>One</a><span>1</span></li><li><a>Two</a>\
+             <span>2</span></li><li><a>Three</a><span>3</span></li></ul></div></body></html>"
+        );
+        // div[2] is the only div left, so it becomes div[1]
+        assert_eq!(
+            page.xpath_map["/html[1]/body[1]/div[1]/ul[1]/li[2]/a[1]"],
+            "/html[1]/body[1]/div[2]/ul[1]/li[2]/a[1]"
+        );
+        let flat_keys: Vec<&str> = page
+            .text_maps
+            .text_map_flat
+            .keys()
+            .map(String::as_str)
+            .collect();
+        assert_eq!(flat_keys[0], "/body[1]/div[1]/ul[1]/li[1]/a[1]");
+        assert!(page
+            .prompt_data(PromptFormat::Flat)
+            .starts_with("{\n  \"/body[1]/div[1]"));
+        assert!(page.stats.reduced_chars < page.stats.original_chars);
+
+        // Reparsing the slim markup yields the reduced tree's numbering
+        let reparsed = parse_html(&page.slim_html);
+        assert_eq!(
+            extract_text_with_xpaths(&reparsed).text_map_flat,
+            page.text_maps.text_map_flat
+        );
+
+        let mapped = page.map_response(&[
+            vec!["/html/body/div/ul/li[1]"],
+            vec!["/body[1]/div[1]/ul[1]/li[3]/span[1]"],
+            vec!["/body[1]/div[2]"],
+        ]);
+        assert_eq!(
+            mapped.records,
+            vec![
+                vec![
+                    "/body[1]/div[2]/ul[1]/li[1]/a[1]".to_string(),
+                    "/body[1]/div[2]/ul[1]/li[1]/span[1]".to_string(),
+                ],
+                vec!["/body[1]/div[2]/ul[1]/li[3]/span[1]".to_string()],
+                vec![],
+            ]
+        );
+        assert_eq!(mapped.num_hallucination, 1);
+
+        // Two levels of context keep the list's heading but not the menu or footer
+        let page = reduce_context(
+            &root,
+            &ReductionOptions {
+                regions: Some(regions),
+                context_levels: 2,
+                ..ReductionOptions::default()
+            },
+        );
+        assert!(page
+            .slim_html
+            .starts_with("<html><body><div><h2>Results</h2><ul>"));
+        assert!(!page.slim_html.contains("Menu") && !page.slim_html.contains("Footer"));
+
+        // Without regions the whole page is kept
+        let page = reduce_context(
+            &root,
+            &ReductionOptions {
+                regions: Some(Vec::new()),
+                ..ReductionOptions::default()
+            },
+        );
+        assert_eq!(page.slim_html, tag_tree_html(&root));
+        assert!(page
+            .xpath_map
+            .iter()
+            .all(|(reduced, original)| reduced == original));
+    }
+
+    #[test]
+    fn copied_regions_do_not_keep_their_ancestors() {
+        let root = parse_html(PAGE);
+        let context = MdrContext::new(10, 0.3, MdrOptions::default());
+        let regions = run_mdr_algorithm_with_context(&root, &context);
+        // The search lists the list's region under every ancestor as well
+        assert!(regions.iter().any(|item| item.parent_xpath == "/html[1]"));
+
+        let page = reduce_context(&root, &ReductionOptions::default());
+        let parents: Vec<&str> = page
+            .regions
+            .iter()
+            .map(|item| item.parent_xpath.as_str())
+            .collect();
+        assert_eq!(parents, vec!["/html[1]/body[1]/div[2]/ul[1]"]);
+        assert_eq!(
+            page.slim_html,
+            "<html><body><div><ul><li><a>One</a><span>1</span></li><li><a>Two</a>\
+             <span>2</span></li><li><a>Three</a><span>3</span></li></ul></div></body></html>"
+        );
+    }
+}
@@ -0,0 +1,412 @@
+use crate::types::SimilarityMetric;
+use once_cell::sync::Lazy;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Process-wide cache used by the entry points that take plain `(k, t)` parameters;
+/// `session::MdrSession` owns its own instead
+pub static DISTANCE_CACHE: Lazy<Arc<DistanceCache>> =
+    Lazy::new(|| Arc::new(DistanceCache::new(DistanceCacheConfig::default())));
+
+/// What is evicted once the cache is full
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum EvictionPolicy {
+    /// Approximate least-recently-used (CLOCK / second chance)
+    #[default]
+    Lru,
+    /// Oldest insertion first
+    Fifo,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DistanceCacheConfig {
+    /// Maximum number of cached distances; 0 disables caching
+    pub capacity: usize,
+    pub policy: EvictionPolicy,
+}
+
+impl Default for DistanceCacheConfig {
+    fn default() -> Self {
+        DistanceCacheConfig {
+            capacity: 65_536,
+            policy: EvictionPolicy::Lru,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct DistanceCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub insertions: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub capacity: usize,
+}
+
+impl DistanceCacheStats {
+    pub fn hit_rate(&self) -> f64 {
+        let lookups = self.hits + self.misses;
+        if lookups == 0 {
+            0.0
+        } else {
+            self.hits as f64 / lookups as f64
+        }
+    }
+}
+
+/// Whether a distance compares two single nodes or two generalized-node sequences.
+/// The two differ (sequences apply the 2x length pruning), so they never share entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DistanceScope {
+    Node,
+    Sequence,
+}
+
+/// Content address of a distance: metric, scope and the structural hashes of both
+/// sides (see `tree_utils::structural_hash`), stored in sorted order since every
+/// metric is symmetric.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DistanceKey {
+    metric: SimilarityMetric,
+    scope: DistanceScope,
+    lo: u128,
+    hi: u128,
+}
+
+impl DistanceKey {
+    pub fn new(metric: SimilarityMetric, scope: DistanceScope, a: u128, b: u128) -> Self {
+        DistanceKey {
+            metric,
+            scope,
+            lo: a.min(b),
+            hi: a.max(b),
+        }
+    }
+}
+
+/// A cached distance, or only the knowledge that it exceeds a bound when it was
+/// computed by a thresholded comparison
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Entry {
+    Exact(f32),
+    Above(f32),
+}
+
+struct Slot {
+    key: DistanceKey,
+    value: Entry,
+    referenced: bool,
+}
+
+/// Most shards a cache is split into; a lookup only locks the shard of its key
+const MAX_SHARDS: usize = 16;
+/// Fewest entries per shard, so small caches keep (nearly) one global eviction order
+const MIN_SHARD_CAPACITY: usize = 1024;
+
+/// One independently locked part of the cache, with its own CLOCK hand
+struct Shard {
+    capacity: usize,
+    policy: EvictionPolicy,
+    index: HashMap<DistanceKey, usize>,
+    slots: Vec<Slot>,
+    /// Next slot considered for eviction
+    hand: usize,
+    stats: DistanceCacheStats,
+}
+
+impl Shard {
+    fn new(capacity: usize, policy: EvictionPolicy) -> Self {
+        Shard {
+            capacity,
+            policy,
+            index: HashMap::new(),
+            slots: Vec::new(),
+            hand: 0,
+            stats: DistanceCacheStats::default(),
+        }
+    }
+
+    fn insert(&mut self, key: DistanceKey, value: Entry) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(&slot) = self.index.get(&key) {
+            self.slots[slot].value = value;
+            return;
+        }
+
+        self.stats.insertions += 1;
+        let slot = Slot {
+            key,
+            value,
+            referenced: false,
+        };
+        if self.slots.len() < self.capacity {
+            self.index.insert(key, self.slots.len());
+            self.slots.push(slot);
+            return;
+        }
+
+        // Full: advance the hand, giving referenced entries a second chance under LRU
+        let victim = loop {
+            let hand = self.hand;
+            self.hand = (hand + 1) % self.capacity;
+            if self.policy == EvictionPolicy::Lru && self.slots[hand].referenced {
+                self.slots[hand].referenced = false;
+                continue;
+            }
+            break hand;
+        };
+        let evicted = std::mem::replace(&mut self.slots[victim], slot).key;
+        self.index.remove(&evicted);
+        self.index.insert(key, victim);
+        self.stats.evictions += 1;
+    }
+
+    fn clear(&mut self) {
+        self.index.clear();
+        self.slots.clear();
+        self.hand = 0;
+    }
+}
+
+/// Bounded map from [`DistanceKey`] to distance.
+///
+/// Keys depend only on subtree structure, so entries stay valid after the trees they
+/// were computed from are dropped and are shared by identical subtrees across pages.
+/// Large caches are split into up to [`MAX_SHARDS`] shards by key, each evicting on
+/// its own, so parallel comparisons rarely wait on each other.
+///
+/// Entries are addressed by 128-bit structural hashes without comparing signatures;
+/// two different subtrees share an entry only if both 64-bit halves collide.
+pub struct DistanceCache {
+    config: Mutex<DistanceCacheConfig>,
+    /// Shards in use, a power of two
+    active: AtomicUsize,
+    shards: Box<[Mutex<Shard>]>,
+}
+
+impl DistanceCache {
+    pub fn new(config: DistanceCacheConfig) -> Self {
+        let cache = DistanceCache {
+            config: Mutex::new(config),
+            active: AtomicUsize::new(1),
+            shards: (0..MAX_SHARDS)
+                .map(|_| Mutex::new(Shard::new(0, config.policy)))
+                .collect(),
+        };
+        cache.configure(config);
+        cache
+    }
+
+    fn shard(&self, key: &DistanceKey) -> &Mutex<Shard> {
+        let mixed = (key.lo as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15) ^ key.hi as u64;
+        let mask = self.active.load(Ordering::Relaxed) - 1;
+        &self.shards[(mixed >> 32) as usize & mask]
+    }
+
+    /// Exact distance for `key`; entries only known to exceed a bound count as misses
+    pub fn get(&self, key: &DistanceKey) -> Option<f32> {
+        self.lookup(key, |entry| match entry {
+            Entry::Exact(value) => Some(value),
+            Entry::Above(_) => None,
+        })
+    }
+
+    /// Resolves the entry for `key` with `resolve`, counting a hit when it yields a value
+    fn lookup<R>(&self, key: &DistanceKey, resolve: impl FnOnce(Entry) -> Option<R>) -> Option<R> {
+        let mut shard = self.shard(key).lock();
+        let resolved = shard.index.get(key).copied().and_then(|slot| {
+            let resolved = resolve(shard.slots[slot].value)?;
+            shard.slots[slot].referenced = true;
+            Some(resolved)
+        });
+        match resolved {
+            Some(_) => shard.stats.hits += 1,
+            None => shard.stats.misses += 1,
+        }
+        resolved
+    }
+
+    pub fn insert(&self, key: DistanceKey, value: f32) {
+        self.insert_entry(key, Entry::Exact(value));
+    }
+
+    fn insert_entry(&self, key: DistanceKey, value: Entry) {
+        self.shard(&key).lock().insert(key, value);
+    }
+
+    /// Returns the cached distance for `key`, computing and storing it on a miss.
+    /// `compute` runs without holding the lock.
+    pub fn get_or_compute(&self, key: DistanceKey, compute: impl FnOnce() -> f32) -> f32 {
+        if let Some(value) = self.get(&key) {
+            return value;
+        }
+        let value = compute();
+        self.insert(key, value);
+        value
+    }
+
+    /// Thresholded variant of [`DistanceCache::get_or_compute`]: returns the distance
+    /// when it is `<= bound` and `None` otherwise.
+    ///
+    /// `compute` gets the same contract and may stop early once the bound is exceeded;
+    /// such results are cached as lower bounds, which answer later queries with an
+    /// equal or smaller bound and are replaced by exact values once those are known.
+    pub fn get_or_compute_within(
+        &self,
+        key: DistanceKey,
+        bound: f32,
+        compute: impl FnOnce(f32) -> Option<f32>,
+    ) -> Option<f32> {
+        let cached = self.lookup(&key, |entry| match entry {
+            Entry::Exact(value) => Some((value <= bound).then_some(value)),
+            Entry::Above(lower) if lower >= bound => Some(None),
+            Entry::Above(_) => None,
+        });
+        if let Some(result) = cached {
+            return result;
+        }
+        let result = compute(bound);
+        self.insert_entry(key, result.map_or(Entry::Above(bound), Entry::Exact));
+        result
+    }
+
+    /// Drops every entry; statistics are kept
+    pub fn clear(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().clear();
+        }
+    }
+
+    pub fn reset_stats(&self) {
+        for shard in self.shards.iter() {
+            shard.lock().stats = DistanceCacheStats::default();
+        }
+    }
+
+    pub fn stats(&self) -> DistanceCacheStats {
+        let mut total = DistanceCacheStats {
+            capacity: self.config.lock().capacity,
+            ..DistanceCacheStats::default()
+        };
+        for shard in self.shards.iter() {
+            let shard = shard.lock();
+            total.hits += shard.stats.hits;
+            total.misses += shard.stats.misses;
+            total.insertions += shard.stats.insertions;
+            total.evictions += shard.stats.evictions;
+            total.entries += shard.slots.len();
+        }
+        total
+    }
+
+    pub fn config(&self) -> DistanceCacheConfig {
+        *self.config.lock()
+    }
+
+    /// Applies a new capacity/policy, dropping all entries
+    pub fn configure(&self, config: DistanceCacheConfig) {
+        let mut current = self.config.lock();
+        // Hold every shard so no lookup sees a half-applied layout
+        let mut shards: Vec<_> = self.shards.iter().map(|shard| shard.lock()).collect();
+        let wanted = (config.capacity / MIN_SHARD_CAPACITY).clamp(1, MAX_SHARDS);
+        let active = 1 << wanted.ilog2();
+        for (i, shard) in shards.iter_mut().enumerate() {
+            shard.clear();
+            shard.policy = config.policy;
+            shard.capacity = if i < active {
+                config.capacity / active + usize::from(i < config.capacity % active)
+            } else {
+                0
+            };
+        }
+        self.active.store(active, Ordering::Relaxed);
+        *current = config;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(a: u128, b: u128) -> DistanceKey {
+        DistanceKey::new(
+            SimilarityMetric::StringEditDistance,
+            DistanceScope::Node,
+            a,
+            b,
+        )
+    }
+
+    #[test]
+    fn lru_gives_recently_used_entries_a_second_chance() {
+        let cache = DistanceCache::new(DistanceCacheConfig {
+            capacity: 2,
+            policy: EvictionPolicy::Lru,
+        });
+        cache.insert(key(1, 2), 0.1);
+        cache.insert(key(3, 4), 0.2);
+        assert_eq!(cache.get(&key(2, 1)), Some(0.1)); // symmetric, and marks it used
+        cache.insert(key(5, 6), 0.3);
+
+        assert_eq!(cache.get(&key(1, 2)), Some(0.1));
+        assert_eq!(cache.get(&key(3, 4)), None);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses, stats.evictions), (2, 1, 1));
+        assert_eq!(stats.entries, 2);
+    }
+
+    #[test]
+    fn fifo_and_zero_capacity() {
+        let cache = DistanceCache::new(DistanceCacheConfig {
+            capacity: 2,
+            policy: EvictionPolicy::Fifo,
+        });
+        cache.insert(key(1, 2), 0.1);
+        cache.insert(key(3, 4), 0.2);
+        cache.get(&key(1, 2));
+        cache.insert(key(5, 6), 0.3);
+        assert_eq!(cache.get(&key(1, 2)), None);
+
+        cache.configure(DistanceCacheConfig {
+            capacity: 0,
+            policy: EvictionPolicy::Lru,
+        });
+        assert_eq!(cache.get_or_compute(key(1, 2), || 0.5), 0.5);
+        assert_eq!(cache.stats().entries, 0);
+    }
+
+    #[test]
+    fn large_caches_are_sharded_without_losing_capacity() {
+        let cache = DistanceCache::new(DistanceCacheConfig {
+            capacity: 10_000,
+            policy: EvictionPolicy::Lru,
+        });
+        assert_eq!(cache.active.load(Ordering::Relaxed), 8);
+        let capacities: usize = cache.shards.iter().map(|shard| shard.lock().capacity).sum();
+        assert_eq!(capacities, 10_000);
+
+        for i in 0..1000u128 {
+            cache.insert(key(i, i << 64 | 7), i as f32);
+        }
+        assert!((0..1000u128).all(|i| cache.get(&key(i << 64 | 7, i)) == Some(i as f32)));
+        let stats = cache.stats();
+        assert_eq!(
+            (stats.entries, stats.hits, stats.evictions),
+            (1000, 1000, 0)
+        );
+        let used = cache
+            .shards
+            .iter()
+            .filter(|shard| !shard.lock().slots.is_empty());
+        assert!(used.count() > 1);
+    }
+}
@@ -0,0 +1,144 @@
+use serde::Serialize;
+use std::fmt;
+
+/// Errors surfaced by the MDR entry points.
+///
+/// Serializes as `{ code: "INVALID_PARAMETER", ...details }`; the same values are
+/// reported as non-fatal `warnings` in [`crate::types::MdrFullOutput`].
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(
+    tag = "code",
+    rename_all = "SCREAMING_SNAKE_CASE",
+    rename_all_fields = "camelCase"
+)]
+pub enum MdrError {
+    /// Input could not be deserialized (`target` names the argument, e.g. `root node`)
+    Deserialize { target: String, message: String },
+    /// Output could not be serialized
+    Serialize { target: String, message: String },
+    /// A parameter is out of range, e.g. `k == 0` or `t` outside `[0, 1]`
+    InvalidParameter {
+        name: String,
+        value: String,
+        reason: String,
+    },
+    /// A region references a parent xpath that does not exist in the tree
+    MissingXpath { xpath: String },
+    /// A configured resource limit was hit
+    LimitExceeded {
+        limit: String,
+        max: usize,
+        actual: usize,
+    },
+    /// The run was stopped through its cancellation token
+    Cancelled,
+}
+
+impl MdrError {
+    /// Stable machine-readable code, identical to the serialized `code` field
+    pub fn code(&self) -> &'static str {
+        match self {
+            MdrError::Deserialize { .. } => "DESERIALIZE",
+            MdrError::Serialize { .. } => "SERIALIZE",
+            MdrError::InvalidParameter { .. } => "INVALID_PARAMETER",
+            MdrError::MissingXpath { .. } => "MISSING_XPATH",
+            MdrError::LimitExceeded { .. } => "LIMIT_EXCEEDED",
+            MdrError::Cancelled => "CANCELLED",
+        }
+    }
+
+    pub fn deserialization(target: &str, error: impl fmt::Display) -> Self {
+        MdrError::Deserialize {
+            target: target.to_string(),
+            message: error.to_string(),
+        }
+    }
+
+    pub fn serialization(target: &str, error: impl fmt::Display) -> Self {
+        MdrError::Serialize {
+            target: target.to_string(),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for MdrError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MdrError::Deserialize { target, message } => {
+                write!(f, "Failed to deserialize {}: {}", target, message)
+            }
+            MdrError::Serialize { target, message } => {
+                write!(f, "Failed to serialize {}: {}", target, message)
+            }
+            MdrError::InvalidParameter {
+                name,
+                value,
+                reason,
+            } => write!(f, "Invalid parameter {} = {}: {}", name, value, reason),
+            MdrError::MissingXpath { xpath } => {
+                write!(f, "Region parent xpath not found in tree: {}", xpath)
+            }
+            MdrError::LimitExceeded { limit, max, actual } => {
+                write!(f, "Limit {} exceeded: {} > {}", limit, actual, max)
+            }
+            MdrError::Cancelled => write!(f, "MDR run cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for MdrError {}
+
+/// Validates the MDR parameters: `k >= 1` and `t` within `[0, 1]`
+pub fn validate_parameters(k: usize, t: f32) -> Result<(), MdrError> {
+    if k == 0 {
+        return Err(MdrError::InvalidParameter {
+            name: "k".to_string(),
+            value: k.to_string(),
+            reason: "must be at least 1".to_string(),
+        });
+    }
+    validate_threshold(t)
+}
+
+/// Validates the similarity threshold alone, for entry points that take no `k`
+pub fn validate_threshold(t: f32) -> Result<(), MdrError> {
+    if !(0.0..=1.0).contains(&t) {
+        return Err(MdrError::InvalidParameter {
+            name: "t".to_string(),
+            value: t.to_string(),
+            reason: "must be within [0, 1]".to_string(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameters_are_validated_and_serialized_with_code() {
+        assert!(validate_parameters(10, 0.3).is_ok());
+        assert!(validate_parameters(1, 1.0).is_ok());
+        assert!(validate_threshold(f32::NAN).is_err());
+
+        let error = validate_parameters(0, 0.3).unwrap_err();
+        assert_eq!(error.code(), "INVALID_PARAMETER");
+        assert_eq!(
+            serde_json::to_value(&error).unwrap(),
+            serde_json::json!({
+                "code": "INVALID_PARAMETER",
+                "name": "k",
+                "value": "0",
+                "reason": "must be at least 1",
+            })
+        );
+
+        let error = validate_parameters(10, 1.5).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Invalid parameter t = 1.5: must be within [0, 1]"
+        );
+    }
+}
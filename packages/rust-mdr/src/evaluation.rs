@@ -0,0 +1,230 @@
+use crate::types::DataRecord;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Precision/recall/F1 of predicted records against ground truth (NEXT-EVAL metric)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct EvaluationResult {
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    pub total_overlap: f64,
+    pub matches: usize,
+}
+
+impl EvaluationResult {
+    fn uniform(score: f64) -> Self {
+        EvaluationResult {
+            precision: score,
+            recall: score,
+            f1: score,
+            total_overlap: 0.0,
+            matches: 0,
+        }
+    }
+}
+
+/// Jaccard similarity between two records (sets of xpaths); 1 when both are empty
+pub fn calculate_overlap<S: AsRef<str>>(record1: &[S], record2: &[S]) -> f64 {
+    let set1: HashSet<&str> = record1.iter().map(AsRef::as_ref).collect();
+    let set2: HashSet<&str> = record2.iter().map(AsRef::as_ref).collect();
+
+    let intersection = set1.intersection(&set2).count();
+    let union = set1.union(&set2).count();
+
+    if union == 0 {
+        return 1.0; // Define overlap as 1 if both sets are empty
+    }
+
+    intersection as f64 / union as f64
+}
+
+/// Matches predicted and ground-truth records one-to-one (Hungarian algorithm on
+/// `1 - overlap`) and derives precision, recall and F1 from the summed overlap.
+/// Mirrors `calculateEvaluationMetrics` in `@wordbricks/next-eval`.
+pub fn calculate_evaluation_metrics<S: AsRef<str>>(
+    predicted_records: &[Vec<S>],
+    ground_truth_records: &[Vec<S>],
+) -> EvaluationResult {
+    let m = predicted_records.len();
+    let n = ground_truth_records.len();
+
+    // Handle edge cases: no records predicted or no ground truth
+    if m == 0 && n == 0 {
+        return EvaluationResult::uniform(1.0);
+    }
+    if m == 0 || n == 0 {
+        return EvaluationResult::uniform(0.0);
+    }
+
+    let overlaps: Vec<Vec<f64>> = predicted_records
+        .iter()
+        .map(|predicted| {
+            ground_truth_records
+                .iter()
+                .map(|truth| calculate_overlap(predicted, truth))
+                .collect()
+        })
+        .collect();
+    let cost: Vec<Vec<f64>> = overlaps
+        .iter()
+        .map(|row| row.iter().map(|overlap| 1.0 - overlap).collect())
+        .collect();
+
+    let assignment = hungarian(&cost);
+    let total_overlap: f64 = assignment.iter().map(|&(i, j)| overlaps[i][j]).sum();
+    let matches = assignment.len();
+
+    let precision = total_overlap / m as f64;
+    let recall = total_overlap / n as f64;
+    let f1 = if precision + recall > 0.0 {
+        2.0 * precision * recall / (precision + recall)
+    } else {
+        0.0
+    };
+
+    EvaluationResult {
+        precision,
+        recall,
+        f1,
+        total_overlap,
+        matches,
+    }
+}
+
+/// Minimum-cost assignment for a rectangular cost matrix, returning
+/// `min(rows, cols)` pairs of `(row, col)` sorted by row.
+pub fn hungarian(cost: &[Vec<f64>]) -> Vec<(usize, usize)> {
+    let rows = cost.len();
+    let cols = cost.first().map_or(0, Vec::len);
+    if rows == 0 || cols == 0 {
+        return Vec::new();
+    }
+
+    // The potentials formulation below needs rows <= cols
+    if rows > cols {
+        let transposed: Vec<Vec<f64>> = (0..cols)
+            .map(|j| (0..rows).map(|i| cost[i][j]).collect())
+            .collect();
+        let mut pairs: Vec<(usize, usize)> = hungarian(&transposed)
+            .into_iter()
+            .map(|(j, i)| (i, j))
+            .collect();
+        pairs.sort_unstable();
+        return pairs;
+    }
+
+    // 1-indexed potentials; p[j] is the row assigned to column j (0 = none)
+    let (n, m) = (rows, cols);
+    let mut u = vec![0.0f64; n + 1];
+    let mut v = vec![0.0f64; m + 1];
+    let mut p = vec![0usize; m + 1];
+    let mut way = vec![0usize; m + 1];
+
+    for i in 1..=n {
+        p[0] = i;
+        let mut j0 = 0;
+        let mut minv = vec![f64::INFINITY; m + 1];
+        let mut used = vec![false; m + 1];
+
+        loop {
+            used[j0] = true;
+            let i0 = p[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+
+            for j in 1..=m {
+                if used[j] {
+                    continue;
+                }
+                let cur = cost[i0 - 1][j - 1] - u[i0] - v[j];
+                if cur < minv[j] {
+                    minv[j] = cur;
+                    way[j] = j0;
+                }
+                if minv[j] < delta {
+                    delta = minv[j];
+                    j1 = j;
+                }
+            }
+
+            for j in 0..=m {
+                if used[j] {
+                    u[p[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    minv[j] -= delta;
+                }
+            }
+
+            j0 = j1;
+            if p[j0] == 0 {
+                break;
+            }
+        }
+
+        // Augment along the alternating path
+        loop {
+            let j1 = way[j0];
+            p[j0] = p[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut pairs: Vec<(usize, usize)> = (1..=m)
+        .filter(|&j| p[j] != 0)
+        .map(|j| (p[j] - 1, j - 1))
+        .collect();
+    pairs.sort_unstable();
+    pairs
+}
+
+/// Xpaths of each record, as consumed by [`calculate_evaluation_metrics`]
+pub fn record_xpaths(records: &[DataRecord]) -> Vec<Vec<String>> {
+    records
+        .iter()
+        .map(|record| match record {
+            DataRecord::Single(node) => vec![node.xpath.clone()],
+            DataRecord::Multi(nodes) => nodes.iter().map(|node| node.xpath.clone()).collect(),
+        })
+        .filter(|xpaths| !xpaths.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn records(raw: &[&[&str]]) -> Vec<Vec<String>> {
+        raw.iter()
+            .map(|r| r.iter().map(|x| x.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn evaluation_edge_cases() {
+        let empty: Vec<Vec<String>> = Vec::new();
+        let some = records(&[&["/a"]]);
+        assert_eq!(calculate_evaluation_metrics(&empty, &empty).f1, 1.0);
+        assert_eq!(calculate_evaluation_metrics(&empty, &some).f1, 0.0);
+        assert_eq!(calculate_evaluation_metrics(&some, &empty).f1, 0.0);
+        assert_eq!(calculate_overlap::<&str>(&[], &[]), 1.0);
+    }
+
+    #[test]
+    fn evaluation_uses_optimal_assignment() {
+        let predicted = records(&[&["/a", "/b"], &["/c"], &["/x"]]);
+        let truth = records(&[&["/c"], &["/a", "/b", "/d"]]);
+
+        let result = calculate_evaluation_metrics(&predicted, &truth);
+        let expected_overlap = 1.0 + 2.0 / 3.0;
+        assert_eq!(result.matches, 2);
+        assert!((result.total_overlap - expected_overlap).abs() < 1e-9);
+        assert!((result.precision - expected_overlap / 3.0).abs() < 1e-9);
+        assert!((result.recall - expected_overlap / 2.0).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,187 @@
+use crate::types::{TagNode, TagNodeRef};
+use html5ever::parse_document;
+use html5ever::tendril::TendrilSink;
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+use std::sync::Arc;
+
+/// Parses an HTML document and builds the tag tree rooted at its document element.
+///
+/// Mirrors `buildTagTree` from `@wordbricks/next-eval` so the resulting xpaths are
+/// interchangeable with the ones produced by the TypeScript pipeline.
+pub fn parse_html(html: &str) -> TagNodeRef {
+    let dom = parse_document(RcDom::default(), Default::default()).one(html);
+
+    match document_element(&dom) {
+        Some(element) => build_tag_tree(&element, "", 1),
+        None => Arc::new(empty_text_node()),
+    }
+}
+
+/// The `<html>` element of a parsed document
+pub(crate) fn document_element(dom: &RcDom) -> Option<Handle> {
+    dom.document
+        .children
+        .borrow()
+        .iter()
+        .find(|child| matches!(child.data, NodeData::Element { .. }))
+        .cloned()
+}
+
+/// The tag tree the MDR pipeline runs on: comments, scripts and styles are stripped
+/// before parsing, as `runMDR.ts` does ahead of `buildTagTree`.
+pub fn parse_page_html(html: &str) -> TagNodeRef {
+    parse_html(&remove_comment_script_style(html))
+}
+
+/// Strips comments, `<script>` and `<style>` blocks from raw HTML.
+///
+/// String-level port of `removeCommentScriptStyleFromHTML`, applied before tree
+/// building in the MDR pipeline.
+pub fn remove_comment_script_style(html: &str) -> String {
+    let no_comments = remove_between(html, "<!--", "-->", false);
+    let no_scripts = remove_between(&no_comments, "<script", "</script>", true);
+    remove_between(&no_scripts, "<style", "</style>", true)
+}
+
+/// Removes every `open ... close` span (shortest match, unterminated spans are kept)
+fn remove_between(input: &str, open: &str, close: &str, ignore_case: bool) -> String {
+    // ASCII lowercasing keeps byte offsets aligned with `input`
+    let haystack = if ignore_case {
+        input.to_ascii_lowercase()
+    } else {
+        input.to_string()
+    };
+
+    let mut out = String::with_capacity(input.len());
+    let mut pos = 0;
+    while let Some(start) = haystack[pos..].find(open).map(|i| pos + i) {
+        let Some(end) = haystack[start + open.len()..]
+            .find(close)
+            .map(|i| start + open.len() + i + close.len())
+        else {
+            break;
+        };
+        out.push_str(&input[pos..start]);
+        pos = end;
+    }
+    out.push_str(&input[pos..]);
+    out
+}
+
+/// Builds a `TagNode` for `handle`, which is the `index`-th child of its parent
+/// sharing the same tag name.
+fn build_tag_tree(handle: &Handle, parent_xpath: &str, index: usize) -> TagNodeRef {
+    match &handle.data {
+        NodeData::Text { contents } => {
+            let text = contents.borrow().trim().to_string();
+            // Text nodes inherit the xpath of their parent element
+            let xpath = if !text.is_empty() && !parent_xpath.is_empty() {
+                parent_xpath.to_string()
+            } else {
+                String::new()
+            };
+            let mut node = TagNode::new("text".to_string(), xpath);
+            node.set_raw_text(text);
+            Arc::new(node)
+        }
+        NodeData::Element { name, attrs, .. } => {
+            let tag_name = name.local.to_ascii_lowercase().to_string();
+            let xpath = format!("{}/{}[{}]", parent_xpath, tag_name, index);
+
+            let mut node = TagNode::new(tag_name, xpath);
+            node.set_raw_text(String::new());
+            for attr in attrs.borrow().iter() {
+                node.set_attribute(attr.name.local.to_string(), attr.value.to_string());
+            }
+
+            // Per-tag sibling counters, matching `tag[n]` numbering in buildTagTree
+            let mut tag_counts: Vec<(&str, usize)> = Vec::new();
+            let children = handle.children.borrow();
+            for child in children.iter() {
+                let child_index = match &child.data {
+                    NodeData::Text { contents } => {
+                        if contents.borrow().trim().is_empty() {
+                            continue; // Skip effectively empty text nodes
+                        }
+                        1
+                    }
+                    NodeData::Element { name, .. } => {
+                        let local: &str = &name.local;
+                        match tag_counts.iter_mut().find(|(tag, _)| *tag == local) {
+                            Some((_, count)) => {
+                                *count += 1;
+                                *count
+                            }
+                            None => {
+                                tag_counts.push((local, 1));
+                                1
+                            }
+                        }
+                    }
+                    _ => 1,
+                };
+                node.add_child(build_tag_tree(child, &node.xpath, child_index));
+            }
+
+            Arc::new(node)
+        }
+        // Comments and other node types fall back to an empty text node
+        _ => Arc::new(empty_text_node()),
+    }
+}
+
+fn empty_text_node() -> TagNode {
+    let mut node = TagNode::new("text".to_string(), String::new());
+    node.set_raw_text(String::new());
+    node
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_html_numbers_same_tag_siblings() {
+        let root = parse_html("<ul><li>a</li><li> </li><li>c</li></ul><p>x</p>");
+        assert_eq!(root.xpath, "/html[1]");
+
+        let body = &root.children[1];
+        assert_eq!(body.xpath, "/html[1]/body[1]");
+
+        let ul = &body.children[0];
+        let xpaths: Vec<&str> = ul.children.iter().map(|c| c.xpath.as_str()).collect();
+        assert_eq!(
+            xpaths,
+            vec![
+                "/html[1]/body[1]/ul[1]/li[1]",
+                "/html[1]/body[1]/ul[1]/li[2]",
+                "/html[1]/body[1]/ul[1]/li[3]",
+            ]
+        );
+
+        // Whitespace-only text is skipped, text nodes inherit the parent xpath
+        assert!(ul.children[1].children.is_empty());
+        let text = &ul.children[2].children[0];
+        assert_eq!(text.tag_name, "text");
+        assert_eq!(text.raw_text.as_deref(), Some("c"));
+        assert_eq!(text.xpath, "/html[1]/body[1]/ul[1]/li[3]");
+
+        assert_eq!(body.children[1].xpath, "/html[1]/body[1]/p[1]");
+    }
+
+    #[test]
+    fn remove_comment_script_style_strips_blocks() {
+        let html = "<p>a<!-- x --></p><SCRIPT>1</script><style>b{}</STYLE><p>c</p>";
+        assert_eq!(remove_comment_script_style(html), "<p>a</p><p>c</p>");
+        assert_eq!(remove_comment_script_style("<!-- open"), "<!-- open");
+    }
+
+    #[test]
+    fn parse_html_keeps_attributes() {
+        let root = parse_html(r#"<a class="item" href="/x">x</a>"#);
+        let link = &root.children[1].children[0];
+        assert_eq!(link.get_attribute("class"), Some("item"));
+        assert_eq!(link.get_attribute("href"), Some("/x"));
+        assert!(link.children[0].attributes.is_none());
+    }
+}
@@ -1,9 +1,30 @@
-mod mdr_algorithm;
-mod record_extraction;
-mod similarity;
-mod tree_utils;
-mod types;
+pub mod arena;
+pub mod chunking;
+pub mod context_reduction;
+pub mod distance_cache;
+pub mod error;
+pub mod evaluation;
+pub mod html_parser;
+pub mod limits;
+pub mod llm_response;
+pub mod mdr_algorithm;
+#[cfg(feature = "python")]
+mod python_bindings;
+pub mod record_extraction;
+pub mod session;
+pub mod similarity;
+pub mod slim_html;
+pub mod sweep;
+pub mod table_export;
+pub mod text_map;
+pub mod trace;
+pub mod tree_alignment;
+pub mod tree_distance;
+pub mod tree_utils;
+pub mod types;
 mod wasm_bindings;
+pub mod wrapper;
+pub mod xpath_index;
 
 // Re-export public functions from wasm_bindings
 pub use wasm_bindings::*;
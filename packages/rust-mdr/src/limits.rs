@@ -0,0 +1,288 @@
+use crate::error::MdrError;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Upper bounds on one region search; `None` means unlimited
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MdrLimits {
+    /// Nodes the region search visits
+    pub max_nodes: Option<usize>,
+    /// Depth below the root (depth 0) the region search descends to; deeper subtrees
+    /// are skipped while the rest of the tree is still searched
+    pub max_depth: Option<usize>,
+    /// Distance requests made during the run
+    pub max_comparisons: Option<usize>,
+    /// Wall-clock budget in milliseconds, measured with the context's [`Clock`]
+    pub time_budget_ms: Option<usize>,
+}
+
+/// Millisecond time source for the wall-clock budget; inject a fake in tests
+pub trait Clock: Send + Sync {
+    fn now_ms(&self) -> u64;
+}
+
+/// `Instant` natively, `Date.now()` in the browser
+pub struct SystemClock;
+
+#[cfg(not(target_arch = "wasm32"))]
+static ORIGIN: once_cell::sync::Lazy<std::time::Instant> =
+    once_cell::sync::Lazy::new(std::time::Instant::now);
+
+impl Clock for SystemClock {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now_ms(&self) -> u64 {
+        ORIGIN.elapsed().as_millis() as u64
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn now_ms(&self) -> u64 {
+        js_sys::Date::now() as u64
+    }
+}
+
+/// Shared flag that stops a run from another thread; clones observe the same flag
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+const NOT_STARTED: u64 = u64::MAX;
+
+/// Limit and cancellation bookkeeping of an `MdrContext`, reset at the start of
+/// every region search
+pub(crate) struct RunBudget {
+    pub(crate) limits: MdrLimits,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) cancellation: Option<CancellationToken>,
+    started_ms: AtomicU64,
+    comparisons_at_start: AtomicU64,
+    nodes: AtomicUsize,
+    stopped: AtomicBool,
+    /// First reason the result is incomplete
+    truncation: Mutex<Option<MdrError>>,
+}
+
+impl Default for RunBudget {
+    fn default() -> Self {
+        RunBudget {
+            limits: MdrLimits::default(),
+            clock: Arc::new(SystemClock),
+            cancellation: None,
+            started_ms: AtomicU64::new(NOT_STARTED),
+            comparisons_at_start: AtomicU64::new(0),
+            nodes: AtomicUsize::new(0),
+            stopped: AtomicBool::new(false),
+            truncation: Mutex::new(None),
+        }
+    }
+}
+
+impl RunBudget {
+    pub(crate) fn begin(&self, comparisons: u64) {
+        self.started_ms.store(NOT_STARTED, Ordering::Relaxed);
+        self.comparisons_at_start
+            .store(comparisons, Ordering::Relaxed);
+        self.nodes.store(0, Ordering::Relaxed);
+        self.stopped.store(false, Ordering::Relaxed);
+        *self.truncation.lock() = None;
+    }
+
+    pub(crate) fn truncation(&self) -> Option<MdrError> {
+        self.truncation.lock().clone()
+    }
+
+    fn truncate(&self, reason: MdrError, stop: bool) {
+        if stop {
+            self.stopped.store(true, Ordering::Relaxed);
+        }
+        self.truncation.lock().get_or_insert(reason);
+    }
+
+    /// Whether the run must stop before making comparison number `comparisons + 1`
+    pub(crate) fn should_stop(&self, comparisons: u64) -> bool {
+        if self.stopped.load(Ordering::Relaxed) {
+            return true;
+        }
+        if self
+            .cancellation
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            self.truncate(MdrError::Cancelled, true);
+            return true;
+        }
+        if let Some(max) = self.limits.max_comparisons {
+            let next =
+                (comparisons - self.comparisons_at_start.load(Ordering::Relaxed)) as usize + 1;
+            if next > max {
+                self.truncate(limit_exceeded("maxComparisons", max, next), true);
+                return true;
+            }
+        }
+        if let Some(max) = self.limits.time_budget_ms {
+            let now = self.clock.now_ms();
+            let started = match self.started_ms.compare_exchange(
+                NOT_STARTED,
+                now,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => now,
+                Err(started) => started,
+            };
+            let elapsed = now.saturating_sub(started) as usize;
+            if elapsed > max {
+                self.truncate(limit_exceeded("timeBudgetMs", max, elapsed), true);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Whether the region search may visit a node at `depth`
+    pub(crate) fn enter_node(&self, depth: usize, comparisons: u64) -> bool {
+        if self.should_stop(comparisons) {
+            return false;
+        }
+        if let Some(max) = self.limits.max_depth {
+            if depth > max {
+                self.truncate(limit_exceeded("maxDepth", max, depth), false);
+                return false;
+            }
+        }
+        let visited = self.nodes.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some(max) = self.limits.max_nodes {
+            if visited > max {
+                self.truncate(limit_exceeded("maxNodes", max, visited), true);
+                return false;
+            }
+        }
+        true
+    }
+}
+
+fn limit_exceeded(limit: &str, max: usize, actual: usize) -> MdrError {
+    MdrError::LimitExceeded {
+        limit: limit.to_string(),
+        max,
+        actual,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_parser::parse_html;
+    use crate::mdr_algorithm::{find_drs_recursive, run_mdr_algorithm_with_context};
+    use crate::session::{run_mdr_pipeline, MdrContext};
+    use crate::types::MdrOptions;
+    use indexmap::IndexMap;
+
+    const PAGE: &str = "<html><body><ul>\
+        <li><a>One</a><span>1</span></li>\
+        <li><a>Two</a><span>2</span></li>\
+        <li><a>Three</a><span>3</span></li>\
+        </ul><div><p><b>x</b></p><p><b>y</b></p></div></body></html>";
+
+    /// Advances by `step` milliseconds on every reading
+    struct SteppingClock {
+        now: AtomicU64,
+        step: u64,
+    }
+
+    impl Clock for SteppingClock {
+        fn now_ms(&self) -> u64 {
+            self.now.fetch_add(self.step, Ordering::Relaxed)
+        }
+    }
+
+    #[test]
+    fn limits_and_cancellation_return_truncated_partial_results() {
+        let root = parse_html(PAGE);
+
+        let full = run_mdr_pipeline(&root, &MdrContext::new(10, 0.3, MdrOptions::default()));
+        assert!(!full.truncated && full.warnings.is_empty());
+        assert!(!full.regions.is_empty());
+
+        let context = MdrContext::new(10, 0.3, MdrOptions::default()).with_limits(MdrLimits {
+            max_comparisons: Some(1),
+            ..MdrLimits::default()
+        });
+        let partial = run_mdr_pipeline(&root, &context);
+        assert!(partial.truncated);
+        assert_eq!(partial.warnings[0], limit_exceeded("maxComparisons", 1, 2));
+
+        let clock = SteppingClock {
+            now: AtomicU64::new(0),
+            step: 10,
+        };
+        let context = MdrContext::new(10, 0.3, MdrOptions::default())
+            .with_limits(MdrLimits {
+                time_budget_ms: Some(25),
+                ..MdrLimits::default()
+            })
+            .with_clock(Arc::new(clock));
+        let output = run_mdr_pipeline(&root, &context);
+        assert!(output.truncated);
+        assert_eq!(output.warnings[0].code(), "LIMIT_EXCEEDED");
+
+        let context = MdrContext::new(10, 0.3, MdrOptions::default()).with_limits(MdrLimits {
+            max_depth: Some(1),
+            ..MdrLimits::default()
+        });
+        let shallow = run_mdr_pipeline(&root, &context);
+        assert!(shallow.truncated && shallow.regions.len() < full.regions.len());
+
+        // The budget is per run: a limit one run just fits does not stop the next one,
+        // and a direct region search after a truncated run starts over too
+        let counting = MdrContext::new(10, 0.3, MdrOptions::default());
+        run_mdr_algorithm_with_context(&root, &counting);
+        let per_run = counting.comparisons() as usize;
+        let context = MdrContext::new(10, 0.3, MdrOptions::default()).with_limits(MdrLimits {
+            max_comparisons: Some(per_run + 1),
+            ..MdrLimits::default()
+        });
+        assert!(!run_mdr_pipeline(&root, &context).truncated);
+        let again = run_mdr_algorithm_with_context(&root, &context);
+        assert_eq!(again.len(), full.regions.len());
+        assert!(context.truncation().is_none());
+
+        let list = &root.children[1].children[0];
+        let mut regions = IndexMap::new();
+        find_drs_recursive(list, 2, &counting, &mut regions);
+        let list_run = counting.comparisons() as usize - per_run;
+        let context = MdrContext::new(10, 0.3, MdrOptions::default()).with_limits(MdrLimits {
+            max_comparisons: Some(list_run + 1),
+            ..MdrLimits::default()
+        });
+        assert!(run_mdr_pipeline(&root, &context).truncated);
+        let mut regions = IndexMap::new();
+        find_drs_recursive(list, 2, &context, &mut regions);
+        assert!(context.truncation().is_none());
+        assert_eq!(regions[&list.xpath], vec![(1, 0, 3)]);
+
+        let token = CancellationToken::new();
+        let context =
+            MdrContext::new(10, 0.3, MdrOptions::default()).with_cancellation(token.clone());
+        assert!(!run_mdr_pipeline(&root, &context).truncated);
+        token.cancel();
+        let cancelled = run_mdr_pipeline(&root, &context);
+        assert!(cancelled.truncated && cancelled.regions.is_empty());
+        assert_eq!(cancelled.warnings, vec![MdrError::Cancelled]);
+    }
+}
@@ -0,0 +1,448 @@
+use crate::error::MdrError;
+use crate::text_map::{for_each_text, llm_xpath};
+use crate::types::{TagNode, TagNodeRef};
+use crate::xpath_index::XpathIndex;
+use indexmap::IndexSet;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Port of `parseAndValidateXPaths`: the record xpath lists in an LLM reply.
+///
+/// Accepts the JSON bare or in a ```` ```json ```` / ```` ``` ```` fence, trims every
+/// xpath and rejects empty ones; a flat list of strings is read as one xpath per
+/// record.
+pub fn parse_llm_response(content: &str) -> Result<Vec<Vec<String>>, MdrError> {
+    let mut cleaned = content.trim();
+    if let Some(inner) = cleaned
+        .strip_prefix("```json")
+        .and_then(|rest| rest.strip_suffix("```"))
+    {
+        cleaned = inner.trim();
+    } else if let Some(inner) = cleaned
+        .strip_prefix("```")
+        .and_then(|rest| rest.strip_suffix("```"))
+    {
+        cleaned = inner.trim();
+    }
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Response {
+        Records(Vec<Vec<String>>),
+        Flat(Vec<String>),
+    }
+
+    let records = match serde_json::from_str(cleaned)
+        .map_err(|e| MdrError::deserialization("LLM response", e))?
+    {
+        Response::Records(records) => records,
+        Response::Flat(xpaths) => xpaths.into_iter().map(|xpath| vec![xpath]).collect(),
+    };
+    records
+        .into_iter()
+        .map(|record| {
+            record
+                .into_iter()
+                .map(|xpath| match xpath.trim() {
+                    "" => Err(MdrError::deserialization(
+                        "LLM response",
+                        "xpaths must not be empty",
+                    )),
+                    trimmed => Ok(trimmed.to_string()),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The xpath rewrite of `mapResponseToFullXpath`: drops a leading `/html[1]` or
+/// `/html` and appends `[1]` to every step without an index, so
+/// `/html/body/div[2]/p` becomes `/body[1]/div[2]/p[1]`. `id('...')` steps are left
+/// as they are.
+pub fn normalize_llm_xpath(xpath: &str) -> String {
+    let path = xpath
+        .strip_prefix("/html[1]")
+        .or_else(|| xpath.strip_prefix("/html"))
+        .unwrap_or(xpath);
+    path.split('/')
+        .map(|step| {
+            if step.is_empty() || id_step(step).is_some() || has_index(step) {
+                step.to_string()
+            } else {
+                format!("{}[1]", step)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// `/\[\d+\]$/`
+fn has_index(step: &str) -> bool {
+    let Some((_, digits)) = step
+        .strip_suffix(']')
+        .and_then(|rest| rest.rsplit_once('['))
+    else {
+        return false;
+    };
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// The `x` of an `id('x')` step
+fn id_step(step: &str) -> Option<&str> {
+    step.strip_prefix("id('")?.strip_suffix("')")
+}
+
+/// `name[n]`, the only step shape `generateXPath` emits besides `id('...')`
+fn is_element_step(step: &str) -> bool {
+    let Some((name, _)) = step.split_once('[') else {
+        return false;
+    };
+    has_index(step)
+        && name.starts_with(|c: char| c.is_ascii_alphabetic())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+}
+
+/// Prefix tree of `/`-separated xpaths, each path holding the keys inserted at it.
+///
+/// [`expand`] returns every key at or below a path in insertion order, the result of
+/// testing each key with `startsWith`, but matching whole steps so `div[1]` does not
+/// pick up `div[10]`.
+///
+/// [`expand`]: XpathTrie::expand
+#[derive(Debug, Clone)]
+pub struct XpathTrie {
+    nodes: Vec<TrieNode>,
+    keys: IndexSet<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct TrieNode {
+    children: HashMap<String, usize>,
+    keys: Vec<usize>,
+}
+
+impl Default for XpathTrie {
+    fn default() -> Self {
+        XpathTrie {
+            nodes: vec![TrieNode::default()],
+            keys: IndexSet::new(),
+        }
+    }
+}
+
+impl XpathTrie {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores `key` at `path`; a key keeps the position of its first insertion
+    pub fn insert(&mut self, path: &str, key: &str) {
+        let (key_index, _) = self.keys.insert_full(key.to_string());
+        let mut node = 0;
+        for step in path.split('/') {
+            node = match self.nodes[node].children.get(step) {
+                Some(&child) => child,
+                None => {
+                    self.nodes.push(TrieNode::default());
+                    let child = self.nodes.len() - 1;
+                    self.nodes[node].children.insert(step.to_string(), child);
+                    child
+                }
+            };
+        }
+        if !self.nodes[node].keys.contains(&key_index) {
+            self.nodes[node].keys.push(key_index);
+        }
+    }
+
+    /// Keys stored at `path` or below it, in insertion order
+    pub fn expand(&self, path: &str) -> Vec<&str> {
+        let mut node = 0;
+        for step in path.split('/') {
+            match self.nodes[node].children.get(step) {
+                Some(&child) => node = child,
+                None => return Vec::new(),
+            }
+        }
+
+        let mut found = Vec::new();
+        let mut stack = vec![node];
+        while let Some(node) = stack.pop() {
+            found.extend_from_slice(&self.nodes[node].keys);
+            stack.extend(self.nodes[node].children.values());
+        }
+        found.sort_unstable();
+        found.dedup();
+        found
+            .into_iter()
+            .map(|index| self.keys[index].as_str())
+            .collect()
+    }
+
+    /// Number of distinct keys
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+/// Why a predicted xpath contributed no text
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum InvalidXpathReason {
+    /// Not an absolute `name[n]` path or `id('...')` reference
+    Malformed,
+    /// Well-formed, but no such element exists (a hallucinated path)
+    NotInTree,
+    /// The element exists but has no text in the text map
+    NoText,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct InvalidXpath {
+    /// Index of the record in the response
+    pub record: usize,
+    /// The xpath as returned
+    pub xpath: String,
+    /// After [`normalize_llm_xpath`]
+    pub normalized: String,
+    pub reason: InvalidXpathReason,
+}
+
+/// Predicted records resolved to text-map keys, ready for
+/// `calculate_evaluation_metrics` against ground truth
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MappedResponse {
+    /// `textMapFlat` keys covered by each predicted record, as `mapResponseToFullXpath`
+    pub records: Vec<Vec<String>>,
+    /// Records that cover no text at all (the web app's hallucination count)
+    pub num_hallucination: usize,
+    /// Every xpath that covered no text, in response order
+    pub invalid_xpaths: Vec<InvalidXpath>,
+}
+
+/// Resolves predicted xpaths (LLM replies or MDR records) against one tag tree.
+///
+/// Built once per page: the text-map keys of the tree go into an [`XpathTrie`] under
+/// their element's positional path, so `/body[1]/div[2]` also expands to the key
+/// `id('main')` when that is the element's id, and an `id('main')` step resolves to
+/// the first element with that id.
+pub struct ResponseMapper {
+    trie: XpathTrie,
+    index: XpathIndex,
+    ids: HashMap<String, String>,
+}
+
+impl ResponseMapper {
+    pub fn new(root: &TagNodeRef) -> Self {
+        let mut trie = XpathTrie::new();
+        for_each_text(root, &mut |element, _| {
+            trie.insert(positional_xpath(element), &llm_xpath(element));
+        });
+
+        let mut ids = HashMap::new();
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            if let Some(id) = node.get_attribute("id") {
+                ids.entry(id.to_string())
+                    .or_insert_with(|| positional_xpath(node).to_string());
+            }
+            stack.extend(node.children.iter().rev());
+        }
+
+        ResponseMapper {
+            trie,
+            index: XpathIndex::new(root),
+            ids,
+        }
+    }
+
+    /// Text-map keys covered by one predicted xpath, or why there are none
+    pub fn resolve(&self, xpath: &str) -> Result<Vec<&str>, InvalidXpathReason> {
+        let normalized = normalize_llm_xpath(xpath);
+        let mut steps = normalized.split('/');
+        let mut path = match steps.next() {
+            Some("") => String::new(),
+            Some(first) => {
+                let id = id_step(first).ok_or(InvalidXpathReason::Malformed)?;
+                self.ids
+                    .get(id)
+                    .ok_or(InvalidXpathReason::NotInTree)?
+                    .clone()
+            }
+            None => String::new(),
+        };
+        for step in steps {
+            if !is_element_step(step) {
+                return Err(InvalidXpathReason::Malformed);
+            }
+            path.push('/');
+            path.push_str(step);
+        }
+
+        if !self.index.contains(&format!("/html[1]{}", path)) {
+            return Err(InvalidXpathReason::NotInTree);
+        }
+        match self.trie.expand(&path) {
+            keys if keys.is_empty() => Err(InvalidXpathReason::NoText),
+            keys => Ok(keys),
+        }
+    }
+
+    pub fn map_response<S: AsRef<str>>(&self, response: &[Vec<S>]) -> MappedResponse {
+        let mut mapped = MappedResponse::default();
+        for (record, xpaths) in response.iter().enumerate() {
+            let mut keys = Vec::new();
+            for xpath in xpaths {
+                let xpath = xpath.as_ref();
+                match self.resolve(xpath) {
+                    Ok(found) => keys.extend(found.into_iter().map(str::to_string)),
+                    Err(reason) => mapped.invalid_xpaths.push(InvalidXpath {
+                        record,
+                        xpath: xpath.to_string(),
+                        normalized: normalize_llm_xpath(xpath),
+                        reason,
+                    }),
+                }
+            }
+            if keys.is_empty() {
+                mapped.num_hallucination += 1;
+            }
+            mapped.records.push(keys);
+        }
+        mapped
+    }
+}
+
+/// `mapResponseToFullXpath` over a tag tree, e.g. `parse_html(&slim_html(html))`
+pub fn map_response_to_full_xpath<S: AsRef<str>>(
+    root: &TagNodeRef,
+    response: &[Vec<S>],
+) -> MappedResponse {
+    ResponseMapper::new(root).map_response(response)
+}
+
+/// The element's xpath without `/html[1]`, the form LLM replies are normalized to
+fn positional_xpath(element: &TagNode) -> &str {
+    element
+        .xpath
+        .strip_prefix("/html[1]")
+        .unwrap_or(&element.xpath)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_parser::parse_html;
+
+    #[test]
+    fn parse_and_normalize_follow_the_typescript_helpers() {
+        let fenced = "```json\n[[\" /html/body/div[2]/p \"], [\"id('main')\"]]\n```";
+        assert_eq!(
+            parse_llm_response(fenced).unwrap(),
+            vec![vec!["/html/body/div[2]/p"], vec!["id('main')"]]
+        );
+        assert_eq!(
+            parse_llm_response("[\"/body[1]\", \"/body[1]/p[2]\"]").unwrap(),
+            vec![vec!["/body[1]"], vec!["/body[1]/p[2]"]]
+        );
+        assert!(parse_llm_response("[[\"  \"]]").is_err());
+        assert!(parse_llm_response("not json").is_err());
+
+        assert_eq!(
+            normalize_llm_xpath("/html/body/div[2]/p"),
+            "/body[1]/div[2]/p[1]"
+        );
+        assert_eq!(normalize_llm_xpath("/html[1]/body[1]"), "/body[1]");
+        assert_eq!(normalize_llm_xpath("/html"), "");
+        assert_eq!(normalize_llm_xpath("id('main')/span"), "id('main')/span[1]");
+    }
+
+    #[test]
+    fn responses_expand_to_text_keys_and_report_invalid_xpaths() {
+        let root = parse_html(
+            "<html><body><div><p>One</p><p>Two <b>bold</b></p></div>\
+             <div id=\"main\"><span>Main</span> text</div>\
+             <div><p>Empty</p></div><div></div><div>x</div><div>y</div><div>z</div>\
+             <div>a</div><div>b</div><div>c</div><div>ten</div></body></html>",
+        );
+        let mapper = ResponseMapper::new(&root);
+
+        assert_eq!(
+            mapper.resolve("/html/body/div").unwrap(),
+            vec![
+                "/body[1]/div[1]/p[1]",
+                "/body[1]/div[1]/p[2]",
+                "/body[1]/div[1]/p[2]/b[1]"
+            ]
+        );
+        // Positional and id paths reach the same keys; `div[1]` stops short of `div[10]`
+        assert_eq!(
+            mapper.resolve("/body[1]/div[2]").unwrap(),
+            vec!["/body[1]/div[2]/span[1]", "id('main')"]
+        );
+        assert_eq!(
+            mapper.resolve("id('main')").unwrap(),
+            mapper.resolve("/body[1]/div[2]").unwrap()
+        );
+        assert_eq!(
+            mapper.resolve("/body[1]/div[10]").unwrap(),
+            vec!["/body[1]/div[10]"]
+        );
+
+        assert_eq!(
+            mapper.resolve("/body[1]/div[4]"),
+            Err(InvalidXpathReason::NoText)
+        );
+        assert_eq!(
+            mapper.resolve("/body[1]/div[40]"),
+            Err(InvalidXpathReason::NotInTree)
+        );
+        assert_eq!(
+            mapper.resolve("id('nope')"),
+            Err(InvalidXpathReason::NotInTree)
+        );
+        assert_eq!(
+            mapper.resolve("//div[@class]"),
+            Err(InvalidXpathReason::Malformed)
+        );
+        assert_eq!(
+            mapper.resolve("body/div"),
+            Err(InvalidXpathReason::Malformed)
+        );
+
+        let mapped = mapper.map_response(&[
+            vec!["/body[1]/div[1]/p[1]", "/body[1]/div[40]"],
+            vec!["/body[1]/div[4]"],
+            vec!["/html[1]/body[1]/div[3]"],
+        ]);
+        assert_eq!(
+            mapped.records,
+            vec![
+                vec!["/body[1]/div[1]/p[1]".to_string()],
+                vec![],
+                vec!["/body[1]/div[3]/p[1]".to_string()],
+            ]
+        );
+        assert_eq!(mapped.num_hallucination, 1);
+        let reasons: Vec<(usize, InvalidXpathReason)> = mapped
+            .invalid_xpaths
+            .iter()
+            .map(|invalid| (invalid.record, invalid.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (0, InvalidXpathReason::NotInTree),
+                (1, InvalidXpathReason::NoText)
+            ]
+        );
+    }
+}
@@ -1,7 +1,13 @@
-use crate::similarity::get_normalized_edit_distance_sequences;
-use crate::tree_utils::get_children;
-use crate::types::{DataRegion, RegionsMapItem, TagNodeRef};
+use crate::arena::{ArenaTree, NodeId};
+use crate::session::MdrContext;
+use crate::similarity::{
+    get_normalized_edit_distance_sequences, get_normalized_edit_distance_sequences_within,
+};
+use crate::trace::{CandidateRegion, CandidateVerdict, GnComparison, IdentPass, ParentTrace};
+use crate::types::{DataRegion, MdrOptions, RegionsMapItem, TagNodeRef};
+use crate::xpath_index::XpathIndex;
 use indexmap::IndexMap;
+use std::ops::Range;
 
 #[cfg(feature = "parallel")]
 use rayon::prelude::*;
@@ -10,12 +16,99 @@ use rayon::prelude::*;
 pub fn ident_drs(
     start_child_idx: usize,
     children: &[TagNodeRef],
-    k: usize,
-    t: f32,
+    context: &MdrContext,
 ) -> Vec<DataRegion> {
+    ident_drs_traced(start_child_idx, children, context, None)
+}
+
+/// [`ident_drs`] that appends one [`IdentPass`] per invocation to `passes` when given
+pub fn ident_drs_traced(
+    start_child_idx: usize,
+    children: &[TagNodeRef],
+    context: &MdrContext,
+    passes: Option<&mut Vec<IdentPass>>,
+) -> Vec<DataRegion> {
+    ident_drs_in(start_child_idx, children, context, passes)
+}
+
+/// Siblings whose generalized nodes `ident_drs` compares, addressed by index ranges
+pub(crate) trait GeneralizedNodes {
+    fn len(&self) -> usize;
+
+    fn distance(&self, a: Range<usize>, b: Range<usize>, context: &MdrContext) -> f32;
+
+    /// The distance when it is `<= context.t`
+    fn distance_within(
+        &self,
+        a: Range<usize>,
+        b: Range<usize>,
+        context: &MdrContext,
+    ) -> Option<f32>;
+}
+
+impl GeneralizedNodes for [TagNodeRef] {
+    fn len(&self) -> usize {
+        <[TagNodeRef]>::len(self)
+    }
+
+    fn distance(&self, a: Range<usize>, b: Range<usize>, context: &MdrContext) -> f32 {
+        get_normalized_edit_distance_sequences(&self[a], &self[b], context)
+    }
+
+    fn distance_within(
+        &self,
+        a: Range<usize>,
+        b: Range<usize>,
+        context: &MdrContext,
+    ) -> Option<f32> {
+        get_normalized_edit_distance_sequences_within(&self[a], &self[b], context)
+    }
+}
+
+/// Children of one arena node
+struct ArenaChildren<'a> {
+    tree: &'a ArenaTree,
+    ids: Range<NodeId>,
+}
+
+impl ArenaChildren<'_> {
+    fn ids(&self, range: Range<usize>) -> Range<NodeId> {
+        self.ids.start + range.start as NodeId..self.ids.start + range.end as NodeId
+    }
+}
+
+impl GeneralizedNodes for ArenaChildren<'_> {
+    fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    fn distance(&self, a: Range<usize>, b: Range<usize>, context: &MdrContext) -> f32 {
+        self.tree
+            .sequence_distance(self.ids(a), self.ids(b), context)
+    }
+
+    fn distance_within(
+        &self,
+        a: Range<usize>,
+        b: Range<usize>,
+        context: &MdrContext,
+    ) -> Option<f32> {
+        self.tree
+            .sequence_distance_within(self.ids(a), self.ids(b), context)
+    }
+}
+
+fn ident_drs_in<C: GeneralizedNodes + ?Sized>(
+    start_child_idx: usize,
+    children: &C,
+    context: &MdrContext,
+    mut passes: Option<&mut Vec<IdentPass>>,
+) -> Vec<DataRegion> {
+    let (k, t) = (context.k, context.t);
     let mut identified_regions: Vec<DataRegion> = Vec::new();
     let n = children.len();
     let mut current_max_dr: Option<DataRegion> = None;
+    let mut pass = passes.is_some().then(|| IdentPass::new(start_child_idx));
 
     for gn_length in 1..=k {
         for start_idx in start_child_idx..=(start_child_idx + gn_length - 1) {
@@ -28,12 +121,31 @@ pub fn ident_drs(
 
             let mut check_idx = start_idx;
             while check_idx + 2 * gn_length <= n {
-                let gn1 = &children[check_idx..check_idx + gn_length];
-                let gn2 = &children[check_idx + gn_length..check_idx + 2 * gn_length];
+                // Regions of earlier passes are kept; this unfinished pass is dropped
+                if context.should_stop() {
+                    return identified_regions;
+                }
+
+                let gn1 = check_idx..check_idx + gn_length;
+                let gn2 = check_idx + gn_length..check_idx + 2 * gn_length;
 
-                let distance = get_normalized_edit_distance_sequences(gn1, gn2);
+                // Only the threshold decision matters unless the exact distance is traced
+                let similar = match pass.as_mut() {
+                    Some(pass) => {
+                        let distance = children.distance(gn1, gn2, context);
+                        pass.comparisons.push(GnComparison {
+                            gn_length,
+                            start_idx,
+                            check_idx,
+                            distance,
+                            similar: distance <= t,
+                        });
+                        distance <= t
+                    }
+                    None => children.distance_within(gn1, gn2, context).is_some(),
+                };
 
-                if distance <= t {
+                if similar {
                     if !is_continuing_region {
                         current_dr = Some((gn_length, check_idx, 2 * gn_length));
                         is_continuing_region = true;
@@ -50,36 +162,34 @@ pub fn ident_drs(
             }
 
             if let Some(dr) = current_dr {
-                // Match TypeScript logic EXACTLY with proper parentheses
-                let should_update = if let Some(ref max_dr) = current_max_dr {
-                    // First complex condition (matches TypeScript lines 128-134)
-                    let cond1_part1 = dr.2 > max_dr.2; // currentDR[2] > currentMaxDR[2]
-                    let cond1_part2 = max_dr.1 == 0 || dr.1 <= max_dr.1; // currentMaxDR[1] === 0 || currentDR[1] <= currentMaxDR[1]
-                    let first_condition = cond1_part1 && cond1_part2;
-
-                    // Second condition (matches TypeScript lines 135-143)
-                    let second_condition = dr.2 == max_dr.2 && dr.1 == max_dr.1 && dr.0 < max_dr.0;
-
-                    first_condition || second_condition
-                } else {
-                    // !currentMaxDR case
-                    true
-                };
+                let verdict = compare_to_max(dr, current_max_dr);
+                if let Some(pass) = pass.as_mut() {
+                    pass.candidates.push(CandidateRegion {
+                        region: dr,
+                        verdict,
+                        previous_max: current_max_dr,
+                    });
+                }
 
-                if should_update {
+                if verdict.accepted() {
                     current_max_dr = Some(dr);
                 }
             }
         }
     }
 
+    if let (Some(mut pass), Some(passes)) = (pass, passes.as_deref_mut()) {
+        pass.selected = current_max_dr;
+        passes.push(pass);
+    }
+
     if let Some(max_dr) = current_max_dr {
         identified_regions.push(max_dr);
 
         // Find additional regions outside the current max region
         let next_start_idx = max_dr.1 + max_dr.2;
         if next_start_idx < n {
-            let additional_regions = ident_drs(next_start_idx, children, k, t);
+            let additional_regions = ident_drs_in(next_start_idx, children, context, passes);
             identified_regions.extend(additional_regions);
         }
     }
@@ -87,60 +197,117 @@ pub fn ident_drs(
     identified_regions
 }
 
-/// Recursively finds data regions in the entire tree
+/// Whether candidate `dr` replaces the maximal region, and why
+fn compare_to_max(dr: DataRegion, current_max_dr: Option<DataRegion>) -> CandidateVerdict {
+    // Match TypeScript logic EXACTLY with proper parentheses
+    let Some(max_dr) = current_max_dr else {
+        // !currentMaxDR case
+        return CandidateVerdict::Initial;
+    };
+
+    // First complex condition (matches TypeScript lines 128-134)
+    let cond1_part1 = dr.2 > max_dr.2; // currentDR[2] > currentMaxDR[2]
+    let cond1_part2 = max_dr.1 == 0 || dr.1 <= max_dr.1; // currentMaxDR[1] === 0 || currentDR[1] <= currentMaxDR[1]
+    let first_condition = cond1_part1 && cond1_part2;
+
+    // Second condition (matches TypeScript lines 135-143)
+    let second_condition = dr.2 == max_dr.2 && dr.1 == max_dr.1 && dr.0 < max_dr.0;
+
+    if first_condition {
+        CandidateVerdict::MoreNodes
+    } else if second_condition {
+        CandidateVerdict::ShorterGn
+    } else if cond1_part1 {
+        CandidateVerdict::StartsLater
+    } else if dr.2 < max_dr.2 {
+        CandidateVerdict::FewerNodes
+    } else {
+        CandidateVerdict::NotBetter
+    }
+}
+
+/// Recursively finds data regions in the entire tree (converted to an [`ArenaTree`]
+/// first); `depth` is that of `node` and counts against `MdrLimits::max_depth`.
+/// Each call is a run of its own: limits start over.
 pub fn find_drs_recursive(
     node: &TagNodeRef,
-    k: usize,
-    t: f32,
     depth: usize,
+    context: &MdrContext,
     node_regions_map: &mut IndexMap<String, Vec<DataRegion>>,
 ) {
-    let children = get_children(node);
+    let tree = ArenaTree::from_tag_node(node, &context.options.signature_attributes);
+    context.begin_run();
+    find_drs_at(&tree, tree.root(), depth, context, node_regions_map);
+}
+
+/// [`find_drs_recursive`] over an [`ArenaTree`], which the region search runs on
+pub fn find_drs_arena(
+    tree: &ArenaTree,
+    node: NodeId,
+    context: &MdrContext,
+    node_regions_map: &mut IndexMap<String, Vec<DataRegion>>,
+) {
+    context.begin_run();
+    find_drs_at(tree, node, 0, context, node_regions_map);
+}
+
+fn find_drs_at(
+    tree: &ArenaTree,
+    node: NodeId,
+    depth: usize,
+    context: &MdrContext,
+    node_regions_map: &mut IndexMap<String, Vec<DataRegion>>,
+) {
+    let children = tree.children(node);
+    let xpath = tree.xpath(node);
 
     // Initialize node regions to empty (matching TypeScript)
-    node_regions_map.insert(node.xpath.clone(), Vec::new());
+    node_regions_map.insert(xpath.to_string(), Vec::new());
 
-    // Check if node has grandchildren (TypeScript: hasGrandchildren)
-    let mut has_grandchildren = false;
-    for child in &children {
-        if !get_children(child).is_empty() {
-            has_grandchildren = true;
-            break;
-        }
+    // Past a limit or cancelled: leave this subtree without regions
+    if !context.enter_node(depth) {
+        return;
     }
 
+    // Check if node has grandchildren (TypeScript: hasGrandchildren)
+    let has_grandchildren = children
+        .clone()
+        .any(|child| !tree.children(child).is_empty());
+
     // Only run MDR if node has grandchildren and at least 2 children
     let mut node_drs = Vec::new();
+    let mut passes = Vec::new();
     if has_grandchildren && children.len() >= 2 {
-        node_drs = ident_drs(0, &children, k, t);
+        let siblings = ArenaChildren {
+            tree,
+            ids: children.clone(),
+        };
+        let passes = context.is_tracing().then_some(&mut passes);
+        node_drs = ident_drs_in(0, &siblings, context, passes);
 
         // Update map with found regions
-        node_regions_map.insert(node.xpath.clone(), node_drs.clone());
+        node_regions_map.insert(xpath.to_string(), node_drs.clone());
     }
 
     // Process children recursively and collect uncovered regions
     let mut temp_drs = Vec::new();
-    for (child_idx, child) in children.iter().enumerate() {
+    for (child_idx, child) in children.clone().enumerate() {
         // Recursive call
-        find_drs_recursive(&child, k, t, depth + 1, node_regions_map);
+        find_drs_at(tree, child, depth + 1, context, node_regions_map);
 
         // Get uncovered child DRs (UnCoveredDRs function logic)
         let child_drs = node_regions_map
-            .get(&child.xpath)
+            .get(tree.xpath(child))
             .cloned()
             .unwrap_or_default();
-        let mut is_covered = false;
 
         // Check if this child index is covered by any parent region
-        for dr in &node_drs {
+        let is_covered = node_drs.iter().any(|dr| {
             let start_idx = dr.1;
             let node_count = dr.2;
             let end_idx = start_idx + node_count - 1;
-            if child_idx >= start_idx && child_idx <= end_idx {
-                is_covered = true;
-                break;
-            }
-        }
+            child_idx >= start_idx && child_idx <= end_idx
+        });
 
         // If not covered, add child's regions to temp
         if !is_covered {
@@ -148,20 +315,56 @@ pub fn find_drs_recursive(
         }
     }
 
+    if context.is_tracing() && !passes.is_empty() {
+        context.record_trace(ParentTrace {
+            parent_xpath: xpath.to_string(),
+            child_count: children.len(),
+            passes,
+            regions: node_drs.clone(),
+            inherited: temp_drs.clone(),
+        });
+    }
+
     // Combine node DRs with uncovered child DRs (matching TypeScript line 215)
     let mut final_drs = node_drs;
     final_drs.extend(temp_drs);
 
     // Always update the map with final regions (matching TypeScript line 216)
-    node_regions_map.insert(node.xpath.clone(), final_drs);
+    node_regions_map.insert(xpath.to_string(), final_drs);
 }
 
 /// Main MDR algorithm entry point
 pub fn run_mdr_algorithm(root_node: &TagNodeRef, k: usize, t: f32) -> Vec<RegionsMapItem> {
+    run_mdr_algorithm_with_options(root_node, k, t, &MdrOptions::default())
+}
+
+/// MDR algorithm entry point with explicit similarity options
+pub fn run_mdr_algorithm_with_options(
+    root_node: &TagNodeRef,
+    k: usize,
+    t: f32,
+    options: &MdrOptions,
+) -> Vec<RegionsMapItem> {
+    let context = MdrContext::global(k, t, options.clone());
+    run_mdr_algorithm_with_context(root_node, &context)
+}
+
+/// MDR algorithm entry point taking parameters, options and cache from `context`
+pub fn run_mdr_algorithm_with_context(
+    root_node: &TagNodeRef,
+    context: &MdrContext,
+) -> Vec<RegionsMapItem> {
+    let tree = ArenaTree::from_tag_node(root_node, &context.options.signature_attributes);
+    run_mdr_algorithm_on_arena(&tree, context)
+}
+
+/// MDR algorithm entry point for a tree already converted to an [`ArenaTree`]; its
+/// signature attributes must match `context.options`
+pub fn run_mdr_algorithm_on_arena(tree: &ArenaTree, context: &MdrContext) -> Vec<RegionsMapItem> {
     let mut node_regions_map = IndexMap::new();
 
     // Run the recursive algorithm to populate the map
-    find_drs_recursive(root_node, k, t, 0, &mut node_regions_map);
+    find_drs_arena(tree, tree.root(), context, &mut node_regions_map);
 
     // Build output vector from the map (matching TypeScript runMDRAlgorithm)
     let mut all_regions = Vec::new();
@@ -185,3 +388,46 @@ pub fn regions_to_map(regions: &[RegionsMapItem]) -> IndexMap<String, Vec<DataRe
     }
     map
 }
+
+/// `regions` without the copies the search lists under ancestors: under TS parity an
+/// ancestor inherits its uncovered descendants' regions with indices into the
+/// descendant's children, and such a copy often still fits the ancestor's children
+/// (e.g. `(1, 0, 2)` on `/html[1]` spans head and body). A region is kept only where
+/// every generalized node is within `t` of the first one under the parent it is
+/// listed for; parents missing from `index` are dropped.
+pub fn own_regions(
+    regions: &[RegionsMapItem],
+    index: &XpathIndex,
+    context: &MdrContext,
+) -> Vec<RegionsMapItem> {
+    regions
+        .iter()
+        .filter_map(|item| {
+            let parent = index.get(&item.parent_xpath)?;
+            let regions: Vec<DataRegion> = item
+                .regions
+                .iter()
+                .copied()
+                .filter(|&region| repeats_signature(parent, region, context))
+                .collect();
+            (!regions.is_empty()).then(|| RegionsMapItem {
+                parent_xpath: item.parent_xpath.clone(),
+                regions,
+            })
+        })
+        .collect()
+}
+
+/// Whether every generalized node of `region` is within `t` of the first one
+fn repeats_signature(parent: &TagNodeRef, region: DataRegion, context: &MdrContext) -> bool {
+    let (gn_length, start_idx, node_count) = region;
+    let Some(covered) = parent.children.get(start_idx..start_idx + node_count) else {
+        return false;
+    };
+    if gn_length == 0 || node_count < 2 * gn_length {
+        return false;
+    }
+    let mut gns = covered.chunks_exact(gn_length);
+    let first = gns.next().unwrap_or(&[]);
+    gns.all(|gn| get_normalized_edit_distance_sequences_within(first, gn, context).is_some())
+}
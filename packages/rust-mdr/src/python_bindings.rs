@@ -0,0 +1,373 @@
+use crate::chunking::{chunk_page, merge_chunk_responses, ChunkOptions, TokenEstimator};
+use crate::context_reduction::{reduce_context, ReductionOptions};
+use crate::error::{self, validate_parameters, validate_threshold};
+use crate::evaluation::{calculate_evaluation_metrics, calculate_overlap, record_xpaths};
+use crate::html_parser::{parse_html, parse_page_html};
+use crate::llm_response::{map_response_to_full_xpath, parse_llm_response};
+use crate::mdr_algorithm::run_mdr_algorithm_with_context;
+use crate::record_extraction::{find_orphan_records, identify_all_data_records_with_tree};
+use crate::session::{run_mdr_pipeline, MdrContext, MdrRunOptions};
+use crate::slim_html::slim_html;
+use crate::text_map::extract_text_with_xpaths;
+use crate::types::{DataRecord, RegionsMapItem, TagNodeRef};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyString;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::cell::RefCell;
+
+create_exception!(
+    rust_mdr_utils,
+    MdrError,
+    PyException,
+    "Raised by the MDR entry points; carries `code` and `details` like the JS `MdrError`."
+);
+
+/// `MdrError` reaches Python as `rust_mdr_utils.MdrError` carrying `code` and `details`
+impl From<error::MdrError> for PyErr {
+    fn from(error: error::MdrError) -> Self {
+        Python::with_gil(|py| {
+            let py_error = MdrError::new_err(error.to_string());
+            let value = py_error.value(py);
+            let _ = value.setattr("code", error.code());
+            if let Ok(details) = to_py(py, &error, "error details") {
+                let _ = value.setattr("details", details);
+            }
+            py_error
+        })
+    }
+}
+
+/// Accepts JSON text or any `json.dumps`-able object (dicts and lists as returned here)
+fn from_py<T: DeserializeOwned>(value: &Bound<'_, PyAny>, target: &str) -> PyResult<T> {
+    let json: String = match value.downcast::<PyString>() {
+        Ok(text) => text.to_str()?.to_owned(),
+        Err(_) => value
+            .py()
+            .import("json")?
+            .call_method1("dumps", (value,))?
+            .extract()?,
+    };
+    Ok(serde_json::from_str(&json).map_err(|e| error::MdrError::deserialization(target, e))?)
+}
+
+/// Like [`from_py`], treating `None` as `T::default()`
+fn options_from_py<T: DeserializeOwned + Default>(
+    value: Option<&Bound<'_, PyAny>>,
+    target: &str,
+) -> PyResult<T> {
+    match value {
+        Some(value) if !value.is_none() => from_py(value, target),
+        _ => Ok(T::default()),
+    }
+}
+
+/// Plain dicts and lists, the same shape `JSON.parse` gives for the wasm output
+fn to_py<T: Serialize>(py: Python<'_>, value: &T, target: &str) -> PyResult<PyObject> {
+    let json =
+        serde_json::to_string(value).map_err(|e| error::MdrError::serialization(target, e))?;
+    Ok(py.import("json")?.call_method1("loads", (json,))?.unbind())
+}
+
+/// Parse an HTML string into a TagNode tree (same numbering as `buildTagTree`)
+#[pyfunction]
+#[pyo3(name = "parse_html")]
+fn parse_html_py(py: Python<'_>, html: &str) -> PyResult<PyObject> {
+    to_py(py, &parse_html(html), "tag tree")
+}
+
+/// The "slim" LLM input format of an HTML document, like `slimHtml`
+#[pyfunction]
+#[pyo3(name = "slim_html")]
+fn slim_html_py(py: Python<'_>, html: &str) -> String {
+    py.allow_threads(|| slim_html(html))
+}
+
+/// `{"textMapFlat", "textMap"}` of a tag tree, like `extractTextWithXPaths`
+#[pyfunction]
+#[pyo3(name = "extract_text_maps")]
+fn extract_text_maps_py(py: Python<'_>, root: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+    let root_node: TagNodeRef = from_py(root, "root node")?;
+    to_py(py, &extract_text_with_xpaths(&root_node), "text maps")
+}
+
+/// Record xpath lists of an LLM reply, like `parseAndValidateXPaths`
+#[pyfunction]
+#[pyo3(name = "parse_llm_response")]
+fn parse_llm_response_py(content: &str) -> PyResult<Vec<Vec<String>>> {
+    Ok(parse_llm_response(content)?)
+}
+
+/// `{"records", "numHallucination", "invalidXpaths"}`, like `mapResponseToFullXpath`
+#[pyfunction]
+#[pyo3(name = "map_response_to_full_xpath")]
+fn map_response_to_full_xpath_py(
+    py: Python<'_>,
+    root: &Bound<'_, PyAny>,
+    response: &Bound<'_, PyAny>,
+) -> PyResult<PyObject> {
+    let root_node: TagNodeRef = from_py(root, "root node")?;
+    let response: Vec<Vec<String>> = from_py(response, "response")?;
+    to_py(
+        py,
+        &map_response_to_full_xpath(&root_node, &response),
+        "mapped response",
+    )
+}
+
+/// The page cut down to its MDR data regions in all three prompt formats, like
+/// `reduceContext`
+#[pyfunction]
+#[pyo3(name = "reduce_context", signature = (root, options=None))]
+fn reduce_context_py(
+    py: Python<'_>,
+    root: &Bound<'_, PyAny>,
+    options: Option<&Bound<'_, PyAny>>,
+) -> PyResult<PyObject> {
+    let root_node: TagNodeRef = from_py(root, "root node")?;
+    let options: ReductionOptions = options_from_py(options, "reduction options")?;
+    validate_parameters(options.k, options.t)?;
+
+    let page = py.allow_threads(|| reduce_context(&root_node, &options));
+    to_py(py, &page, "reduced page")
+}
+
+/// An LLM reply about a reduced page mapped to the original page, like
+/// `mapReducedResponse`
+#[pyfunction]
+#[pyo3(name = "map_reduced_response", signature = (root, response, options=None))]
+fn map_reduced_response_py(
+    py: Python<'_>,
+    root: &Bound<'_, PyAny>,
+    response: &Bound<'_, PyAny>,
+    options: Option<&Bound<'_, PyAny>>,
+) -> PyResult<PyObject> {
+    let root_node: TagNodeRef = from_py(root, "root node")?;
+    let response: Vec<Vec<String>> = from_py(response, "response")?;
+    let options: ReductionOptions = options_from_py(options, "reduction options")?;
+    validate_parameters(options.k, options.t)?;
+
+    let mapped = py.allow_threads(|| reduce_context(&root_node, &options).map_response(&response));
+    to_py(py, &mapped, "mapped response")
+}
+
+/// Runs `chunk` with `estimate_tokens(text) -> int` as estimator, or the default one
+/// without holding the GIL; the first exception the callable raises is re-raised
+fn with_py_estimator<T: Send>(
+    py: Python<'_>,
+    estimate_tokens: Option<&Bound<'_, PyAny>>,
+    options: &ChunkOptions,
+    chunk: impl FnOnce(&dyn TokenEstimator) -> T + Send,
+) -> PyResult<T> {
+    let Some(callable) = estimate_tokens.filter(|callable| !callable.is_none()) else {
+        let estimator = options.default_estimator();
+        return Ok(py.allow_threads(|| chunk(&estimator)));
+    };
+    let error: RefCell<Option<PyErr>> = RefCell::new(None);
+    let estimator = |text: &str| match callable
+        .call1((text,))
+        .and_then(|tokens| tokens.extract::<usize>())
+    {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            error.borrow_mut().get_or_insert(e);
+            0
+        }
+    };
+    let out = chunk(&estimator);
+    match error.into_inner() {
+        Some(e) => Err(e),
+        None => Ok(out),
+    }
+}
+
+/// Chunks of whole subtrees under `maxTokens`, never splitting an MDR data region,
+/// like `chunkPage`
+#[pyfunction]
+#[pyo3(name = "chunk_page", signature = (root, options=None, estimate_tokens=None))]
+fn chunk_page_py(
+    py: Python<'_>,
+    root: &Bound<'_, PyAny>,
+    options: Option<&Bound<'_, PyAny>>,
+    estimate_tokens: Option<&Bound<'_, PyAny>>,
+) -> PyResult<PyObject> {
+    let root_node: TagNodeRef = from_py(root, "root node")?;
+    let options: ChunkOptions = options_from_py(options, "chunk options")?;
+    validate_parameters(options.k, options.t)?;
+
+    let chunks = with_py_estimator(py, estimate_tokens, &options, |estimator| {
+        chunk_page(&root_node, &options, estimator)
+    })?;
+    to_py(py, &chunks, "chunks")
+}
+
+/// One LLM reply per chunk merged into records over the original page, like
+/// `mergeChunkResponses`
+#[pyfunction]
+#[pyo3(
+    name = "merge_chunk_responses",
+    signature = (root, responses, options=None, estimate_tokens=None)
+)]
+fn merge_chunk_responses_py(
+    py: Python<'_>,
+    root: &Bound<'_, PyAny>,
+    responses: &Bound<'_, PyAny>,
+    options: Option<&Bound<'_, PyAny>>,
+    estimate_tokens: Option<&Bound<'_, PyAny>>,
+) -> PyResult<PyObject> {
+    let root_node: TagNodeRef = from_py(root, "root node")?;
+    let responses: Vec<Vec<Vec<String>>> = from_py(responses, "responses")?;
+    let options: ChunkOptions = options_from_py(options, "chunk options")?;
+    validate_parameters(options.k, options.t)?;
+
+    let chunks = with_py_estimator(py, estimate_tokens, &options, |estimator| {
+        chunk_page(&root_node, &options, estimator)
+    })?;
+    to_py(
+        py,
+        &merge_chunk_responses(&chunks, &responses)?,
+        "merged response",
+    )
+}
+
+/// Run the MDR algorithm on a tag tree and return its regions
+#[pyfunction]
+#[pyo3(name = "run_mdr_algorithm", signature = (root, k=10, t=0.3, options=None))]
+fn run_mdr_algorithm_py(
+    py: Python<'_>,
+    root: &Bound<'_, PyAny>,
+    k: usize,
+    t: f32,
+    options: Option<&Bound<'_, PyAny>>,
+) -> PyResult<PyObject> {
+    validate_parameters(k, t)?;
+    let root_node: TagNodeRef = from_py(root, "root node")?;
+    let options: MdrRunOptions = options_from_py(options, "options")?;
+
+    let context = MdrContext::global(k, t, options.mdr).with_limits(options.limits);
+    let regions = py.allow_threads(|| run_mdr_algorithm_with_context(&root_node, &context));
+    to_py(py, &regions, "regions")
+}
+
+/// End-to-end MDR on a tag tree: same output as the wasm `runMdrFull`
+#[pyfunction]
+#[pyo3(name = "run_mdr", signature = (root, k=10, t=0.3, options=None))]
+fn run_mdr_py(
+    py: Python<'_>,
+    root: &Bound<'_, PyAny>,
+    k: usize,
+    t: f32,
+    options: Option<&Bound<'_, PyAny>>,
+) -> PyResult<PyObject> {
+    validate_parameters(k, t)?;
+    let root_node: TagNodeRef = from_py(root, "root node")?;
+    let options: MdrRunOptions = options_from_py(options, "options")?;
+
+    let context = MdrContext::global(k, t, options.mdr).with_limits(options.limits);
+    let out = py.allow_threads(|| run_mdr_pipeline(&root_node, &context));
+    to_py(py, &out, "MDR output")
+}
+
+/// End-to-end MDR straight from an HTML string, like the wasm `runMdrFromHtml`
+#[pyfunction]
+#[pyo3(name = "run_mdr_html", signature = (html, k=10, t=0.3, options=None))]
+fn run_mdr_html_py(
+    py: Python<'_>,
+    html: &str,
+    k: usize,
+    t: f32,
+    options: Option<&Bound<'_, PyAny>>,
+) -> PyResult<PyObject> {
+    validate_parameters(k, t)?;
+    let options: MdrRunOptions = options_from_py(options, "options")?;
+
+    let context = MdrContext::global(k, t, options.mdr).with_limits(options.limits);
+    let out = py.allow_threads(|| run_mdr_pipeline(&parse_page_html(html), &context));
+    to_py(py, &out, "MDR output")
+}
+
+/// Identify all data records from regions
+#[pyfunction]
+#[pyo3(name = "identify_all_data_records")]
+fn identify_all_data_records_py(
+    py: Python<'_>,
+    regions: &Bound<'_, PyAny>,
+    t: f32,
+    root: &Bound<'_, PyAny>,
+) -> PyResult<PyObject> {
+    validate_threshold(t)?;
+    let regions: Vec<RegionsMapItem> = from_py(regions, "regions")?;
+    let root_node: TagNodeRef = from_py(root, "root node")?;
+
+    let records = identify_all_data_records_with_tree(&regions, t, &root_node);
+    to_py(py, &records, "records")
+}
+
+/// Find orphan records
+#[pyfunction]
+#[pyo3(name = "find_orphan_records")]
+fn find_orphan_records_py(
+    py: Python<'_>,
+    regions: &Bound<'_, PyAny>,
+    t: f32,
+    root: &Bound<'_, PyAny>,
+) -> PyResult<PyObject> {
+    validate_threshold(t)?;
+    let regions: Vec<RegionsMapItem> = from_py(regions, "regions")?;
+    let root_node: TagNodeRef = from_py(root, "root node")?;
+
+    let orphans = find_orphan_records(&regions, t, &root_node);
+    to_py(py, &orphans, "orphans")
+}
+
+/// Xpaths of each record (TagNode or list of TagNodes), e.g. of `finalRecords`
+#[pyfunction]
+#[pyo3(name = "record_xpaths")]
+fn record_xpaths_py(records: &Bound<'_, PyAny>) -> PyResult<Vec<Vec<String>>> {
+    let records: Vec<DataRecord> = from_py(records, "records")?;
+    Ok(record_xpaths(&records))
+}
+
+/// Precision/recall/F1 of predicted records (xpath lists) against ground truth
+#[pyfunction]
+#[pyo3(name = "calculate_evaluation_metrics")]
+fn calculate_evaluation_metrics_py(
+    py: Python<'_>,
+    predicted_records: Vec<Vec<String>>,
+    ground_truth_records: Vec<Vec<String>>,
+) -> PyResult<PyObject> {
+    let result = calculate_evaluation_metrics(&predicted_records, &ground_truth_records);
+    to_py(py, &result, "metrics")
+}
+
+/// Jaccard overlap between two records (xpath lists)
+#[pyfunction]
+#[pyo3(name = "calculate_overlap")]
+fn calculate_overlap_py(record1: Vec<String>, record2: Vec<String>) -> f64 {
+    calculate_overlap(&record1, &record2)
+}
+
+#[pymodule]
+fn rust_mdr_utils(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("MdrError", m.py().get_type::<MdrError>())?;
+    m.add_function(wrap_pyfunction!(parse_html_py, m)?)?;
+    m.add_function(wrap_pyfunction!(slim_html_py, m)?)?;
+    m.add_function(wrap_pyfunction!(extract_text_maps_py, m)?)?;
+    m.add_function(wrap_pyfunction!(parse_llm_response_py, m)?)?;
+    m.add_function(wrap_pyfunction!(map_response_to_full_xpath_py, m)?)?;
+    m.add_function(wrap_pyfunction!(reduce_context_py, m)?)?;
+    m.add_function(wrap_pyfunction!(map_reduced_response_py, m)?)?;
+    m.add_function(wrap_pyfunction!(chunk_page_py, m)?)?;
+    m.add_function(wrap_pyfunction!(merge_chunk_responses_py, m)?)?;
+    m.add_function(wrap_pyfunction!(run_mdr_algorithm_py, m)?)?;
+    m.add_function(wrap_pyfunction!(run_mdr_py, m)?)?;
+    m.add_function(wrap_pyfunction!(run_mdr_html_py, m)?)?;
+    m.add_function(wrap_pyfunction!(identify_all_data_records_py, m)?)?;
+    m.add_function(wrap_pyfunction!(find_orphan_records_py, m)?)?;
+    m.add_function(wrap_pyfunction!(record_xpaths_py, m)?)?;
+    m.add_function(wrap_pyfunction!(calculate_evaluation_metrics_py, m)?)?;
+    m.add_function(wrap_pyfunction!(calculate_overlap_py, m)?)?;
+    Ok(())
+}
@@ -1,13 +1,17 @@
-use crate::similarity::{are_all_siblings_similar, get_normalized_edit_distance_sequences};
-use crate::tree_utils::{flatten_subtree, get_children, get_node_by_xpath};
-use crate::types::{DataRecord, RegionsMapItem, TagNodeRef};
+use crate::error::MdrError;
+use crate::session::MdrContext;
+use crate::similarity::{are_all_siblings_similar, get_normalized_edit_distance_sequences_within};
+use crate::tree_utils::{flatten_subtree, get_children};
+use crate::types::{DataRecord, MdrOptions, OrphanMerge, RegionsMapItem, TagNodeRef};
+use crate::xpath_index::XpathIndex;
 use std::collections::HashSet;
+use std::sync::Arc;
 
 /// Find records for a single node (gnLength = 1)
-pub fn find_records1(g: &TagNodeRef, t: f32) -> Vec<TagNodeRef> {
+pub fn find_records1(g: &TagNodeRef, context: &MdrContext) -> Vec<TagNodeRef> {
     let children = get_children(g);
     let is_table_row = g.tag_name == "tr";
-    let children_are_similar = are_all_siblings_similar(&children, t);
+    let children_are_similar = are_all_siblings_similar(&children, context);
 
     if !children.is_empty() && children_are_similar && !is_table_row {
         children
@@ -17,7 +21,7 @@ pub fn find_records1(g: &TagNodeRef, t: f32) -> Vec<TagNodeRef> {
 }
 
 /// Helper to check if merging would produce non-contiguous records
-fn would_produce_non_contiguous(g: &[TagNodeRef], t: f32) -> bool {
+fn would_produce_non_contiguous(g: &[TagNodeRef], context: &MdrContext) -> bool {
     if g.len() <= 1 {
         return false;
     }
@@ -36,7 +40,7 @@ fn would_produce_non_contiguous(g: &[TagNodeRef], t: f32) -> bool {
             same_number_of_children = false;
             break;
         }
-        if !are_all_siblings_similar(&children, t) {
+        if !are_all_siblings_similar(&children, context) {
             children_are_similar_within_components = false;
             break;
         }
@@ -46,12 +50,11 @@ fn would_produce_non_contiguous(g: &[TagNodeRef], t: f32) -> bool {
 }
 
 /// Find records for multiple nodes (gnLength > 1)
-pub fn find_records_n(g: &[TagNodeRef], t: f32) -> Vec<DataRecord> {
+pub fn find_records_n(g: &[TagNodeRef], context: &MdrContext) -> Vec<DataRecord> {
     if g.is_empty() {
         return vec![];
     }
 
-    let n = g.len();
     let mut children_are_similar_within_components = true;
     let mut same_number_of_children = true;
     let first_node_children_count = get_children(&g[0]).len();
@@ -62,7 +65,7 @@ pub fn find_records_n(g: &[TagNodeRef], t: f32) -> Vec<DataRecord> {
             same_number_of_children = false;
             break;
         }
-        if !are_all_siblings_similar(&children, t) {
+        if !are_all_siblings_similar(&children, context) {
             children_are_similar_within_components = false;
             break;
         }
@@ -75,8 +78,8 @@ pub fn find_records_n(g: &[TagNodeRef], t: f32) -> Vec<DataRecord> {
         let mut records: Vec<DataRecord> = Vec::new();
         for i in 0..first_node_children_count {
             let mut record_group: Vec<TagNodeRef> = Vec::new();
-            for j in 0..n {
-                let comp_children = get_children(&g[j]);
+            for component_node in g {
+                let comp_children = get_children(component_node);
                 if i < comp_children.len() {
                     record_group.push(comp_children[i].clone());
                 }
@@ -108,7 +111,7 @@ pub fn identify_all_data_records(regions: &[RegionsMapItem], _t: f32) -> Vec<Dat
         // This requires access to the original tree - we'll need to pass it in
         // For now, we'll skip the actual implementation details that require tree access
 
-        for (_region_index, region) in sorted_regions.iter().enumerate() {
+        for region in sorted_regions.iter() {
             let (gn_length, start_idx, node_count) = *region;
             let _num_gns = node_count / gn_length;
             let region_key = format!("{}-{}", parent_xpath, start_idx);
@@ -131,20 +134,50 @@ pub fn identify_all_data_records_with_tree(
     regions: &[RegionsMapItem],
     t: f32,
     root: &TagNodeRef,
+) -> Vec<DataRecord> {
+    identify_all_data_records_with_options(regions, t, root, &MdrOptions::default())
+}
+
+/// Same as [`identify_all_data_records_with_tree`] with explicit similarity options
+pub fn identify_all_data_records_with_options(
+    regions: &[RegionsMapItem],
+    t: f32,
+    root: &TagNodeRef,
+    options: &MdrOptions,
+) -> Vec<DataRecord> {
+    // `k` plays no part in record identification
+    let context = MdrContext::global(1, t, options.clone());
+    identify_all_data_records_with_context(regions, root, &context)
+}
+
+/// Same as [`identify_all_data_records_with_tree`] with threshold, options and cache from `context`
+pub fn identify_all_data_records_with_context(
+    regions: &[RegionsMapItem],
+    root: &TagNodeRef,
+    context: &MdrContext,
+) -> Vec<DataRecord> {
+    identify_all_data_records_with_index(regions, &XpathIndex::new(root), context)
+}
+
+/// Same as [`identify_all_data_records_with_context`], resolving parents through `index`
+pub fn identify_all_data_records_with_index(
+    regions: &[RegionsMapItem],
+    index: &XpathIndex,
+    context: &MdrContext,
 ) -> Vec<DataRecord> {
     let mut all_records: Vec<DataRecord> = Vec::new();
     let mut processed_region_keys: HashSet<String> = HashSet::new();
 
     for region_item in regions {
         let parent_xpath = &region_item.parent_xpath;
-        let parent_node = match get_node_by_xpath(root, parent_xpath) {
+        let parent_node = match index.get(parent_xpath) {
             Some(node) => node,
             None => {
                 continue;
             }
         };
 
-        let children = get_children(&parent_node);
+        let children = get_children(parent_node);
 
         let sorted_regions = {
             let mut regs = region_item.regions.clone();
@@ -182,38 +215,42 @@ pub fn identify_all_data_records_with_tree(
                         &[]
                     };
 
-                    if !current_gns.is_empty() && !next_gns.is_empty() {
-                        if get_normalized_edit_distance_sequences(current_gns, next_gns) > t {
-                            if would_produce_non_contiguous(current_gns, t)
-                                && would_produce_non_contiguous(next_gns, t)
-                            {
-                                merged = true;
-                                let mut merged_records_non_contiguous: Vec<DataRecord> = Vec::new();
-                                let components: Vec<TagNodeRef> =
-                                    current_gns.iter().chain(next_gns.iter()).cloned().collect();
-
-                                let num_components = components.len();
-                                if num_components > 0 {
-                                    let child_count = get_children(&components[0]).len();
-                                    for c_idx in 0..child_count {
-                                        let mut record_group: Vec<TagNodeRef> = Vec::new();
-                                        for comp_idx in 0..num_components {
-                                            let comp_children = get_children(&components[comp_idx]);
-                                            if c_idx < comp_children.len() {
-                                                record_group.push(comp_children[c_idx].clone());
-                                            }
-                                        }
-                                        if !record_group.is_empty() {
-                                            merged_records_non_contiguous
-                                                .push(DataRecord::Multi(record_group));
-                                        }
+                    if !current_gns.is_empty()
+                        && !next_gns.is_empty()
+                        && get_normalized_edit_distance_sequences_within(
+                            current_gns,
+                            next_gns,
+                            context,
+                        )
+                        .is_none()
+                        && would_produce_non_contiguous(current_gns, context)
+                        && would_produce_non_contiguous(next_gns, context)
+                    {
+                        merged = true;
+                        let mut merged_records_non_contiguous: Vec<DataRecord> = Vec::new();
+                        let components: Vec<TagNodeRef> =
+                            current_gns.iter().chain(next_gns.iter()).cloned().collect();
+
+                        let num_components = components.len();
+                        if num_components > 0 {
+                            let child_count = get_children(&components[0]).len();
+                            for c_idx in 0..child_count {
+                                let mut record_group: Vec<TagNodeRef> = Vec::new();
+                                for component in &components {
+                                    let comp_children = get_children(component);
+                                    if c_idx < comp_children.len() {
+                                        record_group.push(comp_children[c_idx].clone());
                                     }
                                 }
-                                all_records.extend(merged_records_non_contiguous);
-                                processed_region_keys
-                                    .insert(format!("{}-{}", parent_node.tag_name, next_start_idx));
+                                if !record_group.is_empty() {
+                                    merged_records_non_contiguous
+                                        .push(DataRecord::Multi(record_group));
+                                }
                             }
                         }
+                        all_records.extend(merged_records_non_contiguous);
+                        processed_region_keys
+                            .insert(format!("{}-{}", parent_node.tag_name, next_start_idx));
                     }
                 }
             }
@@ -241,11 +278,10 @@ pub fn identify_all_data_records_with_tree(
                 }
 
                 let identified_records: Vec<DataRecord> = if gn_length == 1 {
-                    let records = find_records1(&generalized_node_components[0], t);
+                    let records = find_records1(&generalized_node_components[0], context);
                     records.into_iter().map(DataRecord::Single).collect()
                 } else {
-                    let records = find_records_n(generalized_node_components, t);
-                    records
+                    find_records_n(generalized_node_components, context)
                 };
 
                 all_records.extend(identified_records);
@@ -262,6 +298,35 @@ pub fn find_orphan_records(
     regions: &[RegionsMapItem],
     t: f32,
     root: &TagNodeRef,
+) -> Vec<TagNodeRef> {
+    find_orphan_records_with_options(regions, t, root, &MdrOptions::default())
+}
+
+/// Same as [`find_orphan_records`] with explicit similarity options
+pub fn find_orphan_records_with_options(
+    regions: &[RegionsMapItem],
+    t: f32,
+    root: &TagNodeRef,
+    options: &MdrOptions,
+) -> Vec<TagNodeRef> {
+    let context = MdrContext::global(1, t, options.clone());
+    find_orphan_records_with_context(regions, root, &context)
+}
+
+/// Same as [`find_orphan_records`] with threshold, options and cache from `context`
+pub fn find_orphan_records_with_context(
+    regions: &[RegionsMapItem],
+    root: &TagNodeRef,
+    context: &MdrContext,
+) -> Vec<TagNodeRef> {
+    find_orphan_records_with_index(regions, &XpathIndex::new(root), context)
+}
+
+/// Same as [`find_orphan_records_with_context`], resolving parents through `index`
+pub fn find_orphan_records_with_index(
+    regions: &[RegionsMapItem],
+    index: &XpathIndex,
+    context: &MdrContext,
 ) -> Vec<TagNodeRef> {
     // Use HashSet to track unique nodes by xpath (mimics TypeScript Set behavior)
     let mut found_orphans_set = HashSet::new();
@@ -272,12 +337,12 @@ pub fn find_orphan_records(
             continue;
         }
 
-        let parent_node = match get_node_by_xpath(root, &region_item.parent_xpath) {
+        let parent_node = match index.get(&region_item.parent_xpath) {
             Some(node) => node,
             None => continue,
         };
 
-        let children = get_children(&parent_node);
+        let children = get_children(parent_node);
         let n = children.len();
         let mut covered_indices = HashSet::new();
 
@@ -320,32 +385,34 @@ pub fn find_orphan_records(
             // Compare children of orphan (exactly like TypeScript)
             for orphan_child in get_children(orphan_node) {
                 let orphan_child_string = flatten_subtree(&orphan_child);
-                if !orphan_child_string.is_empty() {
-                    if get_normalized_edit_distance_sequences(
-                        &[orphan_child.clone()],
-                        &[representative_record_node.clone()],
-                    ) <= t
-                    {
-                        // Only add if not already present (mimics Set behavior)
-                        if found_orphans_set.insert(orphan_child.xpath.clone()) {
-                            found_orphans.push(orphan_child);
-                        }
+                if !orphan_child_string.is_empty()
+                    && get_normalized_edit_distance_sequences_within(
+                        std::slice::from_ref(&orphan_child),
+                        std::slice::from_ref(representative_record_node),
+                        context,
+                    )
+                    .is_some()
+                {
+                    // Only add if not already present (mimics Set behavior)
+                    if found_orphans_set.insert(orphan_child.xpath.clone()) {
+                        found_orphans.push(orphan_child);
                     }
                 }
             }
 
             // Compare the orphan node itself (exactly like TypeScript)
             let orphan_node_string = flatten_subtree(orphan_node);
-            if !orphan_node_string.is_empty() {
-                if get_normalized_edit_distance_sequences(
-                    &[orphan_node.clone()],
-                    &[representative_record_node.clone()],
-                ) <= t
-                {
-                    // Only add if not already present (mimics Set behavior)
-                    if found_orphans_set.insert(orphan_node.xpath.clone()) {
-                        found_orphans.push(orphan_node.clone());
-                    }
+            if !orphan_node_string.is_empty()
+                && get_normalized_edit_distance_sequences_within(
+                    std::slice::from_ref(orphan_node),
+                    std::slice::from_ref(representative_record_node),
+                    context,
+                )
+                .is_some()
+            {
+                // Only add if not already present (mimics Set behavior)
+                if found_orphans_set.insert(orphan_node.xpath.clone()) {
+                    found_orphans.push(orphan_node.clone());
                 }
             }
         }
@@ -353,3 +420,192 @@ pub fn find_orphan_records(
 
     found_orphans
 }
+
+/// Regions whose parent xpath cannot be resolved in the indexed tree.
+///
+/// Record and orphan identification skip these silently (as the TypeScript
+/// implementation does); this reports them as [`MdrError::MissingXpath`] warnings.
+pub fn missing_region_parents(regions: &[RegionsMapItem], index: &XpathIndex) -> Vec<MdrError> {
+    let mut seen: HashSet<&str> = HashSet::new();
+    regions
+        .iter()
+        .filter(|item| !item.regions.is_empty())
+        .filter(|item| seen.insert(item.parent_xpath.as_str()))
+        .filter(|item| !index.contains(&item.parent_xpath))
+        .map(|item| MdrError::MissingXpath {
+            xpath: item.parent_xpath.clone(),
+        })
+        .collect()
+}
+
+/// Combines records and orphans into the final record list.
+///
+/// With [`OrphanMerge::Dedup`] duplicate records (same xpaths) are kept once, and an
+/// orphan is dropped if it is a node of any record (`Single` or `Multi`), repeats an
+/// earlier orphan, or lies inside a record node. Record nodes that contain nodes of
+/// other records (say a `Single` record of `/html[1]/body[1]`) are containers rather
+/// than records of their descendants and absorb no orphans. With
+/// [`OrphanMerge::Xpath`] every record is kept and an orphan is appended unless its
+/// xpath is that of a `Single` record.
+pub fn assemble_final_records(
+    records: &[DataRecord],
+    orphans: &[TagNodeRef],
+    merge: OrphanMerge,
+) -> Vec<DataRecord> {
+    if merge == OrphanMerge::Xpath {
+        let single_xpaths: HashSet<&str> = records
+            .iter()
+            .filter_map(|record| match record {
+                DataRecord::Single(node) => Some(node.xpath.as_str()),
+                DataRecord::Multi(_) => None,
+            })
+            .collect();
+        let mut final_records = records.to_vec();
+        final_records.extend(
+            orphans
+                .iter()
+                .filter(|orphan| !single_xpaths.contains(orphan.xpath.as_str()))
+                .map(|orphan| DataRecord::Single(Arc::clone(orphan))),
+        );
+        return final_records;
+    }
+
+    let mut final_records: Vec<DataRecord> = Vec::with_capacity(records.len() + orphans.len());
+    let mut seen_records: HashSet<Vec<&str>> = HashSet::new();
+    let mut record_nodes: HashSet<&str> = HashSet::new();
+    for record in records {
+        let nodes: &[TagNodeRef] = match record {
+            DataRecord::Single(node) => std::slice::from_ref(node),
+            DataRecord::Multi(nodes) => nodes,
+        };
+        let key: Vec<&str> = nodes.iter().map(|node| node.xpath.as_str()).collect();
+        if seen_records.insert(key) {
+            record_nodes.extend(nodes.iter().map(|node| node.xpath.as_str()));
+            final_records.push(record.clone());
+        }
+    }
+
+    let containers: HashSet<&str> = record_nodes
+        .iter()
+        .flat_map(|xpath| xpath_ancestors(xpath))
+        .collect();
+    let absorbs = |xpath: &str| record_nodes.contains(xpath) && !containers.contains(xpath);
+    let mut seen_orphans: HashSet<&str> = HashSet::new();
+    for orphan in orphans {
+        let xpath = orphan.xpath.as_str();
+        if record_nodes.contains(xpath)
+            || xpath_ancestors(xpath).any(absorbs)
+            || !seen_orphans.insert(xpath)
+        {
+            continue;
+        }
+        final_records.push(DataRecord::Single(Arc::clone(orphan)));
+    }
+
+    final_records
+}
+
+/// Proper ancestors of `xpath`, nearest first
+fn xpath_ancestors(xpath: &str) -> impl Iterator<Item = &str> {
+    std::iter::successors(Some(xpath), |current| {
+        current
+            .rsplit_once('/')
+            .map(|(parent, _)| parent)
+            .filter(|parent| !parent.is_empty())
+    })
+    .skip(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TagNode;
+
+    fn node(xpath: &str) -> TagNodeRef {
+        Arc::new(TagNode::new("div".to_string(), xpath.to_string()))
+    }
+
+    #[test]
+    fn assemble_final_records_merges_orphans_by_xpath_or_overlap() {
+        let records = vec![
+            DataRecord::Single(node("/html[1]/body[1]/ul[1]/li[1]")),
+            DataRecord::Multi(vec![
+                node("/html[1]/body[1]/table[1]/tr[1]"),
+                node("/html[1]/body[1]/table[1]/tr[2]"),
+            ]),
+            DataRecord::Single(node("/html[1]/body[1]/ul[1]/li[1]")),
+            DataRecord::Single(node("/html[1]/body[1]")), // container of the others
+        ];
+        let orphans = vec![
+            node("/html[1]/body[1]/ul[1]/li[1]"), // same as a Single record
+            node("/html[1]/body[1]/table[1]/tr[2]"), // inside a Multi record
+            node("/html[1]/body[1]/ul[1]/li[1]/a[1]"), // descendant of a record
+            node("/html[1]/body[1]/table[1]"),    // ancestor of a record
+            node("/html[1]/body[1]/div[3]"),      // only inside the container
+            node("/html[1]/body[1]/div[3]"),      // repeated orphan
+            node("/html[1]/body[1]/div[30]"),     // prefix-alike sibling, new
+        ];
+
+        let first_xpaths = |merge| -> Vec<String> {
+            assemble_final_records(&records, &orphans, merge)
+                .iter()
+                .map(|record| match record {
+                    DataRecord::Single(n) => n.xpath.clone(),
+                    DataRecord::Multi(ns) => ns[0].xpath.clone(),
+                })
+                .collect()
+        };
+
+        // Xpath: only the orphan equal to a Single record is dropped
+        assert_eq!(
+            first_xpaths(OrphanMerge::Xpath),
+            vec![
+                "/html[1]/body[1]/ul[1]/li[1]",
+                "/html[1]/body[1]/table[1]/tr[1]",
+                "/html[1]/body[1]/ul[1]/li[1]",
+                "/html[1]/body[1]",
+                "/html[1]/body[1]/table[1]/tr[2]",
+                "/html[1]/body[1]/ul[1]/li[1]/a[1]",
+                "/html[1]/body[1]/table[1]",
+                "/html[1]/body[1]/div[3]",
+                "/html[1]/body[1]/div[3]",
+                "/html[1]/body[1]/div[30]",
+            ]
+        );
+        assert_eq!(
+            first_xpaths(OrphanMerge::Dedup),
+            vec![
+                "/html[1]/body[1]/ul[1]/li[1]",
+                "/html[1]/body[1]/table[1]/tr[1]",
+                "/html[1]/body[1]",
+                "/html[1]/body[1]/table[1]",
+                "/html[1]/body[1]/div[3]",
+                "/html[1]/body[1]/div[30]",
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_region_parents_reports_each_xpath_once() {
+        let mut body = TagNode::new("body".to_string(), "/html[1]/body[1]".to_string());
+        body.add_child(node("/html[1]/body[1]/div[1]"));
+        let root = Arc::new(body);
+
+        let item = |xpath: &str| RegionsMapItem {
+            parent_xpath: xpath.to_string(),
+            regions: vec![(1, 0, 2)],
+        };
+        let regions = vec![
+            item("/html[1]/body[1]"),
+            item("/html[1]/body[1]/ul[1]"),
+            item("/html[1]/body[1]/ul[1]"),
+        ];
+
+        assert_eq!(
+            missing_region_parents(&regions, &XpathIndex::new(&root)),
+            vec![MdrError::MissingXpath {
+                xpath: "/html[1]/body[1]/ul[1]".to_string()
+            }]
+        );
+    }
+}
@@ -0,0 +1,307 @@
+use crate::distance_cache::{
+    DistanceCache, DistanceCacheConfig, DistanceCacheStats, DISTANCE_CACHE,
+};
+use crate::error::{validate_parameters, MdrError};
+use crate::html_parser::parse_page_html;
+use crate::limits::{CancellationToken, Clock, MdrLimits, RunBudget};
+use crate::mdr_algorithm::run_mdr_algorithm_with_context;
+use crate::record_extraction::{
+    assemble_final_records, find_orphan_records_with_index, identify_all_data_records_with_index,
+    missing_region_parents,
+};
+use crate::trace::ParentTrace;
+use crate::types::{MdrFullOutput, MdrOptions, TagNodeRef};
+use crate::xpath_index::XpathIndex;
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Everything one MDR run needs: parameters, similarity options and the distance
+/// cache to memoize into. Threaded through `mdr_algorithm`, `record_extraction`
+/// and `similarity` in place of separate `k`/`t`/`options` arguments.
+pub struct MdrContext {
+    pub k: usize,
+    pub t: f32,
+    pub options: MdrOptions,
+    cache: Arc<DistanceCache>,
+    comparisons: AtomicU64,
+    trace: Option<Mutex<Vec<ParentTrace>>>,
+    budget: RunBudget,
+}
+
+impl MdrContext {
+    /// Context with its own private cache (default capacity)
+    pub fn new(k: usize, t: f32, options: MdrOptions) -> Self {
+        let cache = Arc::new(DistanceCache::new(DistanceCacheConfig::default()));
+        Self::with_cache(k, t, options, cache)
+    }
+
+    /// Context memoizing into the process-wide [`DISTANCE_CACHE`], as the plain
+    /// `(k, t)` entry points do
+    pub fn global(k: usize, t: f32, options: MdrOptions) -> Self {
+        Self::with_cache(k, t, options, Arc::clone(&DISTANCE_CACHE))
+    }
+
+    pub fn with_cache(k: usize, t: f32, options: MdrOptions, cache: Arc<DistanceCache>) -> Self {
+        MdrContext {
+            k,
+            t,
+            options,
+            cache,
+            comparisons: AtomicU64::new(0),
+            trace: None,
+            budget: RunBudget::default(),
+        }
+    }
+
+    /// Bounds every region search run through this context; see
+    /// [`MdrContext::truncation`]
+    pub fn with_limits(mut self, limits: MdrLimits) -> Self {
+        self.budget.limits = limits;
+        self
+    }
+
+    /// Time source for `MdrLimits::time_budget_ms` (default `SystemClock`)
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.budget.clock = clock;
+        self
+    }
+
+    /// Stops the region search once `token` is cancelled
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.budget.cancellation = Some(token);
+        self
+    }
+
+    pub fn limits(&self) -> &MdrLimits {
+        &self.budget.limits
+    }
+
+    /// Why the last region search stopped early, if it did
+    pub fn truncation(&self) -> Option<MdrError> {
+        self.budget.truncation()
+    }
+
+    /// Resets the budget; limits apply to each run separately
+    pub(crate) fn begin_run(&self) {
+        self.budget.begin(self.comparisons());
+    }
+
+    /// Whether the region search must stop (limit hit or cancelled)
+    pub(crate) fn should_stop(&self) -> bool {
+        self.budget.should_stop(self.comparisons())
+    }
+
+    /// Whether the region search may visit a node at `depth`
+    pub(crate) fn enter_node(&self, depth: usize) -> bool {
+        self.budget.enter_node(depth, self.comparisons())
+    }
+
+    /// Records every `ident_drs` decision, see [`MdrContext::take_trace`]
+    pub fn with_trace(mut self) -> Self {
+        self.trace = Some(Mutex::new(Vec::new()));
+        self
+    }
+
+    pub fn is_tracing(&self) -> bool {
+        self.trace.is_some()
+    }
+
+    /// Returns the decisions recorded so far and starts a new trace
+    pub fn take_trace(&self) -> Vec<ParentTrace> {
+        self.trace
+            .as_ref()
+            .map(|trace| std::mem::take(&mut *trace.lock()))
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn record_trace(&self, parent: ParentTrace) {
+        if let Some(trace) = &self.trace {
+            trace.lock().push(parent);
+        }
+    }
+
+    pub fn cache(&self) -> &DistanceCache {
+        &self.cache
+    }
+
+    /// Number of distance requests (cached or not) made through this context
+    pub fn comparisons(&self) -> u64 {
+        self.comparisons.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn count_comparison(&self) {
+        self.comparisons.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Regions → records → orphans → final records for one tree
+pub fn run_mdr_pipeline(root: &TagNodeRef, context: &MdrContext) -> MdrFullOutput {
+    let regions = run_mdr_algorithm_with_context(root, context);
+    let index = XpathIndex::new(root);
+    let records = identify_all_data_records_with_index(&regions, &index, context);
+    let orphans = find_orphan_records_with_index(&regions, &index, context);
+    let final_records = assemble_final_records(&records, &orphans, context.options.orphan_merge);
+    let mut warnings = missing_region_parents(&regions, &index);
+    let truncation = context.truncation();
+    let truncated = truncation.is_some();
+    warnings.extend(truncation);
+
+    MdrFullOutput {
+        regions,
+        records,
+        orphans,
+        final_records,
+        warnings,
+        truncated,
+    }
+}
+
+/// Options of a single pipeline run: similarity options plus resource limits
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MdrRunOptions {
+    #[serde(flatten)]
+    pub mdr: MdrOptions,
+    pub limits: MdrLimits,
+}
+
+/// Configuration of an [`MdrSession`]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MdrSessionConfig {
+    pub k: usize,
+    pub t: f32,
+    #[serde(flatten)]
+    pub mdr: MdrOptions,
+    pub cache: DistanceCacheConfig,
+    /// Applied to every page separately
+    pub limits: MdrLimits,
+}
+
+impl Default for MdrSessionConfig {
+    fn default() -> Self {
+        MdrSessionConfig {
+            k: 10,
+            t: 0.3,
+            mdr: MdrOptions::default(),
+            cache: DistanceCacheConfig::default(),
+            limits: MdrLimits::default(),
+        }
+    }
+}
+
+/// Cumulative statistics of an [`MdrSession`]
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MdrSessionStats {
+    pub pages: u64,
+    pub regions: u64,
+    pub records: u64,
+    pub comparisons: u64,
+    pub cache: DistanceCacheStats,
+}
+
+#[derive(Default)]
+struct PageCounters {
+    pages: u64,
+    regions: u64,
+    records: u64,
+}
+
+/// Long-lived MDR state: configuration plus an isolated distance cache and statistics.
+///
+/// Reuse one session across pages of the same site to keep the cache warm; separate
+/// sessions never share cache entries, so they can process pages concurrently.
+pub struct MdrSession {
+    context: MdrContext,
+    counters: Mutex<PageCounters>,
+}
+
+impl MdrSession {
+    pub fn new(config: MdrSessionConfig) -> Result<Self, MdrError> {
+        validate_parameters(config.k, config.t)?;
+        let cache = Arc::new(DistanceCache::new(config.cache));
+        Ok(MdrSession {
+            context: MdrContext::with_cache(config.k, config.t, config.mdr, cache)
+                .with_limits(config.limits),
+            counters: Mutex::new(PageCounters::default()),
+        })
+    }
+
+    pub fn context(&self) -> &MdrContext {
+        &self.context
+    }
+
+    pub fn run(&self, root: &TagNodeRef) -> MdrFullOutput {
+        let output = run_mdr_pipeline(root, &self.context);
+
+        let mut counters = self.counters.lock();
+        counters.pages += 1;
+        counters.regions += output
+            .regions
+            .iter()
+            .map(|item| item.regions.len() as u64)
+            .sum::<u64>();
+        counters.records += output.final_records.len() as u64;
+        output
+    }
+
+    pub fn run_html(&self, html: &str) -> MdrFullOutput {
+        self.run(&parse_page_html(html))
+    }
+
+    pub fn stats(&self) -> MdrSessionStats {
+        let counters = self.counters.lock();
+        MdrSessionStats {
+            pages: counters.pages,
+            regions: counters.regions,
+            records: counters.records,
+            comparisons: self.context.comparisons(),
+            cache: self.context.cache().stats(),
+        }
+    }
+
+    /// Drops cached distances; statistics are kept
+    pub fn clear_cache(&self) {
+        self.context.cache().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAGE: &str = "<html><body><ul>\
+        <li><a>One</a><span>1</span></li>\
+        <li><a>Two</a><span>2</span></li>\
+        <li><a>Three</a><span>3</span></li>\
+        </ul></body></html>";
+
+    #[test]
+    fn sessions_have_isolated_warm_caches() {
+        let first = MdrSession::new(MdrSessionConfig::default()).unwrap();
+        let second = MdrSession::new(MdrSessionConfig::default()).unwrap();
+
+        let cold = first.run_html(PAGE);
+        let misses = first.stats().cache.misses;
+        let warm = first.run_html(PAGE);
+
+        let stats = first.stats();
+        assert_eq!(stats.pages, 2);
+        assert_eq!(stats.cache.misses, misses); // second page fully served from cache
+        assert!(stats.cache.hits > 0);
+        assert_eq!(cold.final_records.len(), warm.final_records.len());
+        let noisy = first.run_html(&PAGE.replace("<li>", "<li><!-- x --><script>1</script>"));
+        assert_eq!(noisy.regions.len(), cold.regions.len());
+        assert_eq!(noisy.final_records.len(), cold.final_records.len());
+        assert_eq!(second.stats().cache.entries, 0);
+
+        let invalid = MdrSessionConfig {
+            k: 0,
+            ..MdrSessionConfig::default()
+        };
+        assert!(MdrSession::new(invalid).is_err());
+    }
+}
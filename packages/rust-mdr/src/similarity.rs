@@ -1,17 +1,115 @@
-use crate::tree_utils::flatten_subtree;
-use crate::types::TagNodeRef;
-use dashmap::DashMap;
+use crate::distance_cache::{DistanceKey, DistanceScope};
+use crate::session::MdrContext;
+use crate::tree_distance::{SimpleTreeMatching, TreeEditDistance};
+use crate::tree_utils::{
+    flatten_subtree_tokens, flatten_subtree_with_options, structural_hash,
+    structural_sequence_hash, LabelIds,
+};
+use crate::types::{MdrOptions, SimilarityMetric, TagNodeRef};
 use levenshtein::levenshtein;
-use once_cell::sync::Lazy;
-use std::sync::Arc;
 
 #[cfg(feature = "parallel")]
 use rayon::prelude::*;
 
-/// Global memo: (ptr_a, ptr_b) → distance
-/// We store the lower pointer first so (a,b) == (b,a).
-pub(crate) static NODE_DIST_CACHE: Lazy<DashMap<(usize, usize), f32>> =
-    Lazy::new(|| DashMap::with_capacity(1_024));
+/// A normalized distance between two sequences of sibling subtrees
+pub trait SubtreeSimilarity: Sync {
+    /// Returns a value between 0.0 (identical) and 1.0 (completely different)
+    fn distance(&self, a: &[TagNodeRef], b: &[TagNodeRef], options: &MdrOptions) -> f32;
+
+    /// The distance when it is `<= bound`, `None` otherwise. Backends override this
+    /// to stop early once the bound is exceeded.
+    fn distance_within(
+        &self,
+        a: &[TagNodeRef],
+        b: &[TagNodeRef],
+        options: &MdrOptions,
+        bound: f32,
+    ) -> Option<f32> {
+        let distance = self.distance(a, b, options);
+        (distance <= bound).then_some(distance)
+    }
+}
+
+/// Levenshtein over the concatenated `flatten_subtree` strings (paper default)
+pub struct StringEditDistance;
+
+impl SubtreeSimilarity for StringEditDistance {
+    fn distance(&self, a: &[TagNodeRef], b: &[TagNodeRef], options: &MdrOptions) -> f32 {
+        let s1 = flatten_node_sequence(a, options);
+        let s2 = flatten_node_sequence(b, options);
+        let len1 = s1.len();
+        let len2 = s2.len();
+
+        if len1 > 2 * len2 || len2 > 2 * len1 {
+            return 1.0; // Consider highly dissimilar
+        }
+
+        edit_distance(&s1, &s2)
+    }
+
+    fn distance_within(
+        &self,
+        a: &[TagNodeRef],
+        b: &[TagNodeRef],
+        options: &MdrOptions,
+        bound: f32,
+    ) -> Option<f32> {
+        let s1 = flatten_node_sequence(a, options);
+        let s2 = flatten_node_sequence(b, options);
+        if s1.len() > 2 * s2.len() || s2.len() > 2 * s1.len() {
+            return (1.0 <= bound).then_some(1.0);
+        }
+
+        edit_distance_within(&s1, &s2, bound)
+    }
+}
+
+/// Levenshtein over interned tag tokens, normalized by token count
+pub struct TokenEditDistance;
+
+impl SubtreeSimilarity for TokenEditDistance {
+    fn distance(&self, a: &[TagNodeRef], b: &[TagNodeRef], options: &MdrOptions) -> f32 {
+        let mut labels = LabelIds::default();
+        let t1 = flatten_node_sequence_tokens(a, options, &mut labels);
+        let t2 = flatten_node_sequence_tokens(b, options, &mut labels);
+        let len1 = t1.len();
+        let len2 = t2.len();
+
+        if len1 > 2 * len2 || len2 > 2 * len1 {
+            return 1.0; // Consider highly dissimilar
+        }
+
+        token_edit_distance(&t1, &t2)
+    }
+
+    fn distance_within(
+        &self,
+        a: &[TagNodeRef],
+        b: &[TagNodeRef],
+        options: &MdrOptions,
+        bound: f32,
+    ) -> Option<f32> {
+        let mut labels = LabelIds::default();
+        let t1 = flatten_node_sequence_tokens(a, options, &mut labels);
+        let t2 = flatten_node_sequence_tokens(b, options, &mut labels);
+        if t1.len() > 2 * t2.len() || t2.len() > 2 * t1.len() {
+            return (1.0 <= bound).then_some(1.0);
+        }
+
+        token_edit_distance_within(&t1, &t2, bound)
+    }
+}
+
+impl SimilarityMetric {
+    pub fn backend(self) -> &'static dyn SubtreeSimilarity {
+        match self {
+            SimilarityMetric::StringEditDistance => &StringEditDistance,
+            SimilarityMetric::TokenEditDistance => &TokenEditDistance,
+            SimilarityMetric::TreeEditDistance => &TreeEditDistance,
+            SimilarityMetric::SimpleTreeMatching => &SimpleTreeMatching,
+        }
+    }
+}
 
 /// Calculates normalized edit distance between two strings using Levenshtein
 /// Returns a value between 0.0 and 1.0, where 0.0 means identical and 1.0 means completely different
@@ -25,31 +123,210 @@ pub fn edit_distance(s1: &str, s2: &str) -> f32 {
     }
 }
 
-/// Returns normalized edit distance between two nodes
-pub fn normalized_edit_distance(a: &TagNodeRef, b: &TagNodeRef) -> f32 {
-    // --- fast path: cached? ---------------------------------------------
-    let pa = Arc::as_ptr(a) as usize;
-    let pb = Arc::as_ptr(b) as usize;
-    let key = if pa <= pb { (pa, pb) } else { (pb, pa) };
-    if let Some(v) = NODE_DIST_CACHE.get(&key) {
-        return *v;
+/// Normalized Levenshtein distance between two token sequences
+pub fn token_edit_distance(t1: &[u32], t2: &[u32]) -> f32 {
+    let n = t1.len().max(t2.len());
+    if n == 0 {
+        return 0.0;
+    }
+
+    let mut prev: Vec<usize> = (0..=t2.len()).collect();
+    let mut curr = vec![0usize; t2.len() + 1];
+    for (i, a) in t1.iter().enumerate() {
+        curr[0] = i + 1;
+        for (j, b) in t2.iter().enumerate() {
+            let substitution = prev[j] + usize::from(a != b);
+            curr[j + 1] = substitution.min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[t2.len()] as f32 / n as f32
+}
+
+/// [`edit_distance`] when it is `<= t`, `None` otherwise, computed with a banded
+/// Levenshtein that stops as soon as the threshold is exceeded
+pub fn edit_distance_within(s1: &str, s2: &str, t: f32) -> Option<f32> {
+    let n = s1.len().max(s2.len());
+    if n == 0 {
+        return (0.0 <= t).then_some(0.0);
     }
+    let max = max_edits(n, t)?;
+    let d = if s1.is_ascii() && s2.is_ascii() {
+        bounded_levenshtein(s1.as_bytes(), s2.as_bytes(), max)?
+    } else {
+        let c1: Vec<char> = s1.chars().collect();
+        let c2: Vec<char> = s2.chars().collect();
+        bounded_levenshtein(&c1, &c2, max)?
+    };
+    Some(d as f32 / n as f32)
+}
 
-    // --- slow path: compute, store --------------------------------------
-    let sa = flatten_subtree(a);
-    let sb = flatten_subtree(b);
-    let d = edit_distance(&sa, &sb);
-    NODE_DIST_CACHE.insert(key, d);
-    d
+/// [`token_edit_distance`] when it is `<= t`, `None` otherwise
+pub fn token_edit_distance_within(t1: &[u32], t2: &[u32], t: f32) -> Option<f32> {
+    let n = t1.len().max(t2.len());
+    if n == 0 {
+        return (0.0 <= t).then_some(0.0);
+    }
+    let max = max_edits(n, t)?;
+    let d = bounded_levenshtein(t1, t2, max)?;
+    Some(d as f32 / n as f32)
 }
 
-/// Checks if two sibling nodes are similar based on threshold
-pub fn are_siblings_similar(a: &TagNodeRef, b: &TagNodeRef, t: f32) -> bool {
-    normalized_edit_distance(a, b) <= t
+/// Largest edit count `d` with `d as f32 / n as f32 <= t`, so thresholded results
+/// agree with the float comparison made on exact distances
+fn max_edits(n: usize, t: f32) -> Option<usize> {
+    if t.is_nan() || t < 0.0 {
+        return None;
+    }
+    let within = |d: usize| d as f32 / n as f32 <= t;
+    let mut d = ((t as f64) * n as f64).floor().min(n as f64) as usize;
+    while d < n && within(d + 1) {
+        d += 1;
+    }
+    while d > 0 && !within(d) {
+        d -= 1;
+    }
+    Some(d)
+}
+
+/// Levenshtein distance when it is `<= max`, `None` otherwise.
+///
+/// Strips the common prefix and suffix, then fills only the diagonal band of width
+/// `2 * max + 1` (Ukkonen) and gives up once a whole row exceeds `max`.
+pub fn bounded_levenshtein<T: PartialEq>(a: &[T], b: &[T], max: usize) -> Option<usize> {
+    let (a, b) = if a.len() <= b.len() { (a, b) } else { (b, a) };
+    if b.len() - a.len() > max {
+        return None;
+    }
+
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    let (n, m) = (a.len(), b.len());
+    if n == 0 {
+        return Some(m);
+    }
+
+    // Cells outside the band hold `over`, which can never come back under `max`
+    let over = max + 1;
+    let mut prev = vec![over; m + 1];
+    let mut curr = vec![over; m + 1];
+    for (j, cell) in prev.iter_mut().enumerate().take(m.min(max) + 1) {
+        *cell = j;
+    }
+
+    for i in 1..=n {
+        let lo = i.saturating_sub(max);
+        let hi = m.min(i + max);
+        let mut row_min = over;
+        if lo == 0 {
+            curr[0] = i;
+            row_min = i;
+        } else {
+            curr[lo - 1] = over;
+        }
+        for j in lo.max(1)..=hi {
+            let substitution = prev[j - 1] + usize::from(a[i - 1] != b[j - 1]);
+            let cell = substitution.min(prev[j] + 1).min(curr[j - 1] + 1).min(over);
+            curr[j] = cell;
+            row_min = row_min.min(cell);
+        }
+        if hi < m {
+            curr[hi + 1] = over;
+        }
+        if row_min > max {
+            return None;
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+
+    (prev[m] <= max).then_some(prev[m])
+}
+
+/// Returns normalized edit distance between two nodes, memoized in the context's cache
+pub fn normalized_edit_distance(a: &TagNodeRef, b: &TagNodeRef, context: &MdrContext) -> f32 {
+    let options = &context.options;
+    let attributes = &options.signature_attributes;
+    let key = DistanceKey::new(
+        options.similarity,
+        DistanceScope::Node,
+        structural_hash(a, attributes),
+        structural_hash(b, attributes),
+    );
+    context.count_comparison();
+    context
+        .cache()
+        .get_or_compute(key, || node_distance(a, b, options))
+}
+
+/// [`normalized_edit_distance`] when it is `<= context.t`, `None` otherwise; use this
+/// where only the threshold decision matters
+pub fn normalized_edit_distance_within(
+    a: &TagNodeRef,
+    b: &TagNodeRef,
+    context: &MdrContext,
+) -> Option<f32> {
+    let options = &context.options;
+    let attributes = &options.signature_attributes;
+    let key = DistanceKey::new(
+        options.similarity,
+        DistanceScope::Node,
+        structural_hash(a, attributes),
+        structural_hash(b, attributes),
+    );
+    context.count_comparison();
+    context
+        .cache()
+        .get_or_compute_within(key, context.t, |bound| {
+            node_distance_within(a, b, options, bound)
+        })
+}
+
+fn node_distance_within(
+    a: &TagNodeRef,
+    b: &TagNodeRef,
+    options: &MdrOptions,
+    bound: f32,
+) -> Option<f32> {
+    if options.similarity != SimilarityMetric::StringEditDistance {
+        return options.similarity.backend().distance_within(
+            std::slice::from_ref(a),
+            std::slice::from_ref(b),
+            options,
+            bound,
+        );
+    }
+    let sa = flatten_subtree_with_options(a, options);
+    let sb = flatten_subtree_with_options(b, options);
+    edit_distance_within(&sa, &sb, bound)
+}
+
+fn node_distance(a: &TagNodeRef, b: &TagNodeRef, options: &MdrOptions) -> f32 {
+    if options.similarity != SimilarityMetric::StringEditDistance {
+        return options.similarity.backend().distance(
+            std::slice::from_ref(a),
+            std::slice::from_ref(b),
+            options,
+        );
+    }
+    let sa = flatten_subtree_with_options(a, options);
+    let sb = flatten_subtree_with_options(b, options);
+    edit_distance(&sa, &sb)
+}
+
+/// Checks if two sibling nodes are similar based on the context threshold
+pub fn are_siblings_similar(a: &TagNodeRef, b: &TagNodeRef, context: &MdrContext) -> bool {
+    normalized_edit_distance_within(a, b, context).is_some()
 }
 
 /// Checks if all siblings in a list are similar to each other
-pub fn are_all_siblings_similar(siblings: &[TagNodeRef], t: f32) -> bool {
+pub fn are_all_siblings_similar(siblings: &[TagNodeRef], context: &MdrContext) -> bool {
     if siblings.len() < 2 {
         return true; // No comparison needed for 0 or 1 sibling
     }
@@ -59,14 +336,14 @@ pub fn are_all_siblings_similar(siblings: &[TagNodeRef], t: f32) -> bool {
         siblings.par_iter().enumerate().all(|(i, a)| {
             siblings[i + 1..]
                 .par_iter()
-                .all(|b| normalized_edit_distance(a, b) <= t)
+                .all(|b| are_siblings_similar(a, b, context))
         })
     }
     #[cfg(not(feature = "parallel"))]
     {
         for i in 0..siblings.len() - 1 {
             for j in i + 1..siblings.len() {
-                if normalized_edit_distance(&siblings[i], &siblings[j]) > t {
+                if !are_siblings_similar(&siblings[i], &siblings[j], context) {
                     return false;
                 }
             }
@@ -75,40 +352,138 @@ pub fn are_all_siblings_similar(siblings: &[TagNodeRef], t: f32) -> bool {
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn edit_distance_zero_length() {
-        assert_eq!(edit_distance("", ""), 0.0);
-        assert_eq!(edit_distance("", "abc"), 1.0);
-        assert_eq!(edit_distance("abc", ""), 1.0);
-    }
-}
-
 /// Flattens a sequence of nodes and returns the concatenated string
-pub fn flatten_node_sequence(nodes: &[TagNodeRef]) -> String {
+pub fn flatten_node_sequence(nodes: &[TagNodeRef], options: &MdrOptions) -> String {
     nodes
         .iter()
-        .map(|node| flatten_subtree(node))
+        .map(|node| flatten_subtree_with_options(node, options))
         .collect::<Vec<_>>()
         .join("")
 }
 
-/// Returns normalized edit distance between two node sequences
+/// Flattens a sequence of nodes into one token sequence, numbering attribute labels
+/// in `labels`
+pub fn flatten_node_sequence_tokens(
+    nodes: &[TagNodeRef],
+    options: &MdrOptions,
+    labels: &mut LabelIds,
+) -> Vec<u32> {
+    nodes
+        .iter()
+        .flat_map(|node| flatten_subtree_tokens(node, &options.signature_attributes, labels))
+        .collect()
+}
+
+/// Returns normalized distance between two node sequences using the configured backend,
+/// memoized in the context's cache
 pub fn get_normalized_edit_distance_sequences(
     node_seq1: &[TagNodeRef],
     node_seq2: &[TagNodeRef],
+    context: &MdrContext,
 ) -> f32 {
-    let s1 = flatten_node_sequence(node_seq1);
-    let s2 = flatten_node_sequence(node_seq2);
-    let len1 = s1.len();
-    let len2 = s2.len();
+    let options = &context.options;
+    let attributes = &options.signature_attributes;
+    let key = DistanceKey::new(
+        options.similarity,
+        DistanceScope::Sequence,
+        structural_sequence_hash(node_seq1, attributes),
+        structural_sequence_hash(node_seq2, attributes),
+    );
+    context.count_comparison();
+    context.cache().get_or_compute(key, || {
+        options
+            .similarity
+            .backend()
+            .distance(node_seq1, node_seq2, options)
+    })
+}
+
+/// [`get_normalized_edit_distance_sequences`] when it is `<= context.t`, `None`
+/// otherwise; use this where only the threshold decision matters
+pub fn get_normalized_edit_distance_sequences_within(
+    node_seq1: &[TagNodeRef],
+    node_seq2: &[TagNodeRef],
+    context: &MdrContext,
+) -> Option<f32> {
+    let options = &context.options;
+    let attributes = &options.signature_attributes;
+    let key = DistanceKey::new(
+        options.similarity,
+        DistanceScope::Sequence,
+        structural_sequence_hash(node_seq1, attributes),
+        structural_sequence_hash(node_seq2, attributes),
+    );
+    context.count_comparison();
+    context
+        .cache()
+        .get_or_compute_within(key, context.t, |bound| {
+            options
+                .similarity
+                .backend()
+                .distance_within(node_seq1, node_seq2, options, bound)
+        })
+}
 
-    if len1 > 2 * len2 || len2 > 2 * len1 {
-        return 1.0; // Consider highly dissimilar
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distance_zero_length() {
+        assert_eq!(edit_distance("", ""), 0.0);
+        assert_eq!(edit_distance("", "abc"), 1.0);
+        assert_eq!(edit_distance("abc", ""), 1.0);
     }
 
-    edit_distance(&s1, &s2)
+    #[test]
+    fn edit_distance_within_agrees_with_exact_distance() {
+        // Deterministic pseudo-random strings over a small alphabet, plus non-ASCII
+        let mut seed = 0x2545_f491_u32;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 17;
+            seed ^= seed << 5;
+            seed
+        };
+        let alphabet = ['a', 'b', 'c', 'd', 'é'];
+        for _ in 0..500 {
+            let mut random = || -> String {
+                let len = (next() % 24) as usize;
+                (0..len).map(|_| alphabet[(next() % 5) as usize]).collect()
+            };
+            let (s1, s2) = (random(), random());
+            for t in [0.0, 0.1, 0.3, 0.5, 1.0] {
+                let exact = edit_distance(&s1, &s2);
+                let expected = (exact <= t).then_some(exact);
+                assert_eq!(
+                    edit_distance_within(&s1, &s2, t),
+                    expected,
+                    "{s1:?} {s2:?} {t}"
+                );
+            }
+        }
+        assert_eq!(bounded_levenshtein(b"kitten", b"sitting", 3), Some(3));
+        assert_eq!(bounded_levenshtein(b"kitten", b"sitting", 2), None);
+    }
+
+    #[test]
+    fn token_edit_distance_ignores_tag_name_length() {
+        use crate::types::TagNode;
+        use std::sync::Arc;
+
+        let wrap = |inner: &str| {
+            let mut div = TagNode::new("div".to_string(), String::new());
+            div.add_child(Arc::new(TagNode::new(inner.to_string(), String::new())));
+            Arc::new(div)
+        };
+        let options = MdrOptions::default();
+        let li = [wrap("li")];
+        let article = [wrap("article")];
+        let b = [wrap("b")];
+
+        assert_eq!(TokenEditDistance.distance(&li, &li, &options), 0.0);
+        assert_eq!(TokenEditDistance.distance(&li, &article, &options), 0.5);
+        assert_eq!(TokenEditDistance.distance(&li, &b, &options), 0.5);
+        assert_eq!(token_edit_distance(&[], &[]), 0.0);
+    }
 }
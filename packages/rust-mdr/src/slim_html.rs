@@ -0,0 +1,365 @@
+use crate::html_parser::document_element;
+use crate::types::TagNode;
+use html5ever::driver::ParseOpts;
+use html5ever::tendril::TendrilSink;
+use html5ever::tree_builder::TreeBuilderOpts;
+use html5ever::{local_name, namespace_url, ns, parse_document, LocalName, QualName};
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+
+/// Port of `slimHtml` from `@wordbricks/next-eval`, the "slim" LLM input format.
+///
+/// Removes `script`, `style`, `link` and `meta` elements without a `charset`,
+/// serializes the document element, strips comments and collapses whitespace on the
+/// markup string, then re-parses it and drops every attribute. Documents are parsed
+/// with scripting disabled, as `DOMParser` does, so `noscript` content is kept as
+/// markup; serialization follows the HTML fragment serialization algorithm
+/// (`outerHTML`).
+pub fn slim_html(html: &str) -> String {
+    let dom = parse_dom(html);
+    let Some(root) = document_element(&dom) else {
+        return String::new();
+    };
+    remove_elements(&root);
+
+    let cleaned = collapse_whitespace(&strip_comments(&outer_html(&root)));
+
+    let dom = parse_dom(&cleaned);
+    let Some(root) = document_element(&dom) else {
+        return String::new();
+    };
+    remove_attributes(&root);
+    outer_html(&root)
+}
+
+/// Slim markup of a tag tree, e.g. a subtree or a reduced copy of
+/// `parse_html(&slim_html(html))`.
+///
+/// Attributes, `script` and `style` are left out as in [`slim_html`]. The tag tree
+/// keeps texts trimmed, so a text is separated from its siblings by one space, which
+/// is how `slim_html` leaves `Hello <b>world</b> again`.
+pub fn tag_tree_html(root: &TagNode) -> String {
+    let mut out = String::new();
+    write_tag_node(root, &mut out);
+    out
+}
+
+fn write_tag_node(node: &TagNode, out: &mut String) {
+    if matches!(node.tag_name.as_str(), "script" | "style") {
+        return;
+    }
+    out.push('<');
+    out.push_str(&node.tag_name);
+    out.push('>');
+    if VOID_ELEMENTS.contains(&node.tag_name.as_str()) {
+        return;
+    }
+
+    let last = node.children.len().saturating_sub(1);
+    for (i, child) in node.children.iter().enumerate() {
+        if child.tag_name != "text" {
+            write_tag_node(child, out);
+            continue;
+        }
+        let text = child.raw_text.as_deref().unwrap_or("");
+        if text.is_empty() {
+            continue;
+        }
+        if i > 0 {
+            out.push(' ');
+        }
+        escape_into(text, false, out);
+        if i < last {
+            out.push(' ');
+        }
+    }
+
+    out.push_str("</");
+    out.push_str(&node.tag_name);
+    out.push('>');
+}
+
+const VOID_ELEMENTS: &[&str] = &[
+    "area", "base", "basefont", "bgsound", "br", "col", "embed", "frame", "hr", "img", "input",
+    "keygen", "link", "meta", "param", "source", "track", "wbr",
+];
+
+fn parse_dom(html: &str) -> RcDom {
+    let opts = ParseOpts {
+        tree_builder: TreeBuilderOpts {
+            scripting_enabled: false,
+            ..TreeBuilderOpts::default()
+        },
+        ..ParseOpts::default()
+    };
+    parse_document(RcDom::default(), opts).one(html)
+}
+
+/// Whether `querySelectorAll("script"|"style"|"link"|"meta")` would match and
+/// `slimHtml` remove the element (type selectors match any namespace)
+fn is_removed(handle: &Handle) -> bool {
+    let NodeData::Element { name, attrs, .. } = &handle.data else {
+        return false;
+    };
+    match name.local {
+        local_name!("script") | local_name!("style") | local_name!("link") => true,
+        // `getAttribute("charset") || null`: an empty charset counts as missing
+        local_name!("meta") => !attrs.borrow().iter().any(|attr| {
+            attr.name.ns == ns!() && &*attr.name.local == "charset" && !attr.value.is_empty()
+        }),
+        _ => false,
+    }
+}
+
+fn remove_elements(handle: &Handle) {
+    handle
+        .children
+        .borrow_mut()
+        .retain(|child| !is_removed(child));
+    for child in handle.children.borrow().iter() {
+        remove_elements(child);
+    }
+}
+
+fn remove_attributes(handle: &Handle) {
+    if let NodeData::Element { attrs, .. } = &handle.data {
+        attrs.borrow_mut().clear();
+    }
+    for child in handle.children.borrow().iter() {
+        remove_attributes(child);
+    }
+}
+
+/// `.replace(/<!--[\s\S]*?-->/g, "")`
+fn strip_comments(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut pos = 0;
+    while let Some(start) = html[pos..].find("<!--").map(|i| pos + i) {
+        let Some(end) = html[start + 4..].find("-->").map(|i| start + 4 + i + 3) else {
+            break;
+        };
+        out.push_str(&html[pos..start]);
+        pos = end;
+    }
+    out.push_str(&html[pos..]);
+    out
+}
+
+/// JavaScript's `\s` (and `String.prototype.trim`) character class
+fn is_js_whitespace(c: char) -> bool {
+    matches!(
+        c,
+        '\t'..='\r' | ' ' | '\u{A0}' | '\u{1680}' | '\u{2000}'..='\u{200A}'
+    ) || matches!(
+        c,
+        '\u{2028}' | '\u{2029}' | '\u{202F}' | '\u{205F}' | '\u{3000}' | '\u{FEFF}'
+    )
+}
+
+/// The `slimHtml` whitespace passes in one scan:
+/// `/\n\s*\n/g → "\n"`, `/>\s+</g → "><"`, `/\s+/g → " "`, then `trim()`.
+///
+/// Every pass maps a whitespace run to a (possibly empty) whitespace run, so a run
+/// ends up removed when it sits between `>` and `<` and as one space otherwise.
+fn collapse_whitespace(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut chars = html.chars().peekable();
+    let mut previous = None;
+    while let Some(c) = chars.next() {
+        if !is_js_whitespace(c) {
+            out.push(c);
+            previous = Some(c);
+            continue;
+        }
+        while chars.peek().copied().is_some_and(is_js_whitespace) {
+            chars.next();
+        }
+        let between_tags = previous == Some('>') && chars.peek() == Some(&'<');
+        if !between_tags {
+            out.push(' ');
+        }
+        previous = Some(' ');
+    }
+    out.trim_matches(' ').to_string()
+}
+
+/// `element.outerHTML`
+fn outer_html(handle: &Handle) -> String {
+    let mut out = String::new();
+    serialize_node(handle, None, &mut out);
+    out
+}
+
+fn is_html(name: &QualName, local: &LocalName) -> bool {
+    name.ns == ns!(html) && name.local == *local
+}
+
+fn serialize_node(handle: &Handle, parent: Option<&QualName>, out: &mut String) {
+    match &handle.data {
+        NodeData::Element {
+            name,
+            attrs,
+            template_contents,
+            ..
+        } => {
+            out.push('<');
+            out.push_str(&name.local);
+            for attr in attrs.borrow().iter() {
+                out.push(' ');
+                let ns = &attr.name.ns;
+                if *ns == ns!(xml) {
+                    out.push_str("xml:");
+                } else if *ns == ns!(xmlns) && &*attr.name.local != "xmlns" {
+                    out.push_str("xmlns:");
+                } else if *ns == ns!(xlink) {
+                    out.push_str("xlink:");
+                } else if *ns != ns!() && *ns != ns!(xmlns) {
+                    if let Some(prefix) = &attr.name.prefix {
+                        out.push_str(prefix);
+                        out.push(':');
+                    }
+                }
+                out.push_str(&attr.name.local);
+                out.push_str("=\"");
+                escape_into(&attr.value, true, out);
+                out.push('"');
+            }
+            out.push('>');
+
+            let void = name.ns == ns!(html) && VOID_ELEMENTS.contains(&&*name.local);
+            if void {
+                return;
+            }
+
+            // A template serializes its contents fragment
+            let contents = template_contents.borrow().clone();
+            let children_of = contents.as_ref().unwrap_or(handle);
+            for child in children_of.children.borrow().iter() {
+                serialize_node(child, Some(name), out);
+            }
+
+            out.push_str("</");
+            out.push_str(&name.local);
+            out.push('>');
+        }
+        NodeData::Text { contents } => {
+            let raw = parent.is_some_and(|parent| {
+                [
+                    local_name!("style"),
+                    local_name!("script"),
+                    local_name!("xmp"),
+                    local_name!("iframe"),
+                    local_name!("noembed"),
+                    local_name!("noframes"),
+                    local_name!("plaintext"),
+                ]
+                .iter()
+                .any(|local| is_html(parent, local))
+            });
+            if raw {
+                out.push_str(&contents.borrow());
+            } else {
+                escape_into(&contents.borrow(), false, out);
+            }
+        }
+        NodeData::Comment { contents } => {
+            out.push_str("<!--");
+            out.push_str(contents);
+            out.push_str("-->");
+        }
+        NodeData::ProcessingInstruction { target, contents } => {
+            out.push_str("<?");
+            out.push_str(target);
+            out.push(' ');
+            out.push_str(contents);
+            out.push('>');
+        }
+        NodeData::Doctype { .. } | NodeData::Document => {}
+    }
+}
+
+fn escape_into(text: &str, attr_mode: bool, out: &mut String) {
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '\u{A0}' => out.push_str("&nbsp;"),
+            '"' if attr_mode => out.push_str("&quot;"),
+            '<' if !attr_mode => out.push_str("&lt;"),
+            '>' if !attr_mode => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slim_html_matches_typescript_output() {
+        let html = "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n  <meta charset=\"utf-8\">\n  \
+            <meta name=\"viewport\" content=\"width=device-width\">\n  <meta charset=\"\">\n  \
+            <title>Shop</title>\n  <link rel=\"stylesheet\" href=\"a.css\">\n  \
+            <style>body { color: red; }</style>\n  <script>var x = 1 < 2;</script>\n</head>\n\
+            <body class=\"main\">\n  <!-- nav -->\n  <div id=\"list\">\n    \
+            <p>Hello   <b>world</b> &amp; more&nbsp;text &lt;3</p>\n    \
+            <img src=\"a.png\" alt=\"A\"><br>\n  <svg viewBox=\"0 0 1 1\"><script>x</script>\
+            <circle r=\"1\"/></svg>\n  </div>\n  <template><i class=\"t\">  t </i></template>\n\
+            </body>\n</html>\n";
+
+        assert_eq!(
+            slim_html(html),
+            "<html><head><meta><title>Shop</title></head><body><div>\
+             <p>Hello <b>world</b> &amp; more&nbsp;text &lt;3</p><img><br>\
+             <svg><circle></circle></svg></div><template><i class=\"t\"> t </i></template>\
+             </body></html>"
+        );
+
+        let root = crate::html_parser::parse_html(
+            "<html><body><p>Hello   <b>world</b> &amp; more</p><br><script>x</script></body></html>",
+        );
+        assert_eq!(
+            tag_tree_html(&root),
+            "<html><head></head><body><p>Hello <b>world</b> &amp; more</p><br></body></html>"
+        );
+    }
+
+    #[test]
+    fn slim_html_reproduces_sample_fixtures() {
+        // `mdr --format slim` output for the web app's samples; `mdr-consistency.test.ts`
+        // checks the same files against the TypeScript `slimHtml`
+        let web = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("../../apps/web");
+        let mut checked = 0;
+        for entry in std::fs::read_dir(web.join("public/samples")).unwrap() {
+            let sample = entry.unwrap().path();
+            if sample.extension().and_then(|e| e.to_str()) != Some("html") {
+                continue;
+            }
+            let fixture = web
+                .join("tests/slim-expected")
+                .join(sample.file_name().unwrap());
+            let expected = std::fs::read_to_string(&fixture).unwrap();
+            let html = std::fs::read_to_string(&sample).unwrap();
+            assert!(
+                slim_html(&html) == expected,
+                "{} differs from {}",
+                sample.display(),
+                fixture.display()
+            );
+            checked += 1;
+        }
+        assert_eq!(checked, 20);
+    }
+
+    #[test]
+    fn whitespace_and_comment_passes_follow_the_regexes() {
+        assert_eq!(
+            collapse_whitespace("\n <a> \n\n <b>x \u{3000} y</b>\t</a> z \n"),
+            "<a><b>x y</b></a> z"
+        );
+        assert_eq!(collapse_whitespace("a>\u{85}<b"), "a>\u{85}<b");
+        assert_eq!(
+            strip_comments("a<!-- x -->b<!-->c-->d<!-- open"),
+            "abd<!-- open"
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use crate::distance_cache::{DistanceCache, DistanceCacheConfig, DistanceCacheStats};
+use crate::error::{validate_parameters, MdrError};
+use crate::evaluation::{calculate_evaluation_metrics, record_xpaths, EvaluationResult};
+use crate::session::{run_mdr_pipeline, MdrContext};
+use crate::types::{MdrFullOutput, MdrOptions, TagNodeRef};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Grid of MDR parameters to evaluate on one tree
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SweepOptions {
+    pub ks: Vec<usize>,
+    pub ts: Vec<f32>,
+    #[serde(flatten)]
+    pub mdr: MdrOptions,
+    /// Cache shared by every configuration of the sweep
+    pub cache: DistanceCacheConfig,
+    /// Xpath arrays of the expected records; each configuration is scored when given
+    pub ground_truth: Option<Vec<Vec<String>>>,
+}
+
+impl Default for SweepOptions {
+    fn default() -> Self {
+        SweepOptions {
+            ks: vec![10],
+            ts: vec![0.3],
+            mdr: MdrOptions::default(),
+            cache: DistanceCacheConfig::default(),
+            ground_truth: None,
+        }
+    }
+}
+
+/// MDR output of one `(k, t)` configuration
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SweepResult {
+    pub k: usize,
+    pub t: f32,
+    #[serde(flatten)]
+    pub output: MdrFullOutput,
+    /// Score of `finalRecords` against the ground truth
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics: Option<EvaluationResult>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SweepOutput {
+    /// One entry per configuration, `ks` outer and `ts` inner
+    pub results: Vec<SweepResult>,
+    /// Index into `results` of the highest F1 (first on ties) when scored
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub best: Option<usize>,
+    pub comparisons: u64,
+    pub cache: DistanceCacheStats,
+}
+
+/// Runs the full pipeline for every `(k, t)` in the grid.
+///
+/// Distances depend on neither `k` nor `t`, so all configurations memoize into one
+/// cache and flattened strings stay cached on the tree; only the region search and
+/// record extraction run again per configuration.
+pub fn sweep_parameters(
+    root: &TagNodeRef,
+    options: &SweepOptions,
+) -> Result<SweepOutput, MdrError> {
+    for (name, empty) in [("ks", options.ks.is_empty()), ("ts", options.ts.is_empty())] {
+        if empty {
+            return Err(MdrError::InvalidParameter {
+                name: name.to_string(),
+                value: "[]".to_string(),
+                reason: "must contain at least one value".to_string(),
+            });
+        }
+    }
+    for &k in &options.ks {
+        for &t in &options.ts {
+            validate_parameters(k, t)?;
+        }
+    }
+
+    let cache = Arc::new(DistanceCache::new(options.cache));
+    let mut comparisons = 0;
+    let mut results = Vec::with_capacity(options.ks.len() * options.ts.len());
+
+    for &k in &options.ks {
+        for &t in &options.ts {
+            let context = MdrContext::with_cache(k, t, options.mdr.clone(), Arc::clone(&cache));
+            let output = run_mdr_pipeline(root, &context);
+            comparisons += context.comparisons();
+
+            let metrics = options.ground_truth.as_ref().map(|ground_truth| {
+                calculate_evaluation_metrics(&record_xpaths(&output.final_records), ground_truth)
+            });
+            results.push(SweepResult {
+                k,
+                t,
+                output,
+                metrics,
+            });
+        }
+    }
+
+    let best = results
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, result)| result.metrics.as_ref().map(|m| (idx, m.f1)))
+        .fold(None, |best: Option<(usize, f64)>, (idx, f1)| match best {
+            Some((_, best_f1)) if best_f1 >= f1 => best,
+            _ => Some((idx, f1)),
+        })
+        .map(|(idx, _)| idx);
+
+    Ok(SweepOutput {
+        results,
+        best,
+        comparisons,
+        cache: cache.stats(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_parser::parse_html;
+
+    #[test]
+    fn sweep_reuses_distances_and_scores_against_ground_truth() {
+        let root = parse_html(
+            "<html><body><ul>\
+             <li><a>One</a><span>1</span></li>\
+             <li><a>Two</a><span>2</span></li>\
+             <li><a>Three</a><span>3</span></li>\
+             </ul></body></html>",
+        );
+        let ground_truth: Vec<Vec<String>> = (1..=3)
+            .map(|i| vec![format!("/html[1]/body[1]/ul[1]/li[{}]", i)])
+            .collect();
+        let options = SweepOptions {
+            ks: vec![1, 10],
+            ts: vec![0.0, 0.3],
+            ground_truth: Some(ground_truth),
+            ..SweepOptions::default()
+        };
+
+        let output = sweep_parameters(&root, &options).unwrap();
+        assert_eq!(output.results.len(), 4);
+        assert_eq!((output.results[3].k, output.results[3].t), (10, 0.3));
+        assert!(output.results.iter().all(|result| result.metrics.is_some()));
+        assert!(output.cache.hits > 0);
+        assert!(output.best.is_some());
+
+        let empty = SweepOptions {
+            ts: Vec::new(),
+            ..SweepOptions::default()
+        };
+        assert_eq!(
+            sweep_parameters(&root, &empty).err().map(|e| e.code()),
+            Some("INVALID_PARAMETER")
+        );
+    }
+}
@@ -0,0 +1,237 @@
+use crate::mdr_algorithm::run_mdr_algorithm_with_context;
+use crate::record_extraction::identify_all_data_records_with_context;
+use crate::session::MdrContext;
+use crate::tree_alignment::{align_regions, AlignedRecord, AlignedRegion};
+use crate::types::{DataRegion, MdrOptions, TagNodeRef};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+/// Options for [`extract_tables`]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TableOptions {
+    pub k: usize,
+    pub t: f32,
+    #[serde(flatten)]
+    pub mdr: MdrOptions,
+    /// Keep columns whose cells are empty in every row (e.g. `<br>`, `<img>` leaves)
+    pub keep_empty_columns: bool,
+}
+
+impl Default for TableOptions {
+    fn default() -> Self {
+        TableOptions {
+            k: 10,
+            t: 0.3,
+            mdr: MdrOptions::default(),
+            keep_empty_columns: false,
+        }
+    }
+}
+
+/// Schema entry for one table column
+#[derive(Serialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct TableColumn {
+    pub index: usize,
+    /// Leaf xpath suffix relative to the record root, e.g. `li/a[1]`
+    pub name: String,
+    /// Number of rows with a non-empty cell
+    pub non_empty: usize,
+}
+
+/// One data region as a table: one row per record, one column per aligned leaf
+#[derive(Serialize, Debug, Clone)]
+pub struct Table {
+    pub parent_xpath: String,
+    pub region: DataRegion,
+    pub columns: Vec<TableColumn>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Serializable bundle of a table in every export format
+#[derive(Serialize, Debug, Clone)]
+pub struct TableExport {
+    pub parent_xpath: String,
+    pub region: DataRegion,
+    pub columns: Vec<TableColumn>,
+    pub rows: Vec<IndexMap<String, String>>,
+    pub csv: String,
+}
+
+/// Runs MDR, record identification and partial tree alignment, returning one table per region
+pub fn extract_tables(root: &TagNodeRef, options: &TableOptions) -> Vec<Table> {
+    let context = MdrContext::global(options.k, options.t, options.mdr.clone());
+    let regions = run_mdr_algorithm_with_context(root, &context);
+    let records = identify_all_data_records_with_context(&regions, root, &context);
+
+    align_regions(&regions, &records, root, &options.mdr)
+        .iter()
+        .map(|aligned| table_from_alignment(aligned, options.keep_empty_columns))
+        .collect()
+}
+
+/// Builds a table from an aligned region
+pub fn table_from_alignment(aligned: &AlignedRegion, keep_empty_columns: bool) -> Table {
+    let mut names: Vec<Option<String>> = vec![None; aligned.column_count];
+    let mut rows: Vec<Vec<String>> = Vec::with_capacity(aligned.records.len());
+
+    for record in &aligned.records {
+        let mut row = vec![String::new(); aligned.column_count];
+        for item in &record.items {
+            if names[item.column].is_none() {
+                names[item.column] = Some(column_name(record, &item.xpath));
+            }
+            if let Some(text) = &item.text {
+                row[item.column] = text.clone();
+            }
+        }
+        rows.push(row);
+    }
+
+    let keep: Vec<usize> = (0..aligned.column_count)
+        .filter(|&c| keep_empty_columns || rows.iter().any(|row| !row[c].is_empty()))
+        .collect();
+
+    // Disambiguate repeated suffixes with their occurrence number
+    let mut seen: IndexMap<String, usize> = IndexMap::new();
+    let columns = keep
+        .iter()
+        .enumerate()
+        .map(|(index, &c)| {
+            let base = names[c].clone().unwrap_or_else(|| format!("column{}", c));
+            let count = seen.entry(base.clone()).or_insert(0);
+            *count += 1;
+            let name = if *count == 1 {
+                base
+            } else {
+                format!("{}#{}", base, count)
+            };
+            TableColumn {
+                index,
+                name,
+                non_empty: rows.iter().filter(|row| !row[c].is_empty()).count(),
+            }
+        })
+        .collect();
+
+    let rows = rows
+        .into_iter()
+        .map(|row| keep.iter().map(|&c| row[c].clone()).collect())
+        .collect();
+
+    Table {
+        parent_xpath: aligned.parent_xpath.clone(),
+        region: aligned.region,
+        columns,
+        rows,
+    }
+}
+
+/// Xpath of `xpath` relative to the record root containing it, keeping the root's tag
+fn column_name(record: &AlignedRecord, xpath: &str) -> String {
+    for root in &record.xpaths {
+        let Some(rest) = xpath.strip_prefix(root.as_str()) else {
+            continue;
+        };
+        if !rest.is_empty() && !rest.starts_with('/') {
+            continue;
+        }
+        let segment = root.rsplit('/').next().unwrap_or("");
+        let tag = segment.split('[').next().unwrap_or(segment);
+        return format!("{}{}", tag, rest);
+    }
+    xpath.to_string()
+}
+
+impl Table {
+    /// Rows as objects keyed by column name
+    pub fn json_rows(&self) -> Vec<IndexMap<String, String>> {
+        self.rows
+            .iter()
+            .map(|row| {
+                self.columns
+                    .iter()
+                    .zip(row)
+                    .map(|(column, cell)| (column.name.clone(), cell.clone()))
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// RFC 4180 CSV with a header row and CRLF line endings
+    pub fn to_csv(&self) -> String {
+        let mut out = String::new();
+        let header = self.columns.iter().map(|column| column.name.as_str());
+        write_csv_line(&mut out, header);
+        for row in &self.rows {
+            write_csv_line(&mut out, row.iter().map(String::as_str));
+        }
+        out
+    }
+
+    pub fn export(&self) -> TableExport {
+        TableExport {
+            parent_xpath: self.parent_xpath.clone(),
+            region: self.region,
+            columns: self.columns.clone(),
+            rows: self.json_rows(),
+            csv: self.to_csv(),
+        }
+    }
+}
+
+fn write_csv_line<'a>(out: &mut String, fields: impl Iterator<Item = &'a str>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.push(',');
+        }
+        if field.contains([',', '"', '\r', '\n']) {
+            out.push('"');
+            out.push_str(&field.replace('"', "\"\""));
+            out.push('"');
+        } else {
+            out.push_str(field);
+        }
+    }
+    out.push_str("\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_quotes_special_fields() {
+        let table = Table {
+            parent_xpath: "/ul[1]".to_string(),
+            region: (1, 0, 2),
+            columns: vec![
+                TableColumn {
+                    index: 0,
+                    name: "li/a[1]".to_string(),
+                    non_empty: 2,
+                },
+                TableColumn {
+                    index: 1,
+                    name: "li/span[1]".to_string(),
+                    non_empty: 1,
+                },
+            ],
+            rows: vec![
+                vec!["Say \"hi\"".to_string(), "1,5".to_string()],
+                vec!["two\nlines".to_string(), String::new()],
+            ],
+        };
+
+        assert_eq!(
+            table.to_csv(),
+            "li/a[1],li/span[1]\r\n\"Say \"\"hi\"\"\",\"1,5\"\r\n\"two\nlines\",\r\n"
+        );
+        assert_eq!(table.json_rows()[0]["li/span[1]"], "1,5");
+        assert_eq!(
+            serde_json::to_value(&table.columns[1]).unwrap()["nonEmpty"],
+            1
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use crate::types::{TagNode, TagNodeRef};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+
+/// Value of the hierarchical text map: text at a leaf, nested segments otherwise
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum NestedTextMap {
+    Text(String),
+    Map(IndexMap<String, NestedTextMap>),
+}
+
+/// The `flat` and `hier` LLM input formats, shaped like `HtmlResult`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TextMaps {
+    /// Xpath of each text's parent element → text
+    pub text_map_flat: IndexMap<String, String>,
+    /// The same texts nested by xpath segment (`body[1]` → `div[2]` → ...)
+    pub text_map: IndexMap<String, NestedTextMap>,
+}
+
+/// Port of `extractTextWithXPaths` over a tag tree, e.g. `parse_html(&slim_html(html))`.
+///
+/// Texts are visited in document order, skipping `script` and `style` content. Keys
+/// follow `generateXPath`: the leading `/html[1]` is dropped and an element with a
+/// plain `id` is addressed as `id('...')`. A later text under the same element
+/// overwrites the earlier one in place, and the hierarchical map replaces a text
+/// with an object (or back) when a deeper xpath passes through it, as in TypeScript.
+pub fn extract_text_with_xpaths(root: &TagNodeRef) -> TextMaps {
+    let mut maps = TextMaps::default();
+    for_each_text(root, &mut |element, text| {
+        let xpath = llm_xpath(element);
+        insert_nested(&mut maps.text_map, &xpath, text);
+        maps.text_map_flat.insert(xpath, text.to_string());
+    });
+    maps
+}
+
+/// Calls `visit(parent, trimmed_text)` for every non-empty text in document order,
+/// skipping `script` and `style` content
+pub(crate) fn for_each_text(element: &TagNode, visit: &mut impl FnMut(&TagNode, &str)) {
+    if matches!(element.tag_name.as_str(), "script" | "style") {
+        return;
+    }
+    for child in &element.children {
+        if child.tag_name != "text" {
+            for_each_text(child, visit);
+            continue;
+        }
+        let Some(text) = child.raw_text.as_deref().map(str::trim) else {
+            continue;
+        };
+        if !text.is_empty() {
+            visit(element, text);
+        }
+    }
+}
+
+/// `generateXPath` for an element of the tag tree
+pub(crate) fn llm_xpath(element: &TagNode) -> String {
+    if let Some(id) = element.get_attribute("id") {
+        let mut chars = id.chars();
+        let plain = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+        if plain {
+            return format!("id('{}')", id);
+        }
+    }
+    match element.xpath.strip_prefix("/html[1]") {
+        Some(rest) if rest.starts_with('/') => rest.to_string(),
+        _ => element.xpath.clone(),
+    }
+}
+
+/// One `parts.forEach` step of `extractTextWithXPaths` (`xpath.substring(1).split("/")`)
+fn insert_nested(map: &mut IndexMap<String, NestedTextMap>, xpath: &str, text: &str) {
+    let parts: Vec<&str> = xpath.get(1..).unwrap_or("").split('/').collect();
+    let (last, parents) = parts.split_last().expect("split yields at least one part");
+
+    let mut level = map;
+    for part in parents {
+        let entry = level
+            .entry(part.to_string())
+            .or_insert_with(|| NestedTextMap::Map(IndexMap::new()));
+        if let NestedTextMap::Text(_) = entry {
+            *entry = NestedTextMap::Map(IndexMap::new());
+        }
+        let NestedTextMap::Map(next) = entry else {
+            unreachable!("entry was just made a map")
+        };
+        level = next;
+    }
+    level.insert(last.to_string(), NestedTextMap::Text(text.to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_parser::parse_html;
+    use serde_json::json;
+
+    #[test]
+    fn text_maps_match_extract_text_with_xpaths() {
+        let root = parse_html(
+            "<html><head><title>Shop</title><style>p { }</style></head><body>\
+             <div><p>First <b>bold</b> last</p><p>Second</p></div>\
+             <div id=\"main\"><span>Main</span> text</div><div id=\"1x\">Odd id</div>\
+             </body></html>",
+        );
+        let maps = extract_text_with_xpaths(&root);
+
+        assert_eq!(
+            serde_json::to_value(&maps.text_map_flat).unwrap(),
+            json!({
+                "/head[1]/title[1]": "Shop",
+                "/body[1]/div[1]/p[1]": "last",
+                "/body[1]/div[1]/p[1]/b[1]": "bold",
+                "/body[1]/div[1]/p[2]": "Second",
+                "/body[1]/div[2]/span[1]": "Main",
+                "id('main')": "text",
+                "/body[1]/div[3]": "Odd id",
+            })
+        );
+        let flat_keys: Vec<&str> = maps.text_map_flat.keys().map(String::as_str).collect();
+        assert_eq!(flat_keys[1], "/body[1]/div[1]/p[1]");
+
+        // "First" becomes an object when "bold" passes through p[1], then "last"
+        // replaces that object again; `substring(1)` turns `id('main')` into `d('main')`
+        assert_eq!(
+            serde_json::to_value(&maps).unwrap(),
+            json!({
+                "textMapFlat": serde_json::to_value(&maps.text_map_flat).unwrap(),
+                "textMap": {
+                    "head[1]": { "title[1]": "Shop" },
+                    "body[1]": {
+                        "div[1]": { "p[1]": "last", "p[2]": "Second" },
+                        "div[2]": { "span[1]": "Main" },
+                        "div[3]": "Odd id",
+                    },
+                    "d('main')": "text",
+                },
+            })
+        );
+    }
+}
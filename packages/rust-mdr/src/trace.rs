@@ -0,0 +1,161 @@
+use crate::mdr_algorithm::run_mdr_algorithm_with_context;
+use crate::session::MdrContext;
+use crate::types::{DataRegion, MdrOptions, RegionsMapItem, TagNodeRef};
+use serde::Serialize;
+
+/// One generalized-node comparison made by `ident_drs`
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct GnComparison {
+    pub gn_length: usize,
+    /// Start offset of the scan this comparison belongs to
+    pub start_idx: usize,
+    /// Index of the first node of the left generalized node
+    pub check_idx: usize,
+    pub distance: f32,
+    /// `distance <= t`
+    pub similar: bool,
+}
+
+/// How a candidate region compared to the maximal region found so far
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CandidateVerdict {
+    /// No maximal region yet
+    Initial,
+    /// Covers more nodes and starts no later (or the maximal region starts at 0)
+    MoreNodes,
+    /// Same start and node count with a shorter generalized node
+    ShorterGn,
+    /// Covers more nodes but starts after a maximal region that does not start at 0
+    StartsLater,
+    FewerNodes,
+    /// Same node count without a shorter generalized node at the same start
+    NotBetter,
+}
+
+impl CandidateVerdict {
+    /// Whether the candidate replaces the maximal region
+    pub fn accepted(self) -> bool {
+        matches!(
+            self,
+            CandidateVerdict::Initial | CandidateVerdict::MoreNodes | CandidateVerdict::ShorterGn
+        )
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct CandidateRegion {
+    pub region: DataRegion,
+    pub verdict: CandidateVerdict,
+    /// Maximal region the candidate was compared against
+    pub previous_max: Option<DataRegion>,
+}
+
+/// One invocation of `ident_drs` (the first starts at 0, later ones after the
+/// previously selected region)
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct IdentPass {
+    pub start_child_idx: usize,
+    pub comparisons: Vec<GnComparison>,
+    pub candidates: Vec<CandidateRegion>,
+    pub selected: Option<DataRegion>,
+}
+
+impl IdentPass {
+    pub(crate) fn new(start_child_idx: usize) -> Self {
+        IdentPass {
+            start_child_idx,
+            comparisons: Vec::new(),
+            candidates: Vec::new(),
+            selected: None,
+        }
+    }
+}
+
+/// Decisions made for one parent node
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct ParentTrace {
+    #[serde(rename = "parent_xpath")]
+    pub parent_xpath: String,
+    pub child_count: usize,
+    pub passes: Vec<IdentPass>,
+    /// Regions found among this node's own children
+    pub regions: Vec<DataRegion>,
+    /// Regions of uncovered children appended under this parent (TS parity)
+    pub inherited: Vec<DataRegion>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MdrTraceOutput {
+    pub regions: Vec<RegionsMapItem>,
+    /// Parents in the order their subtrees finished, i.e. children before parents
+    pub trace: Vec<ParentTrace>,
+}
+
+/// Runs the region search with tracing enabled and returns the regions with the trace
+pub fn run_mdr_algorithm_traced(
+    root_node: &TagNodeRef,
+    k: usize,
+    t: f32,
+    options: &MdrOptions,
+) -> MdrTraceOutput {
+    let context = MdrContext::global(k, t, options.clone()).with_trace();
+    let regions = run_mdr_algorithm_with_context(root_node, &context);
+    MdrTraceOutput {
+        regions,
+        trace: context.take_trace(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_parser::parse_html;
+    use crate::mdr_algorithm::run_mdr_algorithm;
+
+    #[test]
+    fn trace_explains_selected_region() {
+        let root = parse_html(
+            "<html><body><div><p>Intro</p>\
+             <div><a>1</a><span>1</span></div>\
+             <div><a>2</a><span>2</span></div>\
+             <div><a>3</a><span>3</span></div></div></body></html>",
+        );
+        let output = run_mdr_algorithm_traced(&root, 10, 0.3, &MdrOptions::default());
+
+        let plain = run_mdr_algorithm(&root, 10, 0.3);
+        assert_eq!(
+            serde_json::to_value(&output.regions).unwrap(),
+            serde_json::to_value(&plain).unwrap()
+        );
+
+        let parent = output
+            .trace
+            .iter()
+            .find(|parent| parent.parent_xpath == "/html[1]/body[1]/div[1]")
+            .unwrap();
+        assert_eq!(parent.regions, vec![(1, 1, 3)]);
+        let first_pass = &parent.passes[0];
+        assert_eq!(first_pass.selected, Some((1, 1, 3)));
+        assert!(first_pass
+            .comparisons
+            .iter()
+            .any(|c| c.gn_length == 1 && c.check_idx == 0 && !c.similar));
+        assert_eq!(first_pass.candidates[0].verdict, CandidateVerdict::Initial);
+        assert!(first_pass.candidates[1..]
+            .iter()
+            .all(|c| !c.verdict.accepted() || c.region == (1, 1, 3)));
+
+        // camelCase like the other outputs, except the region map's `parent_xpath`
+        let json = serde_json::to_value(parent).unwrap();
+        assert_eq!(json["parent_xpath"], "/html[1]/body[1]/div[1]");
+        assert_eq!(json["childCount"], 4);
+        assert_eq!(json["passes"][0]["startChildIdx"], 0);
+        assert!(json["passes"][0]["comparisons"][0]["gnLength"].is_number());
+        assert!(json["passes"][0]["candidates"][0]["previousMax"].is_null());
+    }
+}
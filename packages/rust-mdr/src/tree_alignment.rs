@@ -0,0 +1,514 @@
+use crate::tree_utils::escape_signature_value;
+use crate::types::{DataRecord, DataRegion, MdrOptions, RegionsMapItem, TagNodeRef};
+use crate::xpath_index::XpathIndex;
+use serde::Serialize;
+
+/// Node of the aligned seed tree; leaves that hold data items carry a column index
+#[derive(Serialize, Debug, Clone)]
+pub struct SeedNode {
+    pub tag: String,
+    pub column: Option<usize>,
+    pub children: Vec<SeedNode>,
+}
+
+/// A data item of one record placed into its column
+#[derive(Serialize, Debug, Clone)]
+pub struct AlignedItem {
+    pub column: usize,
+    pub xpath: String,
+    pub text: Option<String>,
+}
+
+/// One record with its data items ordered by column
+#[derive(Serialize, Debug, Clone)]
+pub struct AlignedRecord {
+    /// Xpaths of the record's top-level nodes
+    pub xpaths: Vec<String>,
+    pub items: Vec<AlignedItem>,
+}
+
+/// Alignment of all records found in one data region
+#[derive(Serialize, Debug, Clone)]
+pub struct AlignedRegion {
+    pub parent_xpath: String,
+    pub region: DataRegion,
+    pub seed: SeedNode,
+    pub column_count: usize,
+    pub records: Vec<AlignedRecord>,
+}
+
+/// Records of one data region, as grouped by [`group_records_by_region`]
+pub struct RegionRecords {
+    pub parent_xpath: String,
+    pub parent: TagNodeRef,
+    pub region: DataRegion,
+    pub records: Vec<DataRecord>,
+}
+
+/// Groups `records` by the data region that contains them.
+///
+/// A record belongs to the region whose covered children are (or directly contain)
+/// its first node, as produced by `find_records1`/`find_records_n`; regions without
+/// records are omitted.
+pub fn group_records_by_region(
+    regions: &[RegionsMapItem],
+    records: &[DataRecord],
+    root: &TagNodeRef,
+) -> Vec<RegionRecords> {
+    let index = XpathIndex::new(root);
+    let mut assigned = vec![false; records.len()];
+    let mut groups = Vec::new();
+
+    for region_item in regions {
+        let parent_node = match index.get(&region_item.parent_xpath) {
+            Some(node) => node.clone(),
+            None => continue,
+        };
+
+        let mut sorted_regions = region_item.regions.clone();
+        sorted_regions.sort_by_key(|r| r.1);
+
+        for region in sorted_regions {
+            let (_, start_idx, node_count) = region;
+            let end = (start_idx + node_count).min(parent_node.children.len());
+            let covered = parent_node.children.get(start_idx..end).unwrap_or(&[]);
+
+            let mut group = Vec::new();
+            for (idx, record) in records.iter().enumerate() {
+                if assigned[idx] {
+                    continue;
+                }
+                let Some(first) = record_nodes(record).first() else {
+                    continue;
+                };
+                let parent_xpath = first.xpath.rsplit_once('/').map_or("", |(p, _)| p);
+                if covered
+                    .iter()
+                    .any(|child| child.xpath == first.xpath || child.xpath == parent_xpath)
+                {
+                    assigned[idx] = true;
+                    group.push(record.clone());
+                }
+            }
+
+            if group.is_empty() {
+                continue;
+            }
+
+            groups.push(RegionRecords {
+                parent_xpath: region_item.parent_xpath.clone(),
+                parent: parent_node.clone(),
+                region,
+                records: group,
+            });
+        }
+    }
+
+    groups
+}
+
+/// Groups `records` by the data region that contains them (see
+/// [`group_records_by_region`]) and aligns each group.
+pub fn align_regions(
+    regions: &[RegionsMapItem],
+    records: &[DataRecord],
+    root: &TagNodeRef,
+    options: &MdrOptions,
+) -> Vec<AlignedRegion> {
+    group_records_by_region(regions, records, root)
+        .into_iter()
+        .map(|group| {
+            let (seed, column_count, aligned_records) = align_records(&group.records, options);
+            AlignedRegion {
+                parent_xpath: group.parent_xpath,
+                region: group.region,
+                seed,
+                column_count,
+                records: aligned_records,
+            }
+        })
+        .collect()
+}
+
+/// Partial tree alignment (DEPTA) of the records of one region.
+///
+/// The record with the most nodes becomes the seed; every other record is matched
+/// against it with simple tree matching and its unmatched nodes are inserted when
+/// their position in the seed is unambiguous. Records that could not be fully
+/// inserted are retried while the seed keeps growing. Returns the seed tree, the
+/// number of columns and the per-record item alignment.
+pub fn align_records(
+    records: &[DataRecord],
+    options: &MdrOptions,
+) -> (SeedNode, usize, Vec<AlignedRecord>) {
+    let attributes = &options.signature_attributes;
+    let forests: Vec<&[TagNodeRef]> = records.iter().map(record_nodes).collect();
+
+    let mut seed = SeedTree::new();
+    let mut mappings: Vec<Vec<(usize, TagNodeRef)>> = vec![Vec::new(); records.len()];
+
+    let seed_idx = (0..forests.len())
+        .max_by_key(|&i| (forest_size(forests[i]), std::cmp::Reverse(i)))
+        .unwrap_or(0);
+    if let Some(forest) = forests.get(seed_idx) {
+        for node in forest.iter() {
+            let id = seed.copy_subtree(node, attributes, &mut mappings[seed_idx]);
+            seed.nodes[0].children.push(id);
+        }
+    }
+
+    let mut pending: Vec<usize> = (0..forests.len()).filter(|&i| i != seed_idx).collect();
+    while !pending.is_empty() {
+        let mut inserted_any = false;
+        let mut incomplete = Vec::new();
+
+        for idx in pending {
+            let roots: Vec<&TagNodeRef> = forests[idx].iter().collect();
+            let mut outcome = Outcome {
+                complete: true,
+                ..Default::default()
+            };
+            seed.align_children(0, &roots, attributes, &mut outcome);
+
+            inserted_any |= outcome.inserted;
+            mappings[idx] = outcome.mapping;
+            if !outcome.complete {
+                incomplete.push(idx);
+            }
+        }
+
+        if !inserted_any {
+            break;
+        }
+        pending = incomplete;
+    }
+
+    // Columns are the seed nodes that hold a leaf of at least one record
+    let mut is_column = vec![false; seed.nodes.len()];
+    for mapping in &mappings {
+        for (seed_id, node) in mapping {
+            if node.children.is_empty() {
+                is_column[*seed_id] = true;
+            }
+        }
+    }
+    let mut columns = vec![None; seed.nodes.len()];
+    let mut column_count = 0;
+    seed.assign_columns(0, &is_column, &mut columns, &mut column_count);
+
+    let aligned_records = forests
+        .iter()
+        .zip(&mappings)
+        .map(|(forest, mapping)| {
+            let mut items: Vec<AlignedItem> = mapping
+                .iter()
+                .filter(|(_, node)| node.children.is_empty())
+                .filter_map(|(seed_id, node)| {
+                    columns[*seed_id].map(|column| AlignedItem {
+                        column,
+                        xpath: node.xpath.clone(),
+                        text: node.raw_text.clone().filter(|text| !text.is_empty()),
+                    })
+                })
+                .collect();
+            items.sort_by_key(|item| item.column);
+            AlignedRecord {
+                xpaths: forest.iter().map(|node| node.xpath.clone()).collect(),
+                items,
+            }
+        })
+        .collect();
+
+    (
+        seed.to_seed_node(0, &columns),
+        column_count,
+        aligned_records,
+    )
+}
+
+pub(crate) fn record_nodes(record: &DataRecord) -> &[TagNodeRef] {
+    match record {
+        DataRecord::Single(node) => std::slice::from_ref(node),
+        DataRecord::Multi(nodes) => nodes,
+    }
+}
+
+fn forest_size(nodes: &[TagNodeRef]) -> usize {
+    nodes
+        .iter()
+        .map(|node| 1 + forest_size(&node.children))
+        .sum()
+}
+
+fn node_label(node: &TagNodeRef, attributes: &[String]) -> String {
+    let mut label = node.tag_name.clone();
+    for name in attributes {
+        if let Some(value) = node.get_attribute(name) {
+            label.push(' ');
+            label.push_str(name);
+            label.push_str("=\"");
+            label.push_str(&escape_signature_value(value));
+            label.push('"');
+        }
+    }
+    label
+}
+
+#[derive(Default)]
+struct Outcome {
+    mapping: Vec<(usize, TagNodeRef)>,
+    inserted: bool,
+    complete: bool,
+}
+
+struct SeedArenaNode {
+    label: String,
+    tag: String,
+    children: Vec<usize>,
+}
+
+/// Growing seed tree; id 0 is the virtual root joining the top-level record nodes
+struct SeedTree {
+    nodes: Vec<SeedArenaNode>,
+}
+
+impl SeedTree {
+    fn new() -> Self {
+        SeedTree {
+            nodes: vec![SeedArenaNode {
+                label: String::new(),
+                tag: "record".to_string(),
+                children: Vec::new(),
+            }],
+        }
+    }
+
+    fn copy_subtree(
+        &mut self,
+        node: &TagNodeRef,
+        attributes: &[String],
+        mapping: &mut Vec<(usize, TagNodeRef)>,
+    ) -> usize {
+        let id = self.nodes.len();
+        self.nodes.push(SeedArenaNode {
+            label: node_label(node, attributes),
+            tag: node.tag_name.clone(),
+            children: Vec::new(),
+        });
+        mapping.push((id, node.clone()));
+        for child in &node.children {
+            let child_id = self.copy_subtree(child, attributes, mapping);
+            self.nodes[id].children.push(child_id);
+        }
+        id
+    }
+
+    /// Simple tree matching score between a seed subtree and a record subtree
+    fn match_score(&self, seed_id: usize, node: &TagNodeRef, attributes: &[String]) -> usize {
+        if self.nodes[seed_id].label != node_label(node, attributes) {
+            return 0;
+        }
+        let children: Vec<&TagNodeRef> = node.children.iter().collect();
+        let pairs = self.match_children(&self.nodes[seed_id].children, &children, attributes);
+        1 + pairs.iter().map(|&(_, _, score)| score).sum::<usize>()
+    }
+
+    /// Order-preserving maximum matching of two sibling lists, as `(seed_idx, node_idx, score)`
+    fn match_children(
+        &self,
+        seed_children: &[usize],
+        node_children: &[&TagNodeRef],
+        attributes: &[String],
+    ) -> Vec<(usize, usize, usize)> {
+        let (n, m) = (seed_children.len(), node_children.len());
+        if n == 0 || m == 0 {
+            return Vec::new();
+        }
+
+        let cols = m + 1;
+        let mut w = vec![0usize; n * m];
+        let mut dp = vec![0usize; (n + 1) * cols];
+        for i in 1..=n {
+            for j in 1..=m {
+                let score =
+                    self.match_score(seed_children[i - 1], node_children[j - 1], attributes);
+                w[(i - 1) * m + j - 1] = score;
+                dp[i * cols + j] = (dp[(i - 1) * cols + j - 1] + score)
+                    .max(dp[(i - 1) * cols + j])
+                    .max(dp[i * cols + j - 1]);
+            }
+        }
+
+        let mut pairs = Vec::new();
+        let (mut i, mut j) = (n, m);
+        while i > 0 && j > 0 {
+            if dp[i * cols + j] == dp[(i - 1) * cols + j] {
+                i -= 1;
+            } else if dp[i * cols + j] == dp[i * cols + j - 1] {
+                j -= 1;
+            } else {
+                pairs.push((i - 1, j - 1, w[(i - 1) * m + j - 1]));
+                i -= 1;
+                j -= 1;
+            }
+        }
+        pairs.reverse();
+        pairs
+    }
+
+    /// Aligns `node_children` under `seed_id`, recording matches and inserting
+    /// unmatched record subtrees wherever their seed position is unique.
+    fn align_children(
+        &mut self,
+        seed_id: usize,
+        node_children: &[&TagNodeRef],
+        attributes: &[String],
+        outcome: &mut Outcome,
+    ) {
+        let seed_children = self.nodes[seed_id].children.clone();
+        let pairs = self.match_children(&seed_children, node_children, attributes);
+
+        for &(i, j, _) in &pairs {
+            outcome
+                .mapping
+                .push((seed_children[i], node_children[j].clone()));
+            let grandchildren: Vec<&TagNodeRef> = node_children[j].children.iter().collect();
+            self.align_children(seed_children[i], &grandchildren, attributes, outcome);
+        }
+
+        // Runs of unmatched record nodes between consecutive matches
+        let mut j = 0;
+        let mut next_pair = 0;
+        while j < node_children.len() {
+            if next_pair < pairs.len() && pairs[next_pair].1 == j {
+                next_pair += 1;
+                j += 1;
+                continue;
+            }
+
+            let run_start = j;
+            while j < node_children.len() && pairs.get(next_pair).is_none_or(|p| p.1 != j) {
+                j += 1;
+            }
+
+            let prev = next_pair.checked_sub(1).map(|p| pairs[p].0);
+            let next = pairs.get(next_pair).map(|p| p.0);
+            let anchor = match (prev, next) {
+                (Some(p), Some(n)) if n == p + 1 => Some(Some(seed_children[p])),
+                (None, Some(0)) => Some(None),
+                (Some(p), None) if p + 1 == seed_children.len() => Some(Some(seed_children[p])),
+                (None, None) if seed_children.is_empty() => Some(None),
+                _ => None,
+            };
+
+            match anchor {
+                Some(after) => {
+                    let position = match after {
+                        Some(id) => self.nodes[seed_id]
+                            .children
+                            .iter()
+                            .position(|&c| c == id)
+                            .map_or(0, |p| p + 1),
+                        None => 0,
+                    };
+                    for (offset, node) in node_children[run_start..j].iter().enumerate() {
+                        let id = self.copy_subtree(node, attributes, &mut outcome.mapping);
+                        self.nodes[seed_id].children.insert(position + offset, id);
+                    }
+                    outcome.inserted = true;
+                }
+                None => outcome.complete = false,
+            }
+        }
+    }
+
+    fn assign_columns(
+        &self,
+        id: usize,
+        is_column: &[bool],
+        columns: &mut [Option<usize>],
+        next: &mut usize,
+    ) {
+        if is_column[id] {
+            columns[id] = Some(*next);
+            *next += 1;
+        }
+        for &child in &self.nodes[id].children {
+            self.assign_columns(child, is_column, columns, next);
+        }
+    }
+
+    fn to_seed_node(&self, id: usize, columns: &[Option<usize>]) -> SeedNode {
+        SeedNode {
+            tag: self.nodes[id].tag.clone(),
+            column: columns[id],
+            children: self.nodes[id]
+                .children
+                .iter()
+                .map(|&child| self.to_seed_node(child, columns))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TagNode;
+    use std::sync::Arc;
+
+    fn leaf_text(xpath: &str, text: &str) -> TagNodeRef {
+        let mut node = TagNode::new("text".to_string(), xpath.to_string());
+        node.set_raw_text(text.to_string());
+        Arc::new(node)
+    }
+
+    fn record(xpath: &str, fields: &[(&str, &str)]) -> DataRecord {
+        let mut li = TagNode::new("li".to_string(), xpath.to_string());
+        for (tag, text) in fields {
+            let field_xpath = format!("{}/{}[1]", xpath, tag);
+            let mut field = TagNode::new(tag.to_string(), field_xpath.clone());
+            field.add_child(leaf_text(&field_xpath, text));
+            li.add_child(Arc::new(field));
+        }
+        DataRecord::Single(Arc::new(li))
+    }
+
+    #[test]
+    fn align_records_assigns_stable_columns() {
+        let records = vec![
+            record("/ul[1]/li[1]", &[("a", "one"), ("span", "$1")]),
+            record(
+                "/ul[1]/li[2]",
+                &[("a", "two"), ("b", "new"), ("span", "$2")],
+            ),
+            record(
+                "/ul[1]/li[3]",
+                &[("a", "three"), ("span", "$3"), ("em", "sale")],
+            ),
+        ];
+
+        let (seed, column_count, aligned) = align_records(&records, &MdrOptions::default());
+        assert_eq!(column_count, 4);
+        assert_eq!(seed.children[0].children.len(), 4);
+
+        let columns = |r: &AlignedRecord| -> Vec<(usize, String)> {
+            r.items
+                .iter()
+                .map(|item| (item.column, item.text.clone().unwrap_or_default()))
+                .collect()
+        };
+        assert_eq!(
+            columns(&aligned[0]),
+            vec![(0, "one".into()), (2, "$1".into())]
+        );
+        assert_eq!(
+            columns(&aligned[1]),
+            vec![(0, "two".into()), (1, "new".into()), (2, "$2".into())]
+        );
+        assert_eq!(
+            columns(&aligned[2]),
+            vec![(0, "three".into()), (2, "$3".into()), (3, "sale".into())]
+        );
+    }
+}
@@ -0,0 +1,297 @@
+use crate::similarity::SubtreeSimilarity;
+use crate::types::{MdrOptions, TagNode, TagNodeRef};
+use std::collections::HashSet;
+
+/// True tree edit distance (Zhang–Shasha) with unit insert/delete/relabel costs,
+/// normalized by the size of the larger forest. Structurally unrelated trees can need
+/// up to `|a| + |b|` edits, so the result is capped at 1.0.
+pub struct TreeEditDistance;
+
+/// Yang's simple tree matching, turned into a distance as
+/// `1 - 2·matches / (|a| + |b|)`.
+pub struct SimpleTreeMatching;
+
+/// A node the tree metrics can walk, so they run on `TagNode`s and on
+/// `arena::ArenaTree` ids alike
+pub(crate) trait TreeNode<'a>: Copy {
+    fn tag(self) -> &'a str;
+    fn raw_text(self) -> Option<&'a str>;
+    fn attribute(self, name: &str) -> Option<&'a str>;
+    fn children(self) -> impl Iterator<Item = Self>;
+}
+
+impl<'a> TreeNode<'a> for &'a TagNode {
+    fn tag(self) -> &'a str {
+        &self.tag_name
+    }
+
+    fn raw_text(self) -> Option<&'a str> {
+        self.raw_text.as_deref()
+    }
+
+    fn attribute(self, name: &str) -> Option<&'a str> {
+        self.get_attribute(name)
+    }
+
+    fn children(self) -> impl Iterator<Item = Self> {
+        self.children.iter().map(|child| &**child)
+    }
+}
+
+impl SubtreeSimilarity for TreeEditDistance {
+    fn distance(&self, a: &[TagNodeRef], b: &[TagNodeRef], options: &MdrOptions) -> f32 {
+        tree_edit_distance(
+            a.iter().map(|node| &**node),
+            b.iter().map(|node| &**node),
+            &options.signature_attributes,
+        )
+    }
+}
+
+impl SubtreeSimilarity for SimpleTreeMatching {
+    fn distance(&self, a: &[TagNodeRef], b: &[TagNodeRef], options: &MdrOptions) -> f32 {
+        simple_tree_matching(
+            a.iter().map(|node| &**node),
+            b.iter().map(|node| &**node),
+            &options.signature_attributes,
+        )
+    }
+}
+
+/// [`TreeEditDistance`] between two forests of sibling subtrees
+pub(crate) fn tree_edit_distance<'a, N: TreeNode<'a>>(
+    a: impl Iterator<Item = N>,
+    b: impl Iterator<Item = N>,
+    attributes: &[String],
+) -> f32 {
+    let ta = PostorderForest::new(a);
+    let tb = PostorderForest::new(b);
+    // Both forests carry a virtual root that always matches
+    let size = (ta.len() - 1).max(tb.len() - 1);
+    if size == 0 {
+        return 0.0;
+    }
+    let edits = zhang_shasha(&ta, &tb, attributes);
+    (edits as f32 / size as f32).min(1.0)
+}
+
+/// [`SimpleTreeMatching`] between two forests of sibling subtrees
+pub(crate) fn simple_tree_matching<'a, N: TreeNode<'a>>(
+    a: impl Iterator<Item = N>,
+    b: impl Iterator<Item = N>,
+    attributes: &[String],
+) -> f32 {
+    let (a, b) = (structural(a), structural(b));
+    let total = forest_size(&a) + forest_size(&b);
+    if total == 0 {
+        return 0.0;
+    }
+    let matches = match_forests(&a, &b, attributes);
+    1.0 - (2 * matches) as f32 / total as f32
+}
+
+/// Text nodes with content are invisible to the structural signature, exactly as in
+/// `flatten_subtree`.
+fn is_structural<'a, N: TreeNode<'a>>(node: N) -> bool {
+    !(node.tag() == "text" && node.raw_text().is_some_and(|text| !text.trim().is_empty()))
+}
+
+fn structural<'a, N: TreeNode<'a>>(nodes: impl Iterator<Item = N>) -> Vec<N> {
+    nodes.filter(|&node| is_structural(node)).collect()
+}
+
+/// Nodes of the given structural roots and their structural descendants
+fn forest_size<'a, N: TreeNode<'a>>(roots: &[N]) -> usize {
+    roots
+        .iter()
+        .map(|&node| 1 + forest_size(&structural(node.children())))
+        .sum()
+}
+
+fn labels_match<'a, N: TreeNode<'a>>(a: N, b: N, attributes: &[String]) -> bool {
+    a.tag() == b.tag()
+        && attributes
+            .iter()
+            .all(|name| a.attribute(name) == b.attribute(name))
+}
+
+/// Number of matched nodes between two ordered forests (top-down, order preserving)
+fn match_forests<'a, N: TreeNode<'a>>(a: &[N], b: &[N], attributes: &[String]) -> usize {
+    if a.is_empty() || b.is_empty() {
+        return 0;
+    }
+
+    let cols = b.len() + 1;
+    let mut m = vec![0usize; (a.len() + 1) * cols];
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let diagonal = m[(i - 1) * cols + j - 1] + match_trees(a[i - 1], b[j - 1], attributes);
+            m[i * cols + j] = diagonal.max(m[(i - 1) * cols + j]).max(m[i * cols + j - 1]);
+        }
+    }
+    m[a.len() * cols + b.len()]
+}
+
+fn match_trees<'a, N: TreeNode<'a>>(a: N, b: N, attributes: &[String]) -> usize {
+    if !labels_match(a, b, attributes) {
+        return 0;
+    }
+    1 + match_forests(
+        &structural(a.children()),
+        &structural(b.children()),
+        attributes,
+    )
+}
+
+/// Postorder layout of a forest under a virtual root, as required by Zhang–Shasha
+struct PostorderForest<N> {
+    /// `None` marks the virtual root
+    nodes: Vec<Option<N>>,
+    /// Leftmost leaf descendant of each node
+    lmld: Vec<usize>,
+    keyroots: Vec<usize>,
+}
+
+impl<'a, N: TreeNode<'a>> PostorderForest<N> {
+    fn new(roots: impl Iterator<Item = N>) -> Self {
+        let mut forest = PostorderForest {
+            nodes: Vec::new(),
+            lmld: Vec::new(),
+            keyroots: Vec::new(),
+        };
+
+        let mut first_leaf = None;
+        for root in roots.filter(|&node| is_structural(node)) {
+            let leaf = forest.visit(root);
+            first_leaf.get_or_insert(leaf);
+        }
+        let idx = forest.nodes.len();
+        forest.nodes.push(None);
+        forest.lmld.push(first_leaf.unwrap_or(idx));
+
+        // A keyroot is the highest node sharing its leftmost leaf
+        let mut seen = HashSet::new();
+        for i in (0..forest.nodes.len()).rev() {
+            if seen.insert(forest.lmld[i]) {
+                forest.keyroots.push(i);
+            }
+        }
+        forest.keyroots.reverse();
+        forest
+    }
+
+    fn visit(&mut self, node: N) -> usize {
+        let mut first_leaf = None;
+        for child in node.children().filter(|&child| is_structural(child)) {
+            let leaf = self.visit(child);
+            first_leaf.get_or_insert(leaf);
+        }
+        let idx = self.nodes.len();
+        self.nodes.push(Some(node));
+        self.lmld.push(first_leaf.unwrap_or(idx));
+        self.lmld[idx]
+    }
+
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+fn zhang_shasha<'a, N: TreeNode<'a>>(
+    a: &PostorderForest<N>,
+    b: &PostorderForest<N>,
+    attributes: &[String],
+) -> usize {
+    let (n, m) = (a.len(), b.len());
+    let mut tree_dist = vec![0usize; n * m];
+
+    let relabel_cost = |i: usize, j: usize| match (a.nodes[i], b.nodes[j]) {
+        (None, None) => 0,
+        (Some(x), Some(y)) if labels_match(x, y, attributes) => 0,
+        _ => 1,
+    };
+
+    for &i in &a.keyroots {
+        for &j in &b.keyroots {
+            let (li, lj) = (a.lmld[i], b.lmld[j]);
+            let rows = i - li + 2;
+            let cols = j - lj + 2;
+            let mut forest_dist = vec![0usize; rows * cols];
+
+            for x in 1..rows {
+                forest_dist[x * cols] = forest_dist[(x - 1) * cols] + 1;
+            }
+            for y in 1..cols {
+                forest_dist[y] = forest_dist[y - 1] + 1;
+            }
+
+            for x in 1..rows {
+                for y in 1..cols {
+                    let (i1, j1) = (li + x - 1, lj + y - 1);
+                    let delete = forest_dist[(x - 1) * cols + y] + 1;
+                    let insert = forest_dist[x * cols + y - 1] + 1;
+
+                    if a.lmld[i1] == li && b.lmld[j1] == lj {
+                        let relabel = forest_dist[(x - 1) * cols + y - 1] + relabel_cost(i1, j1);
+                        let d = delete.min(insert).min(relabel);
+                        forest_dist[x * cols + y] = d;
+                        tree_dist[i1 * m + j1] = d;
+                    } else {
+                        let p = a.lmld[i1] - li;
+                        let q = b.lmld[j1] - lj;
+                        let subtree = forest_dist[p * cols + q] + tree_dist[i1 * m + j1];
+                        forest_dist[x * cols + y] = delete.min(insert).min(subtree);
+                    }
+                }
+            }
+        }
+    }
+
+    tree_dist[(n - 1) * m + m - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TagNode;
+    use std::sync::Arc;
+
+    fn node(tag: &str, children: Vec<TagNodeRef>) -> TagNodeRef {
+        let mut n = TagNode::new(tag.to_string(), String::new());
+        for child in children {
+            n.add_child(child);
+        }
+        Arc::new(n)
+    }
+
+    #[test]
+    fn tree_metrics_ignore_tag_name_length() {
+        let options = MdrOptions::default();
+        let short = [node("div", vec![node("b", vec![]), node("i", vec![])])];
+        let long = [node(
+            "div",
+            vec![node("blockquote", vec![]), node("i", vec![])],
+        )];
+
+        assert_eq!(TreeEditDistance.distance(&short, &short, &options), 0.0);
+        assert!((TreeEditDistance.distance(&short, &long, &options) - 1.0 / 3.0).abs() < 1e-6);
+        assert!((SimpleTreeMatching.distance(&short, &long, &options) - 1.0 / 3.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn tree_edit_distance_counts_structural_edits() {
+        let options = MdrOptions::default();
+        let a = [node("ul", vec![node("li", vec![]), node("li", vec![])])];
+        let b = [node("ul", vec![node("li", vec![node("a", vec![])])])];
+
+        // delete one <li>, insert one <a>
+        assert!((TreeEditDistance.distance(&a, &b, &options) - 2.0 / 3.0).abs() < 1e-6);
+        assert_eq!(TreeEditDistance.distance(&[], &[], &options), 0.0);
+
+        // An 8-node chain and an 8-node star share little beyond the root and take
+        // more edits than either has nodes
+        let chain = [(0..7).fold(node("div", vec![]), |child, _| node("div", vec![child]))];
+        let star = [node("div", (0..7).map(|_| node("div", vec![])).collect())];
+        assert_eq!(TreeEditDistance.distance(&chain, &star, &options), 1.0);
+    }
+}
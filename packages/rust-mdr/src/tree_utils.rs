@@ -1,4 +1,64 @@
-use crate::types::TagNodeRef;
+use crate::types::{MdrOptions, TagNodeRef};
+use dashmap::DashMap;
+use once_cell::sync::Lazy;
+use std::borrow::Cow;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Global tag interner: tag name → id. Only tag names go in, so it is bounded by the
+/// tag vocabulary; labels with attribute values are numbered per comparison in
+/// [`LabelIds`].
+static TAG_IDS: Lazy<DashMap<String, u32>> = Lazy::new(|| DashMap::with_capacity(256));
+static NEXT_TAG_ID: AtomicU32 = AtomicU32::new(0);
+
+pub(crate) fn intern_tag(tag_name: &str) -> u32 {
+    if let Some(id) = TAG_IDS.get(tag_name) {
+        return *id;
+    }
+    *TAG_IDS
+        .entry(tag_name.to_string())
+        .or_insert_with(|| NEXT_TAG_ID.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Ids of opening-tag labels carrying signature attribute values (`li class="item"`).
+/// Share one between the two sides of a comparison and drop it afterwards.
+#[derive(Default)]
+pub struct LabelIds(HashMap<String, u32>);
+
+impl LabelIds {
+    pub(crate) fn get(&mut self, label: String) -> u32 {
+        let next = self.0.len() as u32;
+        *self.0.entry(label).or_insert(next)
+    }
+}
+
+/// Attribute value as it appears in a signature: `&`, `"` and `>` are escaped so a
+/// value cannot close the attribute or tag and mimic another attribute set
+pub(crate) fn escape_signature_value(value: &str) -> Cow<'_, str> {
+    if !value.contains(['&', '"', '>']) {
+        return Cow::Borrowed(value);
+    }
+    let mut out = String::with_capacity(value.len() + 8);
+    for c in value.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '"' => out.push_str("&quot;"),
+            '>' => out.push_str("&gt;"),
+            c => out.push(c),
+        }
+    }
+    Cow::Owned(out)
+}
+
+/// Inverse of [`escape_signature_value`]
+pub(crate) fn unescape_signature_value(value: &str) -> String {
+    value
+        .replace("&quot;", "\"")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
 
 pub fn get_children(node: &TagNodeRef) -> Vec<TagNodeRef> {
     node.children.clone()
@@ -11,7 +71,7 @@ pub fn flatten_subtree(root: &TagNodeRef) -> String {
     }
 
     let mut buf: Vec<u8> = Vec::with_capacity(128);
-    inner_flatten(&mut buf, root);
+    inner_flatten(&mut buf, root, &[]);
 
     // Safety: we only push UTF-8 slices from existing Strings & ASCII literals.
     let s = unsafe { String::from_utf8_unchecked(buf) };
@@ -19,7 +79,65 @@ pub fn flatten_subtree(root: &TagNodeRef) -> String {
     s
 }
 
-fn inner_flatten(out: &mut Vec<u8>, node: &TagNodeRef) {
+/// Flattens a subtree, folding the given attributes into each opening tag
+/// (e.g. `<li class="item">`). Falls back to the cached plain signature when
+/// no attributes are selected.
+pub fn flatten_subtree_with_attributes(root: &TagNodeRef, attributes: &[String]) -> String {
+    if attributes.is_empty() {
+        return flatten_subtree(root);
+    }
+
+    let mut buf: Vec<u8> = Vec::with_capacity(256);
+    inner_flatten(&mut buf, root, attributes);
+
+    // Safety: we only push UTF-8 slices from existing Strings & ASCII literals.
+    unsafe { String::from_utf8_unchecked(buf) }
+}
+
+/// Flattens a subtree according to the signature settings in `options`
+pub fn flatten_subtree_with_options(root: &TagNodeRef, options: &MdrOptions) -> String {
+    flatten_subtree_with_attributes(root, &options.signature_attributes)
+}
+
+/// 128-bit hash of the flattened structural signature, the content address used by
+/// `distance_cache`. Cached on the node when no signature attributes are selected.
+pub fn structural_hash(root: &TagNodeRef, attributes: &[String]) -> u128 {
+    if attributes.is_empty() {
+        if let Some(cached) = *root.structural_hash_cache.lock() {
+            return cached;
+        }
+    }
+
+    let hash = hash128(&flatten_subtree_with_attributes(root, attributes));
+
+    if attributes.is_empty() {
+        *root.structural_hash_cache.lock() = Some(hash);
+    }
+    hash
+}
+
+/// Structural hash of a sequence of sibling subtrees
+pub fn structural_sequence_hash(nodes: &[TagNodeRef], attributes: &[String]) -> u128 {
+    let hashes: Vec<u128> = nodes
+        .iter()
+        .map(|node| structural_hash(node, attributes))
+        .collect();
+    hash128(&hashes)
+}
+
+/// Two independently seeded 64-bit hashes of `value`; a false cache hit needs both
+/// halves to collide
+pub(crate) fn hash128<T: Hash + ?Sized>(value: &T) -> u128 {
+    let half = |seed: u8| {
+        let mut hasher = DefaultHasher::new();
+        seed.hash(&mut hasher);
+        value.hash(&mut hasher);
+        hasher.finish()
+    };
+    (u128::from(half(0)) << 64) | u128::from(half(1))
+}
+
+fn inner_flatten(out: &mut Vec<u8>, node: &TagNodeRef, attributes: &[String]) {
     // Match TypeScript logic exactly:
     // If text node with non-empty trimmed content, return empty
     if node.tag_name == "text" {
@@ -33,11 +151,20 @@ fn inner_flatten(out: &mut Vec<u8>, node: &TagNodeRef) {
     // Opening tag  "<tag>"
     out.extend_from_slice(b"<");
     out.extend_from_slice(node.tag_name.as_bytes());
+    for name in attributes {
+        if let Some(value) = node.get_attribute(name) {
+            out.extend_from_slice(b" ");
+            out.extend_from_slice(name.as_bytes());
+            out.extend_from_slice(b"=\"");
+            out.extend_from_slice(escape_signature_value(value).as_bytes());
+            out.extend_from_slice(b"\"");
+        }
+    }
     out.extend_from_slice(b">");
 
     // Children
     for child in &node.children {
-        inner_flatten(out, child);
+        inner_flatten(out, child, attributes);
     }
 
     // Closing tag "</tag>" - but NOT for text nodes (matching TypeScript)
@@ -48,6 +175,62 @@ fn inner_flatten(out: &mut Vec<u8>, node: &TagNodeRef) {
     }
 }
 
+/// Flattens a subtree into tag tokens instead of characters, so every open/close tag
+/// costs one edit regardless of its name length. Opening tokens are even (`2·id`),
+/// closing tokens odd (`2·id + 1`); text nodes follow `inner_flatten`. Without
+/// attributes both use the interned tag id; with attributes the opening id comes from
+/// `labels`, so only token sequences flattened with the same `labels` are comparable.
+pub fn flatten_subtree_tokens(
+    root: &TagNodeRef,
+    attributes: &[String],
+    labels: &mut LabelIds,
+) -> Vec<u32> {
+    let mut out = Vec::with_capacity(32);
+    inner_flatten_tokens(&mut out, root, attributes, labels);
+    out
+}
+
+fn inner_flatten_tokens(
+    out: &mut Vec<u32>,
+    node: &TagNodeRef,
+    attributes: &[String],
+    labels: &mut LabelIds,
+) {
+    if node.tag_name == "text" {
+        if let Some(raw_text) = &node.raw_text {
+            if !raw_text.trim().is_empty() {
+                return; // Ignore text content for structural comparison
+            }
+        }
+    }
+
+    let tag_id = intern_tag(&node.tag_name);
+    let open_id = if attributes.is_empty() {
+        tag_id
+    } else {
+        let mut label = node.tag_name.clone();
+        for name in attributes {
+            if let Some(value) = node.get_attribute(name) {
+                label.push(' ');
+                label.push_str(name);
+                label.push_str("=\"");
+                label.push_str(&escape_signature_value(value));
+                label.push('"');
+            }
+        }
+        labels.get(label)
+    };
+    out.push(open_id * 2);
+
+    for child in &node.children {
+        inner_flatten_tokens(out, child, attributes, labels);
+    }
+
+    if node.tag_name != "text" {
+        out.push(tag_id * 2 + 1);
+    }
+}
+
 pub fn flatten_subtree_with_xpath(root: &TagNodeRef) -> String {
     let mut buf = String::with_capacity(256);
     inner_flatten_with_xpath(&mut buf, root);
@@ -106,11 +289,63 @@ pub fn get_depth(root: &TagNodeRef) -> usize {
         return 1;
     }
 
-    let max_child_depth = children
-        .iter()
-        .map(|child| get_depth(child))
-        .max()
-        .unwrap_or(0);
+    let max_child_depth = children.iter().map(get_depth).max().unwrap_or(0);
 
     1 + max_child_depth
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::TagNode;
+    use std::sync::Arc;
+
+    #[test]
+    fn flatten_with_attributes_includes_selected_only() {
+        let mut li = TagNode::new("li".to_string(), "/ul[1]/li[1]".to_string());
+        li.set_attribute("class".to_string(), "item".to_string());
+        li.set_attribute("id".to_string(), "a1".to_string());
+        let li = Arc::new(li);
+
+        assert_eq!(flatten_subtree(&li), "<li></li>");
+        assert_eq!(
+            flatten_subtree_with_attributes(&li, &["class".to_string()]),
+            "<li class=\"item\"></li>"
+        );
+
+        // A quote in a value must not read as a second attribute
+        let mut quoted = TagNode::new("li".to_string(), "/ul[1]/li[2]".to_string());
+        quoted.set_attribute("class".to_string(), "a\" id=\"b".to_string());
+        let mut split = TagNode::new("li".to_string(), "/ul[1]/li[3]".to_string());
+        split.set_attribute("class".to_string(), "a".to_string());
+        split.set_attribute("id".to_string(), "b".to_string());
+        let attributes = ["class".to_string(), "id".to_string()];
+        assert_eq!(
+            flatten_subtree_with_attributes(&Arc::new(quoted), &attributes),
+            "<li class=\"a&quot; id=&quot;b\"></li>"
+        );
+        assert_eq!(
+            flatten_subtree_with_attributes(&Arc::new(split), &attributes),
+            "<li class=\"a\" id=\"b\"></li>"
+        );
+    }
+
+    #[test]
+    fn attribute_labels_stay_out_of_the_tag_interner() {
+        let attributes = ["class".to_string()];
+        let mut labels = LabelIds::default();
+        let tokens: Vec<Vec<u32>> = ["a", "b", "a"]
+            .iter()
+            .map(|class| {
+                let mut li = TagNode::new("li".to_string(), "/ul[1]/li[1]".to_string());
+                li.set_attribute("class".to_string(), format!("item-{class}"));
+                flatten_subtree_tokens(&Arc::new(li), &attributes, &mut labels)
+            })
+            .collect();
+
+        assert_eq!(tokens[0], tokens[2]);
+        assert_ne!(tokens[0][0], tokens[1][0]);
+        assert_eq!(tokens[0][1], tokens[1][1]);
+        assert!(!TAG_IDS.iter().any(|entry| entry.key().contains(' ')));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::error::MdrError;
+use indexmap::IndexMap;
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
@@ -10,10 +12,16 @@ pub struct TagNode {
     #[serde(rename = "rawText")]
     pub raw_text: Option<String>,
     pub xpath: String,
+    /// Element attributes in document order; absent in trees built by the JS `buildTagTree`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub attributes: Option<IndexMap<String, String>>,
 
     #[serde(skip)]
     #[serde(default = "default_mutex")]
     pub flattened_cache: Mutex<Option<String>>,
+
+    #[serde(skip)]
+    pub structural_hash_cache: Mutex<Option<u128>>,
 }
 
 fn default_mutex() -> Mutex<Option<String>> {
@@ -37,12 +45,61 @@ pub enum DataRecord {
     Multi(Vec<TagNodeRef>),
 }
 
+/// Distance backend used to compare generalized nodes
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum SimilarityMetric {
+    /// Character-level Levenshtein over `flatten_subtree` strings (paper default)
+    #[default]
+    StringEditDistance,
+    /// Levenshtein over interned open/close tag tokens
+    TokenEditDistance,
+    /// Zhang–Shasha tree edit distance
+    TreeEditDistance,
+    /// Yang's simple tree matching
+    SimpleTreeMatching,
+}
+
+/// How orphans are merged with the records into `finalRecords`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum OrphanMerge {
+    /// Keep each distinct record once and append each orphan once, unless it is a node
+    /// of a record or lies inside a record node that contains no other record
+    #[default]
+    Dedup,
+    /// Keep every record and append each orphan whose xpath is not that of a
+    /// `Single` record, as the TypeScript pipeline did
+    Xpath,
+}
+
+/// Tunable knobs for the similarity comparisons used throughout MDR
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "camelCase", default)]
+pub struct MdrOptions {
+    /// Attributes (e.g. `class`) folded into the structural signature of each element
+    pub signature_attributes: Vec<String>,
+    pub similarity: SimilarityMetric,
+    pub orphan_merge: OrphanMerge,
+}
+
 /// Helper struct for the "single-call" pathway
 #[derive(Serialize)]
 pub struct MdrFullOutput {
     pub regions: Vec<RegionsMapItem>,
     pub records: Vec<DataRecord>,
     pub orphans: Vec<TagNodeRef>,
+    /// Records plus orphans merged per `MdrOptions::orphan_merge`, see
+    /// `assemble_final_records`
+    #[serde(rename = "finalRecords")]
+    pub final_records: Vec<DataRecord>,
+    /// Non-fatal problems, e.g. regions skipped because their parent xpath is missing
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<MdrError>,
+    /// The region search stopped early (limit or cancellation, reason in `warnings`);
+    /// the output covers only the part of the tree searched so far
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub truncated: bool,
 }
 
 impl Clone for TagNode {
@@ -52,7 +109,9 @@ impl Clone for TagNode {
             children: self.children.clone(),
             raw_text: self.raw_text.clone(),
             xpath: self.xpath.clone(),
+            attributes: self.attributes.clone(),
             flattened_cache: Mutex::new(None), // Don't clone the cache
+            structural_hash_cache: Mutex::new(None),
         }
     }
 }
@@ -64,7 +123,9 @@ impl TagNode {
             children: Vec::new(),
             raw_text: None,
             xpath,
+            attributes: None,
             flattened_cache: Mutex::new(None),
+            structural_hash_cache: Mutex::new(None),
         }
     }
 
@@ -75,4 +136,17 @@ impl TagNode {
     pub fn set_raw_text(&mut self, text: String) {
         self.raw_text = Some(text);
     }
+
+    pub fn set_attribute(&mut self, name: String, value: String) {
+        self.attributes
+            .get_or_insert_with(IndexMap::new)
+            .insert(name, value);
+    }
+
+    pub fn get_attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .as_ref()
+            .and_then(|attrs| attrs.get(name))
+            .map(String::as_str)
+    }
 }
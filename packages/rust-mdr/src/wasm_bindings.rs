@@ -1,9 +1,59 @@
+use crate::chunking::{chunk_page, merge_chunk_responses, ChunkOptions, TokenEstimator};
+use crate::context_reduction::{reduce_context, ReductionOptions};
+use crate::distance_cache::{DistanceCacheConfig, DISTANCE_CACHE};
+use crate::error::{validate_parameters, validate_threshold, MdrError};
+use crate::evaluation::{calculate_evaluation_metrics, calculate_overlap};
+use crate::html_parser::parse_page_html;
+use crate::llm_response::{map_response_to_full_xpath, parse_llm_response};
 use crate::mdr_algorithm::run_mdr_algorithm;
 use crate::record_extraction::{find_orphan_records, identify_all_data_records_with_tree};
+use crate::session::{run_mdr_pipeline, MdrContext, MdrRunOptions, MdrSession, MdrSessionConfig};
 use crate::similarity::edit_distance;
-use crate::types::{MdrFullOutput, RegionsMapItem, TagNodeRef};
+use crate::slim_html::slim_html;
+use crate::sweep::{sweep_parameters, SweepOptions};
+use crate::table_export::{extract_tables, TableExport, TableOptions};
+use crate::text_map::extract_text_with_xpaths;
+use crate::trace::run_mdr_algorithm_traced;
+use crate::types::{DataRecord, MdrOptions, RegionsMapItem, TagNodeRef};
+use crate::wrapper::{apply_wrapper, induce_wrapper, Wrapper, WrapperApplyOptions};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
 use wasm_bindgen::prelude::*;
 
+/// `MdrError` reaches JS as an `Error` named `MdrError` carrying `code` and `details`
+impl From<MdrError> for JsValue {
+    fn from(error: MdrError) -> Self {
+        let js_error = js_sys::Error::new(&error.to_string());
+        js_error.set_name("MdrError");
+        let _ = js_sys::Reflect::set(&js_error, &"code".into(), &error.code().into());
+        if let Ok(details) = error.serialize(&serde_wasm_bindgen::Serializer::json_compatible()) {
+            let _ = js_sys::Reflect::set(&js_error, &"details".into(), &details);
+        }
+        js_error.into()
+    }
+}
+
+fn from_js<T: DeserializeOwned>(value: JsValue, target: &str) -> Result<T, MdrError> {
+    serde_wasm_bindgen::from_value(value).map_err(|e| MdrError::deserialization(target, e))
+}
+
+/// Like [`from_js`], treating `undefined`/`null` as `T::default()`
+fn options_from_js<T: DeserializeOwned + Default>(
+    value: JsValue,
+    target: &str,
+) -> Result<T, MdrError> {
+    if value.is_undefined() || value.is_null() {
+        Ok(T::default())
+    } else {
+        from_js(value, target)
+    }
+}
+
+fn to_js<T: Serialize>(value: &T, target: &str) -> Result<JsValue, MdrError> {
+    serde_wasm_bindgen::to_value(value).map_err(|e| MdrError::serialization(target, e))
+}
+
 /// Initialize the WASM module (called automatically)
 #[wasm_bindgen(start)]
 pub fn init() {
@@ -31,14 +81,13 @@ pub fn run_mdr_algorithm_wasm(
 ) -> Result<JsValue, JsValue> {
     let k = k.unwrap_or(10);
     let t = t.unwrap_or(0.3);
+    validate_parameters(k, t)?;
 
-    let root_node: TagNodeRef = serde_wasm_bindgen::from_value(root)
-        .map_err(|e| JsValue::from_str(&format!("Failed to deserialize root node: {}", e)))?;
+    let root_node: TagNodeRef = from_js(root, "root node")?;
 
     let regions = run_mdr_algorithm(&root_node, k, t);
 
-    serde_wasm_bindgen::to_value(&regions)
-        .map_err(|e| JsValue::from_str(&format!("Failed to serialize regions: {}", e)))
+    Ok(to_js(&regions, "regions")?)
 }
 
 /// Identify all data records from regions
@@ -48,16 +97,13 @@ pub fn identify_all_data_records_wasm(
     t: f32,
     root: JsValue,
 ) -> Result<JsValue, JsValue> {
-    let regions: Vec<RegionsMapItem> = serde_wasm_bindgen::from_value(regions_js)
-        .map_err(|e| JsValue::from_str(&format!("Failed to deserialize regions: {}", e)))?;
-
-    let root_node: TagNodeRef = serde_wasm_bindgen::from_value(root)
-        .map_err(|e| JsValue::from_str(&format!("Failed to deserialize root node: {}", e)))?;
+    validate_threshold(t)?;
+    let regions: Vec<RegionsMapItem> = from_js(regions_js, "regions")?;
+    let root_node: TagNodeRef = from_js(root, "root node")?;
 
     let records = identify_all_data_records_with_tree(&regions, t, &root_node);
 
-    serde_wasm_bindgen::to_value(&records)
-        .map_err(|e| JsValue::from_str(&format!("Failed to serialize records: {}", e)))
+    Ok(to_js(&records, "records")?)
 }
 
 /// Find orphan records
@@ -67,16 +113,13 @@ pub fn find_orphan_records_wasm(
     t: f32,
     root: JsValue,
 ) -> Result<JsValue, JsValue> {
-    let regions: Vec<RegionsMapItem> = serde_wasm_bindgen::from_value(regions_js)
-        .map_err(|e| JsValue::from_str(&format!("Failed to deserialize regions: {}", e)))?;
-
-    let root_node: TagNodeRef = serde_wasm_bindgen::from_value(root)
-        .map_err(|e| JsValue::from_str(&format!("Failed to deserialize root node: {}", e)))?;
+    validate_threshold(t)?;
+    let regions: Vec<RegionsMapItem> = from_js(regions_js, "regions")?;
+    let root_node: TagNodeRef = from_js(root, "root node")?;
 
     let orphans = find_orphan_records(&regions, t, &root_node);
 
-    serde_wasm_bindgen::to_value(&orphans)
-        .map_err(|e| JsValue::from_str(&format!("Failed to serialize orphans: {}", e)))
+    Ok(to_js(&orphans, "orphans")?)
 }
 
 /// Calculate normalized edit distance between two strings (for testing/compatibility)
@@ -91,36 +134,381 @@ pub fn get_normalized_edit_distance_wasm_legacy(s1: &str, s2: &str) -> f32 {
     edit_distance(s1, s2)
 }
 
-/// Clear the distance cache between pages
+/// Drop every cached distance (statistics are kept). No longer required between
+/// pages: entries are keyed by subtree structure, not by node identity.
 #[wasm_bindgen(js_name = clearDistanceCache)]
 pub fn clear_distance_cache() {
-    use crate::similarity::NODE_DIST_CACHE;
-    NODE_DIST_CACHE.clear();
+    DISTANCE_CACHE.clear();
+}
+
+/// Set the distance cache capacity and eviction policy (drops all entries)
+#[wasm_bindgen(js_name = configureDistanceCache)]
+pub fn configure_distance_cache(config: JsValue) -> Result<(), JsValue> {
+    let config: DistanceCacheConfig = options_from_js(config, "cache config")?;
+    DISTANCE_CACHE.configure(config);
+    Ok(())
+}
+
+/// Hit/miss/eviction counters and current size of the distance cache
+#[wasm_bindgen(js_name = getDistanceCacheStats)]
+pub fn get_distance_cache_stats() -> Result<JsValue, JsValue> {
+    Ok(to_js(&DISTANCE_CACHE.stats(), "cache stats")?)
+}
+
+/// Reset the distance cache counters
+#[wasm_bindgen(js_name = resetDistanceCacheStats)]
+pub fn reset_distance_cache_stats() {
+    DISTANCE_CACHE.reset_stats();
 }
 
-/// End-to-end MDR: regions → records → orphans in **one** bridge call.
+/// Region search that also returns every `ident_drs` comparison and decision per parent
+#[wasm_bindgen(js_name = traceMdr)]
+pub fn trace_mdr(
+    root: JsValue,
+    k: Option<usize>,
+    t: Option<f32>,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
+    let k = k.unwrap_or(10);
+    let t = t.unwrap_or(0.3);
+    validate_parameters(k, t)?;
+
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+    let options: MdrOptions = options_from_js(options, "options")?;
+
+    let out = run_mdr_algorithm_traced(&root_node, k, t, &options);
+    Ok(to_js(&out, "MDR trace")?)
+}
+
+/// End-to-end MDR: regions → records → orphans → final records in **one** bridge call.
+/// With `options.limits` a run that hits a limit returns its partial result flagged
+/// `truncated` instead of blocking the worker.
 #[wasm_bindgen(js_name = runMdrFull)]
-pub fn run_mdr_full(root: JsValue, k: Option<usize>, t: Option<f32>) -> Result<JsValue, JsValue> {
+pub fn run_mdr_full(
+    root: JsValue,
+    k: Option<usize>,
+    t: Option<f32>,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
     let k = k.unwrap_or(10);
     let t = t.unwrap_or(0.3);
+    validate_parameters(k, t)?;
 
-    let root_node: TagNodeRef = serde_wasm_bindgen::from_value(root)
-        .map_err(|e| JsValue::from_str(&format!("root deserialise: {}", e)))?;
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+    let options: MdrRunOptions = options_from_js(options, "options")?;
 
-    // Step 1 – regions
-    let regions = run_mdr_algorithm(&root_node, k, t);
+    let context = MdrContext::global(k, t, options.mdr).with_limits(options.limits);
+    let out = run_mdr_pipeline(&root_node, &context);
+    Ok(to_js(&out, "MDR output")?)
+}
 
-    // Step 2 – records
-    let records = identify_all_data_records_with_tree(&regions, t, &root_node);
+/// End-to-end MDR straight from an HTML string, skipping the JS tag-tree step.
+#[wasm_bindgen(js_name = runMdrFromHtml)]
+pub fn run_mdr_from_html(html: &str, k: Option<usize>, t: Option<f32>) -> Result<JsValue, JsValue> {
+    let k = k.unwrap_or(10);
+    let t = t.unwrap_or(0.3);
+    validate_parameters(k, t)?;
 
-    // Step 3 – orphans
-    let orphans = find_orphan_records(&regions, t, &root_node);
+    let root_node = parse_page_html(html);
+
+    let context = MdrContext::global(k, t, MdrOptions::default());
+    let out = run_mdr_pipeline(&root_node, &context);
+    Ok(to_js(&out, "MDR output")?)
+}
+
+/// The "slim" LLM input format (no scripts, styles, comments, attributes or extra
+/// whitespace), byte-compatible with `slimHtml` from `@wordbricks/next-eval`.
+#[wasm_bindgen(js_name = slimHtml)]
+pub fn slim_html_wasm(html: &str) -> String {
+    slim_html(html)
+}
+
+/// `{ textMapFlat, textMap }` (the flat and hier LLM input formats) of a tag tree,
+/// like `extractTextWithXPaths`
+#[wasm_bindgen(js_name = extractTextMaps)]
+pub fn extract_text_maps_wasm(root: JsValue) -> Result<JsValue, JsValue> {
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+
+    let maps = extract_text_with_xpaths(&root_node);
+
+    // Text maps must become plain objects, not JS `Map`s
+    Ok(maps
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| MdrError::serialization("text maps", e))?)
+}
+
+/// The record xpath lists of an LLM reply (bare or fenced JSON), like
+/// `parseAndValidateXPaths`; throws `DESERIALIZE` where that returns `null`
+#[wasm_bindgen(js_name = parseLlmResponse)]
+pub fn parse_llm_response_wasm(content: &str) -> Result<JsValue, JsValue> {
+    let records = parse_llm_response(content)?;
+    Ok(to_js(&records, "LLM response")?)
+}
+
+/// `{ records, numHallucination, invalidXpaths }`: predicted records (LLM or MDR
+/// xpaths) expanded to the `textMapFlat` keys of the tree, like
+/// `mapResponseToFullXpath`, with every xpath that matched no text
+#[wasm_bindgen(js_name = mapResponseToFullXpath)]
+pub fn map_response_to_full_xpath_wasm(
+    root: JsValue,
+    response: JsValue,
+) -> Result<JsValue, JsValue> {
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+    let response: Vec<Vec<String>> = from_js(response, "response")?;
+
+    let mapped = map_response_to_full_xpath(&root_node, &response);
+    Ok(to_js(&mapped, "mapped response")?)
+}
+
+/// The page cut down to its MDR data regions (plus `contextLevels` of context) in
+/// all three prompt formats: `{ regions, slimHtml, textMapFlat, textMap, xpathMap,
+/// stats }`. Pass `regions` in `opts` to reuse regions instead of running MDR.
+#[wasm_bindgen(js_name = reduceContext)]
+pub fn reduce_context_wasm(root: JsValue, opts: JsValue) -> Result<JsValue, JsValue> {
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+    let options: ReductionOptions = options_from_js(opts, "reduction options")?;
+    validate_parameters(options.k, options.t)?;
+
+    let page = reduce_context(&root_node, &options);
+
+    // Text and xpath maps must become plain objects, not JS `Map`s
+    Ok(page
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| MdrError::serialization("reduced page", e))?)
+}
+
+/// Maps an LLM reply about a reduced page to `textMapFlat` keys of the original page,
+/// like `mapResponseToFullXpath`; `opts` must reproduce the reduction, e.g. with the
+/// page's `regions`
+#[wasm_bindgen(js_name = mapReducedResponse)]
+pub fn map_reduced_response_wasm(
+    root: JsValue,
+    response: JsValue,
+    opts: JsValue,
+) -> Result<JsValue, JsValue> {
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+    let response: Vec<Vec<String>> = from_js(response, "response")?;
+    let options: ReductionOptions = options_from_js(opts, "reduction options")?;
+    validate_parameters(options.k, options.t)?;
 
-    let out = MdrFullOutput {
-        regions,
-        records,
-        orphans,
+    let mapped = reduce_context(&root_node, &options).map_response(&response);
+    Ok(to_js(&mapped, "mapped response")?)
+}
+
+/// Runs `chunk` with `estimateTokens(text) => number` as estimator, or the default
+/// one; the first exception or non-numeric result is rethrown
+fn with_js_estimator<T>(
+    estimate_tokens: Option<js_sys::Function>,
+    options: &ChunkOptions,
+    chunk: impl FnOnce(&dyn TokenEstimator) -> T,
+) -> Result<T, JsValue> {
+    let Some(function) = estimate_tokens else {
+        return Ok(chunk(&options.default_estimator()));
     };
-    serde_wasm_bindgen::to_value(&out)
-        .map_err(|e| JsValue::from_str(&format!("serialise full: {}", e)))
+    let error: RefCell<Option<JsValue>> = RefCell::new(None);
+    let estimator = |text: &str| {
+        let tokens = function
+            .call1(&JsValue::NULL, &JsValue::from_str(text))
+            .and_then(|tokens| {
+                tokens.as_f64().ok_or_else(|| {
+                    js_sys::TypeError::new("estimateTokens must return a number").into()
+                })
+            });
+        match tokens {
+            Ok(tokens) => tokens.max(0.0) as usize,
+            Err(e) => {
+                error.borrow_mut().get_or_insert(e);
+                0
+            }
+        }
+    };
+    let out = chunk(&estimator);
+    match error.into_inner() {
+        Some(e) => Err(e),
+        None => Ok(out),
+    }
+}
+
+/// Splits the page into chunks of whole subtrees under `maxTokens`, never splitting
+/// an MDR data region; each chunk carries `xpaths`, `startXpath`/`endXpath`,
+/// `tokens` and the reduced page fields of `reduceContext`
+#[wasm_bindgen(js_name = chunkPage)]
+pub fn chunk_page_wasm(
+    root: JsValue,
+    opts: JsValue,
+    estimate_tokens: Option<js_sys::Function>,
+) -> Result<JsValue, JsValue> {
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+    let options: ChunkOptions = options_from_js(opts, "chunk options")?;
+    validate_parameters(options.k, options.t)?;
+
+    let chunks = with_js_estimator(estimate_tokens, &options, |estimator| {
+        chunk_page(&root_node, &options, estimator)
+    })?;
+
+    // Text and xpath maps must become plain objects, not JS `Map`s
+    Ok(chunks
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| MdrError::serialization("chunks", e))?)
+}
+
+/// Merges one LLM reply per chunk into records over the original page, like
+/// `mapResponseToFullXpath`; `opts` and `estimateTokens` must reproduce the chunks,
+/// e.g. with `regions` set
+#[wasm_bindgen(js_name = mergeChunkResponses)]
+pub fn merge_chunk_responses_wasm(
+    root: JsValue,
+    responses: JsValue,
+    opts: JsValue,
+    estimate_tokens: Option<js_sys::Function>,
+) -> Result<JsValue, JsValue> {
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+    let responses: Vec<Vec<Vec<String>>> = from_js(responses, "responses")?;
+    let options: ChunkOptions = options_from_js(opts, "chunk options")?;
+    validate_parameters(options.k, options.t)?;
+
+    let chunks = with_js_estimator(estimate_tokens, &options, |estimator| {
+        chunk_page(&root_node, &options, estimator)
+    })?;
+    let merged = merge_chunk_responses(&chunks, &responses)?;
+    Ok(to_js(&merged, "merged response")?)
+}
+
+/// Turns every MDR data region into a table (JSON rows, CSV and column schema).
+#[wasm_bindgen(js_name = extractTables)]
+pub fn extract_tables_wasm(root: JsValue, opts: JsValue) -> Result<JsValue, JsValue> {
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+    let options: TableOptions = options_from_js(opts, "options")?;
+    validate_parameters(options.k, options.t)?;
+
+    let tables: Vec<TableExport> = extract_tables(&root_node, &options)
+        .iter()
+        .map(|table| table.export())
+        .collect();
+
+    // Row maps must become plain objects, not JS `Map`s
+    Ok(tables
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| MdrError::serialization("tables", e))?)
+}
+
+/// Runs MDR for every `(k, t)` of a grid on one tree, reusing distances across settings
+#[wasm_bindgen(js_name = sweepParameters)]
+pub fn sweep_parameters_wasm(root: JsValue, opts: JsValue) -> Result<JsValue, JsValue> {
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+    let options: SweepOptions = options_from_js(opts, "options")?;
+
+    let output = sweep_parameters(&root_node, &options)?;
+
+    // Flattened results must become plain objects, not JS `Map`s
+    Ok(output
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .map_err(|e| MdrError::serialization("sweep output", e))?)
+}
+
+/// Precision/recall/F1 of predicted records (xpath arrays) against ground truth
+#[wasm_bindgen(js_name = calculateEvaluationMetrics)]
+pub fn calculate_evaluation_metrics_wasm(
+    predicted_records: JsValue,
+    ground_truth_records: JsValue,
+) -> Result<JsValue, JsValue> {
+    let predicted: Vec<Vec<String>> = from_js(predicted_records, "predicted records")?;
+    let ground_truth: Vec<Vec<String>> = from_js(ground_truth_records, "ground truth records")?;
+
+    let result = calculate_evaluation_metrics(&predicted, &ground_truth);
+
+    Ok(to_js(&result, "metrics")?)
+}
+
+/// Jaccard overlap between two records (xpath arrays)
+#[wasm_bindgen(js_name = calculateOverlap)]
+pub fn calculate_overlap_wasm(record1: Vec<String>, record2: Vec<String>) -> f64 {
+    calculate_overlap(&record1, &record2)
+}
+
+/// The parts of an MDR result a wrapper is induced from; other fields are ignored
+#[derive(Deserialize)]
+struct InductionInput {
+    regions: Vec<RegionsMapItem>,
+    records: Vec<DataRecord>,
+}
+
+/// Induces a reusable wrapper from a page and its MDR output (`runMdrFull` result)
+#[wasm_bindgen(js_name = induceWrapper)]
+pub fn induce_wrapper_wasm(
+    root: JsValue,
+    mdr_output: JsValue,
+    k: Option<usize>,
+    t: Option<f32>,
+    options: JsValue,
+) -> Result<JsValue, JsValue> {
+    let k = k.unwrap_or(10);
+    let t = t.unwrap_or(0.3);
+    validate_parameters(k, t)?;
+
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+    let input: InductionInput = from_js(mdr_output, "MDR output")?;
+    let options: MdrOptions = options_from_js(options, "options")?;
+
+    let context = MdrContext::global(k, t, options);
+    let wrapper = induce_wrapper(&root_node, &input.regions, &input.records, &context);
+    Ok(to_js(&wrapper, "wrapper")?)
+}
+
+/// Extracts records from a page with a wrapper, falling back to full MDR on mismatch
+#[wasm_bindgen(js_name = applyWrapper)]
+pub fn apply_wrapper_wasm(
+    wrapper: JsValue,
+    root: JsValue,
+    opts: JsValue,
+) -> Result<JsValue, JsValue> {
+    let wrapper: Wrapper = from_js(wrapper, "wrapper")?;
+    validate_parameters(wrapper.k, wrapper.t)?;
+    let root_node: TagNodeRef = from_js(root, "root node")?;
+    let options: WrapperApplyOptions = options_from_js(opts, "options")?;
+
+    let result = apply_wrapper(&wrapper, &root_node, &options);
+    Ok(to_js(&result, "wrapper result")?)
+}
+
+/// Long-lived MDR session owning its configuration, distance cache and statistics.
+/// Sessions are isolated from each other and from the global cache.
+#[wasm_bindgen(js_name = MdrSession)]
+pub struct WasmMdrSession {
+    inner: MdrSession,
+}
+
+#[wasm_bindgen(js_class = MdrSession)]
+impl WasmMdrSession {
+    /// `config`: `{ k?, t?, signatureAttributes?, similarity?, cache?: { capacity?, policy? },
+    /// limits?: { maxNodes?, maxDepth?, maxComparisons?, timeBudgetMs? } }`
+    #[wasm_bindgen(constructor)]
+    pub fn new(config: JsValue) -> Result<WasmMdrSession, JsValue> {
+        let config: MdrSessionConfig = options_from_js(config, "session config")?;
+        Ok(WasmMdrSession {
+            inner: MdrSession::new(config)?,
+        })
+    }
+
+    /// Same output as `runMdrFull`, using the session's parameters and cache
+    pub fn run(&self, root: JsValue) -> Result<JsValue, JsValue> {
+        let root_node: TagNodeRef = from_js(root, "root node")?;
+        Ok(to_js(&self.inner.run(&root_node), "MDR output")?)
+    }
+
+    /// Same output as `runMdrFromHtml`, using the session's parameters and cache
+    #[wasm_bindgen(js_name = runHtml)]
+    pub fn run_html(&self, html: &str) -> Result<JsValue, JsValue> {
+        Ok(to_js(&self.inner.run_html(html), "MDR output")?)
+    }
+
+    /// `{ pages, regions, records, comparisons, cache: { hits, misses, ... } }`
+    pub fn stats(&self) -> Result<JsValue, JsValue> {
+        Ok(to_js(&self.inner.stats(), "session stats")?)
+    }
+
+    #[wasm_bindgen(js_name = clearCache)]
+    pub fn clear_cache(&self) {
+        self.inner.clear_cache();
+    }
 }
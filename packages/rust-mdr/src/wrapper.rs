@@ -0,0 +1,674 @@
+use crate::mdr_algorithm::own_regions;
+use crate::record_extraction::assemble_final_records;
+use crate::session::{run_mdr_pipeline, MdrContext};
+use crate::similarity::flatten_node_sequence;
+use crate::tree_alignment::{group_records_by_region, record_nodes};
+use crate::tree_utils::unescape_signature_value;
+use crate::types::{DataRecord, DataRegion, MdrOptions, RegionsMapItem, TagNode, TagNodeRef};
+use crate::xpath_index::XpathIndex;
+use indexmap::{IndexMap, IndexSet};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+/// Upper bound on parents tried per rule when the pattern has to be widened
+const MAX_CANDIDATES: usize = 64;
+
+/// How records are cut out of each generalized node of a region
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum RecordShape {
+    /// The generalized node itself (`Single` for length 1, `Multi` otherwise)
+    Node,
+    /// Every child of a length-1 generalized node is a `Single` record
+    Children,
+    /// Record `i` groups the `i`-th child of every component (`Multi`)
+    Columns,
+}
+
+/// Extraction rule learned from one data region
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct WrapperRule {
+    /// Parent xpath on the page the wrapper was induced from
+    #[serde(rename = "parent_xpath")]
+    pub parent_xpath: String,
+    /// Parents the rule applies to: `parent_xpath` with every index replaced by `[*]`.
+    /// Pin an index (`div[2]`) to narrow the match.
+    pub parent_pattern: String,
+    pub gn_length: usize,
+    pub record_shape: RecordShape,
+    /// Paths of a record's nodes relative to the parent, the `j`-th one starting at
+    /// component `j` of the generalized node, e.g. `li[*]` or `tr[*]/td[*]`. A step
+    /// the region's generalized nodes disagree on is `*` (any element), `text()` or
+    /// `node()`. Records are cut from the nodes these paths match.
+    pub record_paths: Vec<String>,
+    /// Flattened structure of the representative generalized node, compared with the
+    /// wrapper's similarity metric
+    pub signature: String,
+    /// Records the region produced on the source page (weight of the rule)
+    pub record_count: usize,
+}
+
+/// Serializable extraction wrapper for pages sharing a template
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Wrapper {
+    pub k: usize,
+    pub t: f32,
+    #[serde(default)]
+    pub options: MdrOptions,
+    pub rules: Vec<WrapperRule>,
+}
+
+/// Options for [`apply_wrapper`]
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase", default)]
+pub struct WrapperApplyOptions {
+    /// Below this confidence the page is treated as not matching the wrapper
+    pub min_confidence: f32,
+    /// Run full MDR when the wrapper does not match
+    pub fallback: bool,
+}
+
+impl Default for WrapperApplyOptions {
+    fn default() -> Self {
+        WrapperApplyOptions {
+            min_confidence: 0.6,
+            fallback: true,
+        }
+    }
+}
+
+/// Where a rule matched on the new page
+#[derive(Serialize, Debug, Clone)]
+pub struct RuleMatch {
+    pub rule: usize,
+    pub parent_xpath: String,
+    pub region: DataRegion,
+    /// Mean similarity (`1 - distance`) of the matched generalized nodes to the signature
+    pub confidence: f32,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WrapperResult {
+    pub regions: Vec<RegionsMapItem>,
+    pub records: Vec<DataRecord>,
+    pub matches: Vec<RuleMatch>,
+    /// Record-weighted mean of the rule confidences; unmatched rules count as 0
+    pub confidence: f32,
+    /// True when `regions`/`records` come from full MDR instead of the wrapper
+    pub fallback: bool,
+}
+
+/// Derives a wrapper from a page's MDR result (`regions` and `records`), one rule per
+/// data region that produced records.
+pub fn induce_wrapper(
+    root: &TagNodeRef,
+    regions: &[RegionsMapItem],
+    records: &[DataRecord],
+    context: &MdrContext,
+) -> Wrapper {
+    // Inherited copies of descendant regions would steal records from the real ones
+    let regions = own_regions(regions, &XpathIndex::new(root), context);
+
+    let rules = group_records_by_region(&regions, records, root)
+        .into_iter()
+        .filter_map(|group| {
+            let (gn_length, start_idx, node_count) = group.region;
+            let representative = group
+                .parent
+                .children
+                .get(start_idx..start_idx + gn_length)?;
+            let covered = group
+                .parent
+                .children
+                .get(start_idx..start_idx + node_count)?;
+            let first_nodes = record_nodes(group.records.first()?);
+            let first = first_nodes.first()?;
+
+            let is_covered_child = covered.iter().any(|child| child.xpath == first.xpath);
+            let record_shape = match (is_covered_child, gn_length) {
+                (true, _) => RecordShape::Node,
+                (false, 1) => RecordShape::Children,
+                (false, _) => RecordShape::Columns,
+            };
+
+            Some(WrapperRule {
+                parent_pattern: wildcard_indices(&group.parent_xpath),
+                record_paths: record_paths(covered, gn_length, record_shape),
+                signature: flatten_node_sequence(representative, &context.options),
+                record_count: group.records.len(),
+                parent_xpath: group.parent_xpath,
+                gn_length,
+                record_shape,
+            })
+        })
+        .collect();
+
+    Wrapper {
+        k: context.k,
+        t: context.t,
+        options: context.options.clone(),
+        rules,
+    }
+}
+
+/// Applies `wrapper` to a new page.
+///
+/// Each rule looks for its parent among the nodes matching `parent_pattern`, the
+/// recorded `parent_xpath` first, taking the longest run of generalized nodes within
+/// `t` of the rule signature. Records are the nodes matching `record_paths` in each
+/// generalized node, grouped by `record_shape`. When the overall confidence is below
+/// `min_confidence` and `fallback` is set, full MDR runs with the wrapper's parameters.
+pub fn apply_wrapper(
+    wrapper: &Wrapper,
+    root: &TagNodeRef,
+    options: &WrapperApplyOptions,
+) -> WrapperResult {
+    // Parent xpath → children already taken by an earlier rule
+    let mut claimed: HashMap<String, HashSet<usize>> = HashMap::new();
+    let mut matches = Vec::new();
+    let mut regions: IndexMap<String, Vec<DataRegion>> = IndexMap::new();
+    let mut records = Vec::new();
+    let mut weighted = 0.0f32;
+    let mut total_weight = 0.0f32;
+
+    for (rule_idx, rule) in wrapper.rules.iter().enumerate() {
+        let weight = rule.record_count.max(1) as f32;
+        total_weight += weight;
+
+        let representative = signature_nodes(&rule.signature);
+        let found = candidate_parents(root, &rule.parent_pattern, &rule.parent_xpath)
+            .into_iter()
+            .find_map(|parent| {
+                let taken = claimed.get(&parent.xpath);
+                match_rule(&parent, rule, &representative, wrapper, taken)
+                    .map(|found| (parent, found))
+            });
+        let Some((parent, (region, confidence))) = found else {
+            continue;
+        };
+
+        let (gn_length, start_idx, node_count) = region;
+        claimed
+            .entry(parent.xpath.clone())
+            .or_default()
+            .extend(start_idx..start_idx + node_count);
+        for gn in parent.children[start_idx..start_idx + node_count].chunks(gn_length) {
+            records.extend(cut_records(gn, rule));
+        }
+
+        weighted += weight * confidence;
+        regions
+            .entry(parent.xpath.clone())
+            .or_default()
+            .push(region);
+        matches.push(RuleMatch {
+            rule: rule_idx,
+            parent_xpath: parent.xpath.clone(),
+            region,
+            confidence,
+        });
+    }
+
+    let confidence = if total_weight > 0.0 {
+        weighted / total_weight
+    } else {
+        0.0
+    };
+
+    if confidence < options.min_confidence && options.fallback {
+        let context = MdrContext::global(wrapper.k, wrapper.t, wrapper.options.clone());
+        let output = run_mdr_pipeline(root, &context);
+        return WrapperResult {
+            regions: output.regions,
+            records: output.final_records,
+            matches,
+            confidence,
+            fallback: true,
+        };
+    }
+
+    WrapperResult {
+        regions: regions
+            .into_iter()
+            .map(|(parent_xpath, regions)| RegionsMapItem {
+                parent_xpath,
+                regions,
+            })
+            .collect(),
+        records: assemble_final_records(&records, &[], wrapper.options.orphan_merge),
+        matches,
+        confidence,
+        fallback: false,
+    }
+}
+
+/// Longest unclaimed run of at least two generalized nodes under `parent` within `t`
+/// of the rule's `representative` under the wrapper's metric, with its mean similarity
+fn match_rule(
+    parent: &TagNodeRef,
+    rule: &WrapperRule,
+    representative: &[TagNodeRef],
+    wrapper: &Wrapper,
+    claimed: Option<&HashSet<usize>>,
+) -> Option<(DataRegion, f32)> {
+    let children = &parent.children;
+    let gn_length = rule.gn_length;
+    if gn_length == 0 || children.len() < 2 * gn_length {
+        return None;
+    }
+
+    let backend = wrapper.options.similarity.backend();
+    let distances: Vec<Option<f32>> = (0..=children.len() - gn_length)
+        .map(|start| {
+            if claimed
+                .is_some_and(|claimed| (start..start + gn_length).any(|i| claimed.contains(&i)))
+            {
+                return None;
+            }
+            backend.distance_within(
+                &children[start..start + gn_length],
+                representative,
+                &wrapper.options,
+                wrapper.t,
+            )
+        })
+        .collect();
+
+    let mut best: Option<(usize, usize, f32)> = None;
+    for start in 0..distances.len() {
+        let mut count = 0;
+        let mut similarity = 0.0;
+        let mut idx = start;
+        while let Some(Some(distance)) = distances.get(idx) {
+            count += 1;
+            similarity += 1.0 - distance;
+            idx += gn_length;
+        }
+        if count >= 2 && best.is_none_or(|(_, best_count, _)| count > best_count) {
+            best = Some((start, count, similarity / count as f32));
+        }
+    }
+
+    best.map(|(start, count, confidence)| ((gn_length, start, count * gn_length), confidence))
+}
+
+/// Rebuilds the structure a signature was flattened from: the tags, the signature
+/// attributes and the text nodes `flatten_subtree` keeps. Every similarity backend
+/// ignores what the signature drops, so distances to these nodes equal distances to
+/// the original generalized node.
+fn signature_nodes(signature: &str) -> Vec<TagNodeRef> {
+    let mut stack = vec![TagNode::new(String::new(), String::new())];
+    let mut rest = signature;
+    while let Some(tag_start) = rest.find('<') {
+        let Some(tag_len) = rest[tag_start..].find('>') else {
+            break;
+        };
+        let tag = &rest[tag_start + 1..tag_start + tag_len];
+        rest = &rest[tag_start + tag_len + 1..];
+
+        if tag.starts_with('/') {
+            if stack.len() > 1 {
+                let closed = Arc::new(stack.pop().expect("stack has an open element"));
+                stack.last_mut().expect("root stays").add_child(closed);
+            }
+            continue;
+        }
+        let (name, mut attributes) = tag.split_once(' ').unwrap_or((tag, ""));
+        let mut node = TagNode::new(name.to_string(), String::new());
+        while let Some((attribute, after)) = attributes.trim_start().split_once("=\"") {
+            let (value, after) = after.split_once('"').unwrap_or((after, ""));
+            node.set_attribute(attribute.to_string(), unescape_signature_value(value));
+            attributes = after;
+        }
+        if name == "text" {
+            // Only whitespace-only text nodes reach the signature, without a close tag
+            stack
+                .last_mut()
+                .expect("root stays")
+                .add_child(Arc::new(node));
+        } else {
+            stack.push(node);
+        }
+    }
+    while stack.len() > 1 {
+        let unclosed = Arc::new(stack.pop().expect("stack has an open element"));
+        stack.last_mut().expect("root stays").add_child(unclosed);
+    }
+    stack.pop().expect("root stays").children
+}
+
+/// Records of one generalized node: record path `j` is matched inside component `j`,
+/// and the matches are grouped as `record_shape` says
+fn cut_records(gn: &[TagNodeRef], rule: &WrapperRule) -> Vec<DataRecord> {
+    let matched: Vec<Vec<TagNodeRef>> = gn
+        .iter()
+        .zip(&rule.record_paths)
+        .map(|(component, path)| {
+            let segments: Vec<PatternSegment> =
+                path.split('/').map(parse_pattern_segment).collect();
+            let mut out = Vec::new();
+            if segments
+                .first()
+                .is_some_and(|&first| matches_segment(component, first))
+            {
+                collect_matches(component, &segments[1..], &mut out);
+            }
+            out
+        })
+        .collect();
+
+    match rule.record_shape {
+        RecordShape::Node => {
+            let mut nodes: Vec<TagNodeRef> = matched.into_iter().flatten().collect();
+            match nodes.len() {
+                0 => Vec::new(),
+                1 => vec![DataRecord::Single(nodes.remove(0))],
+                _ => vec![DataRecord::Multi(nodes)],
+            }
+        }
+        RecordShape::Children => matched
+            .into_iter()
+            .flatten()
+            .map(DataRecord::Single)
+            .collect(),
+        RecordShape::Columns => {
+            let rows = matched.first().map_or(0, Vec::len);
+            (0..rows)
+                .map(|i| {
+                    DataRecord::Multi(
+                        matched
+                            .iter()
+                            .filter_map(|nodes| nodes.get(i).cloned())
+                            .collect(),
+                    )
+                })
+                .collect()
+        }
+    }
+}
+
+/// Descendants of `node` along `segments`, in document order
+fn collect_matches(node: &TagNodeRef, segments: &[PatternSegment], out: &mut Vec<TagNodeRef>) {
+    let Some(&segment) = segments.first() else {
+        out.push(node.clone());
+        return;
+    };
+    for child in &node.children {
+        if matches_segment(child, segment) {
+            collect_matches(child, &segments[1..], out);
+        }
+    }
+}
+
+/// A step of a parent pattern or record path
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PatternSegment<'a> {
+    /// `div[3]`, or `div[*]` with no index
+    Element(&'a str, Option<usize>),
+    /// `*`
+    AnyElement,
+    /// `text()`
+    Text,
+    /// `node()`
+    Any,
+}
+
+fn parse_pattern_segment(segment: &str) -> PatternSegment<'_> {
+    match segment {
+        "*" => PatternSegment::AnyElement,
+        "text()" => PatternSegment::Text,
+        "node()" => PatternSegment::Any,
+        _ => match segment.split_once('[') {
+            Some((tag, rest)) => {
+                PatternSegment::Element(tag, rest.trim_end_matches(']').parse().ok())
+            }
+            None => PatternSegment::Element(segment, None),
+        },
+    }
+}
+
+fn matches_segment(node: &TagNodeRef, segment: PatternSegment) -> bool {
+    let is_text = node.tag_name == "text";
+    match segment {
+        PatternSegment::Element(tag, index) => {
+            node.tag_name == tag && index.is_none_or(|index| node_index(node) == index)
+        }
+        PatternSegment::AnyElement => !is_text,
+        PatternSegment::Text => is_text,
+        PatternSegment::Any => true,
+    }
+}
+
+/// Same-tag sibling index of an element, from the last step of its xpath
+fn node_index(node: &TagNodeRef) -> usize {
+    parse_segment(node.xpath.rsplit('/').next().unwrap_or("")).1
+}
+
+/// Nodes matching `pattern`, those on the recorded `xpath` first and then ones
+/// deviating from it as late as possible
+fn candidate_parents(root: &TagNodeRef, pattern: &str, xpath: &str) -> Vec<TagNodeRef> {
+    let segments: Vec<PatternSegment> = pattern
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(parse_pattern_segment)
+        .collect();
+    let preferred: Vec<usize> = xpath
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .map(|segment| parse_segment(segment).1)
+        .collect();
+
+    let mut out = Vec::new();
+    if segments
+        .first()
+        .is_some_and(|&first| matches_segment(root, first))
+    {
+        collect_candidates(
+            root,
+            &segments[1..],
+            preferred.get(1..).unwrap_or(&[]),
+            &mut out,
+        );
+    }
+    out
+}
+
+fn collect_candidates(
+    node: &TagNodeRef,
+    segments: &[PatternSegment],
+    preferred: &[usize],
+    out: &mut Vec<TagNodeRef>,
+) {
+    if out.len() >= MAX_CANDIDATES {
+        return;
+    }
+    let Some(&segment) = segments.first() else {
+        out.push(node.clone());
+        return;
+    };
+
+    let mut matching: Vec<&TagNodeRef> = node
+        .children
+        .iter()
+        .filter(|child| child.tag_name != "text" && matches_segment(child, segment))
+        .collect();
+    // Stable sort: the recorded index first, the rest in document order
+    if let Some(&index) = preferred.first() {
+        matching.sort_by_key(|child| node_index(child) != index);
+    }
+    for child in matching {
+        collect_candidates(
+            child,
+            &segments[1..],
+            preferred.get(1..).unwrap_or(&[]),
+            out,
+        );
+    }
+}
+
+/// `div[3]` → (`div`, 3); a segment without index counts as `[1]`
+fn parse_segment(segment: &str) -> (&str, usize) {
+    match segment.split_once('[') {
+        Some((tag, rest)) => (tag, rest.trim_end_matches(']').parse().unwrap_or(1)),
+        None => (segment, 1),
+    }
+}
+
+/// `/html[1]/body[1]/div[3]` → `/html[*]/body[*]/div[*]`
+fn wildcard_indices(xpath: &str) -> String {
+    let mut out = String::with_capacity(xpath.len());
+    let mut in_index = false;
+    for c in xpath.chars() {
+        match c {
+            '[' => {
+                in_index = true;
+                out.push_str("[*");
+            }
+            ']' => {
+                in_index = false;
+                out.push(']');
+            }
+            _ if in_index => {}
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Record paths covering every generalized node of a region, see
+/// [`WrapperRule::record_paths`]
+fn record_paths(covered: &[TagNodeRef], gn_length: usize, shape: RecordShape) -> Vec<String> {
+    (0..gn_length)
+        .map(|j| {
+            let components = || covered.iter().skip(j).step_by(gn_length);
+            let component = generalize_step(components());
+            match shape {
+                RecordShape::Node => component,
+                RecordShape::Children | RecordShape::Columns => {
+                    let child = generalize_step(components().flat_map(|node| &node.children));
+                    format!("{component}/{child}")
+                }
+            }
+        })
+        .collect()
+}
+
+/// One path step matching all of `nodes`: `li[*]` when they share a tag
+fn generalize_step<'a>(nodes: impl Iterator<Item = &'a TagNodeRef>) -> String {
+    let tags: IndexSet<&str> = nodes.map(|node| node.tag_name.as_str()).collect();
+    match (tags.len(), tags.first()) {
+        (1, Some(&"text")) => "text()".to_string(),
+        (1, Some(tag)) => format!("{tag}[*]"),
+        _ if tags.contains("text") => "node()".to_string(),
+        _ => "*".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_parser::parse_html;
+    use crate::types::SimilarityMetric;
+
+    fn list_page(prefix: &str, items: usize) -> String {
+        let items: String = (0..items)
+            .map(|i| format!("<li><a>Item {}</a><span>{}</span></li>", i, i))
+            .collect();
+        format!(
+            "<html><body>{}<div><ul>{}</ul></div></body></html>",
+            prefix, items
+        )
+    }
+
+    #[test]
+    fn wrapper_transfers_to_shifted_page_and_falls_back_on_mismatch() {
+        let context = MdrContext::new(10, 0.3, MdrOptions::default());
+        let source = parse_html(&list_page("", 3));
+        let output = run_mdr_pipeline(&source, &context);
+        let wrapper = induce_wrapper(&source, &output.regions, &output.records, &context);
+
+        assert_eq!(wrapper.rules.len(), 1);
+        assert_eq!(
+            wrapper.rules[0].parent_pattern,
+            "/html[*]/body[*]/div[*]/ul[*]"
+        );
+        assert_eq!(wrapper.rules[0].record_paths, vec!["li[*]".to_string()]);
+        let json = serde_json::to_value(&wrapper.rules[0]).unwrap();
+        assert_eq!(json["parent_xpath"], "/html[1]/body[1]/div[1]/ul[1]");
+        assert_eq!(json["recordShape"], "node");
+        assert_eq!(json["gnLength"], 1);
+
+        // Same template, one more <div> before the list and more items
+        let target = parse_html(&list_page("<div><p>Intro</p></div>", 5));
+        let result = apply_wrapper(&wrapper, &target, &WrapperApplyOptions::default());
+        assert!(!result.fallback);
+        assert_eq!(result.confidence, 1.0);
+        assert_eq!(
+            result.matches[0].parent_xpath,
+            "/html[1]/body[1]/div[2]/ul[1]"
+        );
+        assert_eq!(result.records.len(), 5);
+
+        // The patterns drive extraction: a pinned index rules the shifted list out, a
+        // longer record path cuts the links out of each item
+        let strict = WrapperApplyOptions {
+            fallback: false,
+            ..WrapperApplyOptions::default()
+        };
+        let mut pinned = wrapper.clone();
+        pinned.rules[0].parent_pattern = "/html[*]/body[*]/div[1]/ul[*]".to_string();
+        assert!(apply_wrapper(&pinned, &target, &strict).matches.is_empty());
+        let mut links = wrapper.clone();
+        links.rules[0].record_paths = vec!["li[*]/a[*]".to_string()];
+        let result = apply_wrapper(&links, &target, &strict);
+        assert_eq!(result.records.len(), 5);
+        assert!(result.records.iter().all(|record| matches!(
+            record,
+            DataRecord::Single(node) if node.tag_name == "a"
+        )));
+
+        let unrelated = parse_html("<html><body><table><tr><td>1</td></tr></table></body></html>");
+        let result = apply_wrapper(&wrapper, &unrelated, &WrapperApplyOptions::default());
+        assert!(result.fallback);
+        assert_eq!(result.confidence, 0.0);
+    }
+
+    #[test]
+    fn rules_match_with_the_wrapper_metric() {
+        let page = |tag: &str| {
+            let items: String = (0..4)
+                .map(|i| format!("<li><a>{i}</a><{tag}>1</{tag}><i>2</i><em>3</em></li>"))
+                .collect();
+            parse_html(&format!("<html><body><ul>{items}</ul></body></html>"))
+        };
+        // Whitespace-only text (from TagNode JSON) appears as an unclosed `<text>`
+        let signature = "<li class=\"x&quot;y&gt;\"><a></a><text><b class=\"z\"></b></li>";
+        let options = MdrOptions {
+            signature_attributes: vec!["class".to_string()],
+            ..MdrOptions::default()
+        };
+        let rebuilt = signature_nodes(signature);
+        assert_eq!(flatten_node_sequence(&rebuilt, &options), signature);
+        assert_eq!(rebuilt[0].get_attribute("class"), Some("x\"y>"));
+
+        let source = page("b");
+        // <b> → <blockquote> is one token edit in ten but 18 characters of 57
+        let target = page("blockquote");
+        for (similarity, fallback) in [
+            (SimilarityMetric::TokenEditDistance, false),
+            (SimilarityMetric::StringEditDistance, true),
+        ] {
+            let options = MdrOptions {
+                similarity,
+                ..MdrOptions::default()
+            };
+            let context = MdrContext::new(10, 0.3, options);
+            let output = run_mdr_pipeline(&source, &context);
+            let wrapper = induce_wrapper(&source, &output.regions, &output.records, &context);
+            let result = apply_wrapper(&wrapper, &target, &WrapperApplyOptions::default());
+            assert_eq!(result.fallback, fallback, "{similarity:?}");
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::types::{DataRecord, TagNodeRef};
+use std::collections::HashMap;
+
+/// Xpath → node lookup built once per tree.
+///
+/// Text nodes carry their parent element's xpath, so one xpath can name several
+/// nodes; they are kept in document order, which puts the element first. [`get`]
+/// therefore returns the same node as `tree_utils::get_node_by_xpath`.
+///
+/// [`get`]: XpathIndex::get
+pub struct XpathIndex {
+    nodes: HashMap<String, Vec<TagNodeRef>>,
+}
+
+impl XpathIndex {
+    pub fn new(root: &TagNodeRef) -> Self {
+        let mut nodes: HashMap<String, Vec<TagNodeRef>> = HashMap::new();
+        let mut stack = vec![root];
+        while let Some(node) = stack.pop() {
+            nodes
+                .entry(node.xpath.clone())
+                .or_default()
+                .push(node.clone());
+            // Reversed so children are visited in document order
+            stack.extend(node.children.iter().rev());
+        }
+        XpathIndex { nodes }
+    }
+
+    /// First node (in document order) with this xpath
+    pub fn get(&self, xpath: &str) -> Option<&TagNodeRef> {
+        self.nodes.get(xpath).and_then(|nodes| nodes.first())
+    }
+
+    /// Every node with this xpath: the element followed by text nodes sharing it
+    pub fn get_all(&self, xpath: &str) -> &[TagNodeRef] {
+        self.nodes.get(xpath).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn contains(&self, xpath: &str) -> bool {
+        self.nodes.contains_key(xpath)
+    }
+
+    /// Number of distinct xpaths
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Resolves a record given as xpaths (e.g. ground truth or
+    /// `evaluation::record_xpaths` output) back to its nodes; `None` if any xpath is
+    /// missing
+    pub fn resolve_record<S: AsRef<str>>(&self, xpaths: &[S]) -> Option<DataRecord> {
+        let nodes: Vec<TagNodeRef> = xpaths
+            .iter()
+            .map(|xpath| self.get(xpath.as_ref()).cloned())
+            .collect::<Option<_>>()?;
+        match nodes.len() {
+            0 => None,
+            1 => nodes.into_iter().next().map(DataRecord::Single),
+            _ => Some(DataRecord::Multi(nodes)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::html_parser::parse_html;
+    use crate::tree_utils::get_node_by_xpath;
+    use std::sync::Arc;
+
+    #[test]
+    fn index_matches_depth_first_lookup_with_text_duplicates() {
+        let root =
+            parse_html("<html><body><p>Hello <b>world</b> again</p><p>Two</p></body></html>");
+        let index = XpathIndex::new(&root);
+
+        let p = "/html[1]/body[1]/p[1]";
+        assert_eq!(index.get(p).unwrap().tag_name, "p");
+        assert!(Arc::ptr_eq(
+            index.get(p).unwrap(),
+            &get_node_by_xpath(&root, p).unwrap()
+        ));
+        let tags: Vec<&str> = index
+            .get_all(p)
+            .iter()
+            .map(|node| node.tag_name.as_str())
+            .collect();
+        assert_eq!(tags, vec!["p", "text", "text"]);
+
+        match index.resolve_record(&[p, "/html[1]/body[1]/p[2]"]) {
+            Some(DataRecord::Multi(nodes)) => assert_eq!(nodes.len(), 2),
+            _ => panic!("expected a multi-node record"),
+        }
+        assert!(index.resolve_record(&["/html[1]/body[1]/p[3]"]).is_none());
+    }
+}